@@ -0,0 +1,151 @@
+//
+// ThreadPool benchmark
+// 比较 work-stealing 线程池与所有 worker 共享 Arc<Mutex<mpsc::Receiver>> 的线程池
+// run: cargo bench --bench pool
+//
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use world_hello::webserver::pool::{PoolConfig, QueuePolicy, ThreadPool};
+
+const WORKERS: usize = 4;
+
+// 原来的实现：worker 争用同一个 receiver
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+struct MutexPool {
+    workers: Vec<thread::JoinHandle<()>>,
+    sender: Option<mpsc::Sender<Job>>,
+}
+
+impl MutexPool {
+    fn new(size: usize) -> Self {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers = (0..size)
+            .map(|_| {
+                let receiver = Arc::clone(&receiver);
+                thread::spawn(move || loop {
+                    let job = receiver.lock().unwrap().recv();
+                    match job {
+                        Ok(job) => job(),
+                        Err(_) => break,
+                    }
+                })
+            })
+            .collect();
+        MutexPool {
+            workers,
+            sender: Some(sender),
+        }
+    }
+
+    fn execute<F: FnOnce() + Send + 'static>(&self, f: F) {
+        self.sender.as_ref().unwrap().send(Box::new(f)).unwrap();
+    }
+}
+
+impl Drop for MutexPool {
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        for worker in self.workers.drain(..) {
+            worker.join().unwrap();
+        }
+    }
+}
+
+fn stealing_pool() -> ThreadPool {
+    ThreadPool::with_config(PoolConfig {
+        min_workers: WORKERS,
+        max_workers: WORKERS,
+        queue_capacity: usize::MAX,
+        queue_policy: QueuePolicy::Block,
+        ..PoolConfig::default()
+    })
+}
+
+// 提交 n 个很小的任务，等待全部执行完
+fn bench_tiny_jobs(c: &mut Criterion) {
+    let mut group = c.benchmark_group("tiny_jobs");
+    let stealing = stealing_pool();
+    let mutex = MutexPool::new(WORKERS);
+
+    for n in [1_000, 10_000] {
+        group.bench_with_input(BenchmarkId::new("work_stealing", n), &n, |b, &n| {
+            b.iter(|| {
+                let (tx, rx) = mpsc::channel();
+                for i in 0..n {
+                    let tx = tx.clone();
+                    stealing.execute(move || tx.send(i).unwrap()).unwrap();
+                }
+                drop(tx);
+                assert_eq!(rx.iter().count(), n);
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("mutex_receiver", n), &n, |b, &n| {
+            b.iter(|| {
+                let (tx, rx) = mpsc::channel();
+                for i in 0..n {
+                    let tx = tx.clone();
+                    mutex.execute(move || tx.send(i).unwrap());
+                }
+                drop(tx);
+                assert_eq!(rx.iter().count(), n);
+            })
+        });
+    }
+    group.finish();
+}
+
+// 任务中再提交子任务：work-stealing 线程池中子任务进入本地队列
+fn bench_nested_jobs(c: &mut Criterion) {
+    let mut group = c.benchmark_group("nested_jobs");
+    let stealing = Arc::new(stealing_pool());
+    let mutex = Arc::new(MutexPool::new(WORKERS));
+    let (outer, inner) = (100, 100);
+
+    group.bench_function("work_stealing", |b| {
+        b.iter(|| {
+            let (tx, rx) = mpsc::channel();
+            for _ in 0..outer {
+                let (pool, tx) = (Arc::clone(&stealing), tx.clone());
+                stealing
+                    .execute(move || {
+                        for i in 0..inner {
+                            let tx = tx.clone();
+                            pool.execute(move || tx.send(i).unwrap()).unwrap();
+                        }
+                    })
+                    .unwrap();
+            }
+            drop(tx);
+            assert_eq!(rx.iter().count(), outer * inner);
+        })
+    });
+    group.bench_function("mutex_receiver", |b| {
+        b.iter(|| {
+            let (tx, rx) = mpsc::channel();
+            for _ in 0..outer {
+                let (pool, tx) = (Arc::clone(&mutex), tx.clone());
+                mutex.execute(move || {
+                    for i in 0..inner {
+                        let tx = tx.clone();
+                        pool.execute(move || tx.send(i).unwrap());
+                    }
+                });
+            }
+            drop(tx);
+            assert_eq!(rx.iter().count(), outer * inner);
+        })
+    });
+    group.finish();
+
+    // MutexPool 在 worker 中 drop 时会 join 自己，等任务中的 Arc 释放后再 drop
+    while Arc::strong_count(&mutex) > 1 {
+        thread::yield_now();
+    }
+}
+
+criterion_group!(benches, bench_tiny_jobs, bench_nested_jobs);
+criterion_main!(benches);
@@ -1,26 +1,125 @@
+use crate::apps::cache::cluster::ClusterNode;
+use crate::apps::cache::config::Config;
 use crate::apps::cache::process;
+use crate::apps::cache::stats::Stats;
+use crate::apps::cache::tracking::Tracking;
 use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
 use std::sync::{Arc, Mutex};
 use tokio::net::TcpListener;
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
 
 // mini redis cache server
 
 #[allow(dead_code)]
-pub async fn srv() {
-    let addr = "127.0.0.1:6379";
-    let listener = TcpListener::bind(addr).await.unwrap();
-    println!("Listening: {}", addr);
+pub async fn srv(config: Config) {
+    let handle = CacheServer::start(config).await.unwrap();
+    println!("Listening: {}", handle.addr());
+    handle.wait().await;
+}
+
+/// 可嵌入的 cache server, 运行在当前的 tokio runtime 上
+pub struct CacheServer;
 
-    let db: process::Db = Arc::new(Mutex::new(HashMap::new()));
+impl CacheServer {
+    /// 绑定 config.addr 并在后台接收连接，addr 的端口为 0 时绑定一个临时端口
+    pub async fn start(config: Config) -> io::Result<ServerHandle> {
+        let listener = TcpListener::bind(&config.addr).await?;
+        let addr = listener.local_addr()?;
 
-    loop {
-        let (socket, _) = listener.accept().await.unwrap();
-        let db = db.clone();
+        // 集群模式下，以 announce 的地址或绑定的地址在拓扑中找到当前节点
+        let cluster = match config.cluster.clone() {
+            Some(topology) => {
+                let myself = match &config.cluster_announce {
+                    Some(announce) => announce.clone(),
+                    // 通配地址不会出现在拓扑中，无法判断哪些 slot 属于自己
+                    None if addr.ip().is_unspecified() => {
+                        let msg = format!("cluster-announce must be set when binding {addr}");
+                        return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+                    }
+                    None => addr.to_string(),
+                };
+                let node = ClusterNode::new(topology, &myself)
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+                Some(Arc::new(node))
+            }
+            None => None,
+        };
+        let shared = process::Shared {
+            db: Arc::new(Mutex::new(HashMap::new())),
+            stats: Arc::new(Mutex::new(Stats::new(&config))),
+            tracking: Arc::new(Mutex::new(Tracking::default())),
+            cluster,
+        };
+        // 停止时向所有 receiver 广播，或者所有 sender 都被 drop 后 receiver 收到 Closed 错误
+        let (notify_shutdown, _) = broadcast::channel(1);
 
-        println!("Accepted");
-        let t = tokio::spawn(async move {
-            process::run(socket, db).await;
+        let mut shutdown = notify_shutdown.subscribe();
+        let notify = notify_shutdown.clone();
+        let task = tokio::spawn(async move {
+            loop {
+                let socket = tokio::select! {
+                    res = listener.accept() => match res {
+                        Ok((socket, _)) => socket,
+                        Err(err) => {
+                            eprintln!("accept error: {}", err);
+                            continue;
+                        }
+                    },
+                    _ = shutdown.recv() => break,
+                };
+
+                let shared = shared.clone();
+                let shutdown = notify.subscribe();
+                // 不等待任务结束，使多个连接可以被并发处理
+                tokio::spawn(async move {
+                    process::run(socket, shared, shutdown).await;
+                });
+            }
         });
-        t.await.unwrap();
+
+        Ok(ServerHandle {
+            addr,
+            notify_shutdown: Some(notify_shutdown),
+            task: Some(task),
+        })
+    }
+}
+
+/// 运行中的 cache server 句柄，drop 时停止接收连接并关闭已有的连接
+pub struct ServerHandle {
+    addr: SocketAddr,
+    notify_shutdown: Option<broadcast::Sender<()>>,
+    task: Option<JoinHandle<()>>,
+}
+
+impl ServerHandle {
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// 发送停止信号，并等待 server 停止接收连接
+    pub async fn shutdown(mut self) {
+        let _ = self.notify_shutdown.take().map(|tx| tx.send(()));
+        if let Some(task) = self.task.take() {
+            let _ = task.await;
+        }
+    }
+
+    /// 一直运行，直到 server 停止
+    pub async fn wait(mut self) {
+        if let Some(task) = self.task.take() {
+            let _ = task.await;
+        }
+    }
+}
+
+impl Drop for ServerHandle {
+    fn drop(&mut self) {
+        if let Some(tx) = self.notify_shutdown.take() {
+            let _ = tx.send(());
+        }
     }
 }
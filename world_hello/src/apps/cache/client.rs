@@ -0,0 +1,264 @@
+use crate::apps::cache::cluster;
+use bytes::Bytes;
+use mini_redis::{Connection, Frame};
+use std::collections::HashMap;
+use tokio::net::{TcpStream, ToSocketAddrs};
+use tokio::sync::{mpsc, oneshot};
+
+// cache client
+// NearCacheClient: 连接开启 CLIENT TRACKING, 读过的 key 缓存在本地，直到收到 server 的失效通知
+// ClusterClient: 集群模式下根据 MOVED 重定向访问 key 所在的节点
+
+type Responder<T> = oneshot::Sender<mini_redis::Result<T>>;
+
+#[derive(Debug)]
+enum Command {
+    Get {
+        key: String,
+        resp: Responder<Option<Bytes>>,
+    },
+    Set {
+        key: String,
+        val: Bytes,
+        resp: Responder<()>,
+    },
+}
+
+/// 可以 clone 后在多个任务中使用，所有命令都通过 channel 交给同一个连接执行
+#[derive(Clone)]
+pub struct NearCacheClient {
+    sender: mpsc::Sender<Command>,
+}
+
+impl NearCacheClient {
+    pub async fn connect<T: ToSocketAddrs>(addr: T) -> mini_redis::Result<NearCacheClient> {
+        let socket = TcpStream::connect(addr).await?;
+        let mut connection = Connection::new(socket);
+        let response = request(
+            &mut connection,
+            &mut HashMap::new(),
+            &["CLIENT", "TRACKING", "ON"],
+        )
+        .await?;
+        if let Frame::Error(msg) = response {
+            return Err(msg.into());
+        }
+
+        let (sender, receiver) = mpsc::channel(32);
+        tokio::spawn(manager(connection, receiver));
+        Ok(NearCacheClient { sender })
+    }
+
+    pub async fn get(&self, key: &str) -> mini_redis::Result<Option<Bytes>> {
+        let (resp, resp_rx) = oneshot::channel();
+        let cmd = Command::Get {
+            key: key.to_string(),
+            resp,
+        };
+        self.sender.send(cmd).await?;
+        resp_rx.await?
+    }
+
+    pub async fn set(&self, key: &str, val: Bytes) -> mini_redis::Result<()> {
+        let (resp, resp_rx) = oneshot::channel();
+        let cmd = Command::Set {
+            key: key.to_string(),
+            val,
+            resp,
+        };
+        self.sender.send(cmd).await?;
+        resp_rx.await?
+    }
+}
+
+// 从 channel 获取 cmd 执行，空闲时处理 server 推送的失效通知
+async fn manager(mut connection: Connection, mut receiver: mpsc::Receiver<Command>) {
+    // 本地缓存，None 表示 server 上不存在该 key
+    let mut cache: HashMap<String, Option<Bytes>> = HashMap::new();
+
+    loop {
+        tokio::select! {
+            cmd = receiver.recv() => {
+                let Some(cmd) = cmd else {
+                    // 所有 client 都已 drop
+                    break;
+                };
+                if let Err(err) = execute(&mut connection, &mut cache, cmd).await {
+                    eprintln!("near cache client error: {}", err);
+                    break;
+                }
+            }
+            // read_frame 只在读到完整的 frame 后才消费缓冲区，被 select! 取消时不会丢数据
+            frame = connection.read_frame() => match frame {
+                Ok(Some(frame)) => {
+                    if let Some(key) = invalidated_key(&frame) {
+                        cache.remove(&key);
+                    }
+                }
+                _ => break,
+            },
+        }
+    }
+}
+
+async fn execute(
+    connection: &mut Connection,
+    cache: &mut HashMap<String, Option<Bytes>>,
+    cmd: Command,
+) -> mini_redis::Result<()> {
+    match cmd {
+        Command::Get { key, resp } => {
+            if let Some(value) = cache.get(&key) {
+                // 往 oneshot 中发送消息时，并没有使用 .await, 原因是该发送操作要么直接成功、要么失败，并不需要等待
+                let _ = resp.send(Ok(value.clone()));
+                return Ok(());
+            }
+
+            let res = match request(connection, cache, &["GET", &key]).await? {
+                Frame::Bulk(value) => Ok(Some(value)),
+                Frame::Null => Ok(None),
+                frame => Err(unexpected(frame)),
+            };
+            if let Ok(value) = &res {
+                cache.insert(key, value.clone());
+            }
+            let _ = resp.send(res);
+        }
+        Command::Set { key, val, resp } => {
+            // 不缓存写入的值，server 会在 key 被修改后发送失效通知
+            cache.remove(&key);
+            let frame = Frame::Array(vec![
+                Frame::Bulk(Bytes::from("SET")),
+                Frame::Bulk(Bytes::from(key)),
+                Frame::Bulk(val),
+            ]);
+            connection.write_frame(&frame).await?;
+            let res = match read_response(connection, cache).await? {
+                Frame::Simple(s) if s == "OK" => Ok(()),
+                frame => Err(unexpected(frame)),
+            };
+            let _ = resp.send(res);
+        }
+    }
+    Ok(())
+}
+
+async fn request(
+    connection: &mut Connection,
+    cache: &mut HashMap<String, Option<Bytes>>,
+    args: &[&str],
+) -> mini_redis::Result<Frame> {
+    let frame = Frame::Array(
+        args.iter()
+            .map(|arg| Frame::Bulk(Bytes::from(arg.to_string())))
+            .collect(),
+    );
+    connection.write_frame(&frame).await?;
+    read_response(connection, cache).await
+}
+
+// 读取命令的响应，期间收到的失效通知直接应用到本地缓存
+async fn read_response(
+    connection: &mut Connection,
+    cache: &mut HashMap<String, Option<Bytes>>,
+) -> mini_redis::Result<Frame> {
+    loop {
+        match connection.read_frame().await? {
+            Some(frame) => match invalidated_key(&frame) {
+                Some(key) => {
+                    cache.remove(&key);
+                }
+                None => return Ok(frame),
+            },
+            None => return Err("connection reset by server".into()),
+        }
+    }
+}
+
+fn invalidated_key(frame: &Frame) -> Option<String> {
+    match frame {
+        Frame::Array(items) => match &items[..] {
+            [Frame::Bulk(kind), Frame::Bulk(key)] if &kind[..] == b"invalidate" => {
+                Some(String::from_utf8_lossy(key).into_owned())
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn unexpected(frame: Frame) -> mini_redis::Error {
+    match frame {
+        Frame::Error(msg) => msg.into(),
+        frame => format!("unexpected frame: {:?}", frame).into(),
+    }
+}
+
+/// 集群模式的 cache client, 根据 MOVED 重定向自动连接负责 key 所在 slot 的节点
+pub struct ClusterClient {
+    seed: String,
+    // slot -> 节点地址，由 MOVED 重定向学习得到
+    slots: HashMap<u16, String>,
+    clients: HashMap<String, mini_redis::client::Client>,
+}
+
+// 重定向次数上限，避免拓扑不一致时无限重定向
+const MAX_REDIRECTS: usize = 5;
+
+impl ClusterClient {
+    pub async fn connect(seed: &str) -> mini_redis::Result<ClusterClient> {
+        let client = mini_redis::client::connect(seed).await?;
+        Ok(ClusterClient {
+            seed: seed.to_string(),
+            slots: HashMap::new(),
+            clients: HashMap::from([(seed.to_string(), client)]),
+        })
+    }
+
+    pub async fn get(&mut self, key: &str) -> mini_redis::Result<Option<Bytes>> {
+        let slot = cluster::key_slot(key);
+        for _ in 0..MAX_REDIRECTS {
+            let res = self.client(slot).await?.get(key).await;
+            if !self.follow_moved(&res) {
+                return res;
+            }
+        }
+        Err(format!("too many redirects for key {}", key).into())
+    }
+
+    pub async fn set(&mut self, key: &str, val: Bytes) -> mini_redis::Result<()> {
+        let slot = cluster::key_slot(key);
+        for _ in 0..MAX_REDIRECTS {
+            let res = self.client(slot).await?.set(key, val.clone()).await;
+            if !self.follow_moved(&res) {
+                return res;
+            }
+        }
+        Err(format!("too many redirects for key {}", key).into())
+    }
+
+    // 返回 slot 所在节点的连接，未知的 slot 先发送到 seed 节点
+    async fn client(&mut self, slot: u16) -> mini_redis::Result<&mut mini_redis::client::Client> {
+        let addr = self.slots.get(&slot).unwrap_or(&self.seed).clone();
+        if !self.clients.contains_key(&addr) {
+            let client = mini_redis::client::connect(&addr).await?;
+            self.clients.insert(addr.clone(), client);
+        }
+        Ok(self.clients.get_mut(&addr).unwrap())
+    }
+
+    // 收到 MOVED 错误时记录 slot 的新节点，返回 true 表示需要重试
+    fn follow_moved<T>(&mut self, res: &mini_redis::Result<T>) -> bool {
+        let moved = match res {
+            Err(err) => cluster::parse_moved(&err.to_string()),
+            Ok(_) => None,
+        };
+        match moved {
+            Some((slot, addr)) => {
+                self.slots.insert(slot, addr);
+                true
+            }
+            None => false,
+        }
+    }
+}
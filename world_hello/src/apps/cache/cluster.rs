@@ -0,0 +1,189 @@
+use std::fs;
+use std::path::Path;
+
+// cluster: key 通过 CRC16 映射到 16384 个 hash slot, 每个节点负责静态拓扑文件中指定的 slot 范围
+// refer: https://redis.io/docs/reference/cluster-spec/
+
+pub const SLOTS: usize = 16384;
+
+/// CRC16 (XMODEM), 与 redis cluster 使用的算法一致
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+    for byte in data {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// 计算 key 所在的 slot, 如果 key 包含非空的 {hashtag}, 只对 hashtag 计算
+pub fn key_slot(key: &str) -> u16 {
+    let bytes = key.as_bytes();
+    let tag = bytes.iter().position(|b| *b == b'{').and_then(|start| {
+        bytes[start + 1..]
+            .iter()
+            .position(|b| *b == b'}')
+            .filter(|len| *len > 0)
+            .map(|len| &bytes[start + 1..start + 1 + len])
+    });
+    crc16(tag.unwrap_or(bytes)) % SLOTS as u16
+}
+
+/// 解析 MOVED 错误，返回 (slot, addr)
+pub fn parse_moved(msg: &str) -> Option<(u16, String)> {
+    let mut parts = msg.split_whitespace();
+    if parts.next()? != "MOVED" {
+        return None;
+    }
+    let slot = parts.next()?.parse().ok()?;
+    let addr = parts.next()?.to_string();
+    Some((slot, addr))
+}
+
+/// 静态集群拓扑，每行为一个节点地址和它负责的 slot 范围，所有 slot 都必须被覆盖:
+///
+/// ```text
+/// # addr          slots
+/// 127.0.0.1:7001  0-8191
+/// 127.0.0.1:7002  8192-16383
+/// ```
+#[derive(Debug, Clone)]
+pub struct Topology {
+    nodes: Vec<String>,
+    // slot -> 负责该 slot 的节点在 nodes 中的下标
+    owners: Vec<usize>,
+}
+
+impl Topology {
+    pub fn load(path: impl AsRef<Path>) -> mini_redis::Result<Topology> {
+        let contents = fs::read_to_string(path)?;
+        Topology::parse(&contents)
+    }
+
+    pub fn parse(contents: &str) -> mini_redis::Result<Topology> {
+        const UNASSIGNED: usize = usize::MAX;
+
+        let mut nodes: Vec<String> = Vec::new();
+        let mut owners = vec![UNASSIGNED; SLOTS];
+        for (lineno, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let invalid = || format!("invalid topology line {}: {}", lineno + 1, line);
+            let (addr, range) = match line.split_whitespace().collect::<Vec<_>>()[..] {
+                [addr, range] => (addr, range),
+                _ => return Err(invalid().into()),
+            };
+            let (start, end) = range.split_once('-').unwrap_or((range, range));
+            let (start, end): (usize, usize) = match (start.parse(), end.parse()) {
+                (Ok(start), Ok(end)) if start <= end && end < SLOTS => (start, end),
+                _ => return Err(invalid().into()),
+            };
+
+            let idx = match nodes.iter().position(|node| node == addr) {
+                Some(idx) => idx,
+                None => {
+                    nodes.push(addr.to_string());
+                    nodes.len() - 1
+                }
+            };
+            for (slot, owner) in owners.iter_mut().enumerate().take(end + 1).skip(start) {
+                if *owner != UNASSIGNED {
+                    return Err(format!("slot {} is assigned more than once", slot).into());
+                }
+                *owner = idx;
+            }
+        }
+
+        if let Some(slot) = owners.iter().position(|owner| *owner == UNASSIGNED) {
+            return Err(format!("slot {} is not assigned to any node", slot).into());
+        }
+        Ok(Topology { nodes, owners })
+    }
+
+    pub fn nodes(&self) -> &[String] {
+        &self.nodes
+    }
+
+    pub fn owner(&self, slot: u16) -> &str {
+        &self.nodes[self.owners[slot as usize]]
+    }
+}
+
+/// 集群中当前节点的视图
+#[derive(Debug)]
+pub struct ClusterNode {
+    topology: Topology,
+    myself: String,
+}
+
+impl ClusterNode {
+    pub fn new(topology: Topology, myself: &str) -> mini_redis::Result<ClusterNode> {
+        if !topology.nodes().iter().any(|node| node == myself) {
+            return Err(format!("node {} is not in the cluster topology", myself).into());
+        }
+        Ok(ClusterNode {
+            topology,
+            myself: myself.to_string(),
+        })
+    }
+
+    /// key 不属于当前节点时，返回 MOVED 错误
+    pub fn redirect(&self, key: &str) -> Option<String> {
+        let slot = key_slot(key);
+        let owner = self.topology.owner(slot);
+        (owner != self.myself).then(|| format!("MOVED {} {}", slot, owner))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_slot() {
+        assert_eq!(crc16(b"123456789"), 0x31c3);
+        assert_eq!(key_slot("foo"), 12182);
+        assert_eq!(key_slot("{user1000}.following"), key_slot("user1000"));
+        assert_eq!(key_slot("foo{}{bar}"), crc16(b"foo{}{bar}") % SLOTS as u16);
+        assert_eq!(key_slot("foo{bar}{zap}"), key_slot("bar"));
+    }
+
+    #[test]
+    fn test_topology_parse() {
+        let topology = Topology::parse(
+            "# two nodes\n127.0.0.1:7001 0-8191\n\n127.0.0.1:7002 8192-16382\n127.0.0.1:7001 16383\n",
+        )
+        .unwrap();
+        assert_eq!(topology.nodes().len(), 2);
+        assert_eq!(topology.owner(0), "127.0.0.1:7001");
+        assert_eq!(topology.owner(12182), "127.0.0.1:7002");
+        assert_eq!(topology.owner(16383), "127.0.0.1:7001");
+
+        assert!(Topology::parse("127.0.0.1:7001 0-8191").is_err());
+        assert!(Topology::parse("127.0.0.1:7001 0-16383\n127.0.0.1:7002 1-2").is_err());
+        assert!(Topology::parse("127.0.0.1:7001 0-16384").is_err());
+    }
+
+    #[test]
+    fn test_cluster_node_redirect() {
+        let topology = Topology::parse("a:1 0-8191\nb:2 8192-16383").unwrap();
+        let node = ClusterNode::new(topology.clone(), "a:1").unwrap();
+        assert_eq!(node.redirect("foo"), Some("MOVED 12182 b:2".to_string()));
+        assert_eq!(node.redirect("{user1000}"), None);
+        assert_eq!(
+            parse_moved("MOVED 12182 b:2"),
+            Some((12182, "b:2".to_string()))
+        );
+
+        assert!(ClusterNode::new(topology, "c:3").is_err());
+    }
+}
@@ -0,0 +1,63 @@
+use crate::apps::cache::cluster::Topology;
+use std::time::Duration;
+
+// cache server config
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub addr: String,
+    /// 执行耗时超过该阈值的命令会被记录到 slowlog
+    pub slowlog_slower_than: Duration,
+    /// slowlog 最多保留的条数，超出后丢弃最旧的记录
+    pub slowlog_max_len: usize,
+    /// 集群拓扑，为 None 时以单机模式运行
+    pub cluster: Option<Topology>,
+    /// 当前节点在拓扑中的地址，默认为绑定的地址；绑定 0.0.0.0 等通配地址时必须设置
+    pub cluster_announce: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            addr: String::from("127.0.0.1:6379"),
+            slowlog_slower_than: Duration::from_millis(10),
+            slowlog_max_len: 128,
+            cluster: None,
+            cluster_announce: None,
+        }
+    }
+}
+
+impl Config {
+    /// 从命令行参数构建配置:
+    /// [--addr host:port] [--cluster topology.conf] [--cluster-announce host:port]
+    /// [--slowlog-slower-than us] [--slowlog-max-len n]
+    pub fn build(mut args: impl Iterator<Item = String>) -> Result<Config, String> {
+        args.next();
+
+        let mut config = Config::default();
+        while let Some(flag) = args.next() {
+            let value = match args.next() {
+                Some(value) => value,
+                None => return Err(format!("missing value for {flag}")),
+            };
+            let invalid = |_| format!("invalid value [{value}] for {flag}");
+            match &flag[..] {
+                "--addr" => config.addr = value,
+                "--cluster" => {
+                    let topology = Topology::load(&value)
+                        .map_err(|err| format!("load cluster topology {value}: {err}"))?;
+                    config.cluster = Some(topology);
+                }
+                "--cluster-announce" => config.cluster_announce = Some(value),
+                "--slowlog-slower-than" => {
+                    let us = value.parse().map_err(invalid)?;
+                    config.slowlog_slower_than = Duration::from_micros(us);
+                }
+                "--slowlog-max-len" => config.slowlog_max_len = value.parse().map_err(invalid)?,
+                _ => return Err(format!("unknown option {flag}")),
+            }
+        }
+        Ok(config)
+    }
+}
@@ -1,2 +1,7 @@
 pub mod app;
+pub mod client;
+pub mod cluster;
+pub mod config;
 mod process;
+mod stats;
+mod tracking;
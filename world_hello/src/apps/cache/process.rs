@@ -1,33 +1,258 @@
+use crate::apps::cache::cluster::{self, ClusterNode};
+use crate::apps::cache::stats::SharedStats;
+use crate::apps::cache::tracking::SharedTracking;
 use bytes::Bytes;
 use mini_redis::{Connection, Frame};
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
+use std::time::Instant;
 use tokio::net::TcpStream;
+use tokio::sync::{broadcast, mpsc};
 
 pub type Db = Arc<Mutex<HashMap<String, Bytes>>>;
 
-pub async fn run(socket: TcpStream, db: Db) {
-    use mini_redis::Command::{self, Get, Set};
+const SLOWLOG_DEFAULT_COUNT: usize = 10;
+
+/// server 的共享状态，每个连接持有一份 clone
+#[derive(Clone)]
+pub struct Shared {
+    pub db: Db,
+    pub stats: SharedStats,
+    pub tracking: SharedTracking,
+    /// 非集群模式时为 None
+    pub cluster: Option<Arc<ClusterNode>>,
+}
 
+pub async fn run(socket: TcpStream, shared: Shared, mut shutdown: broadcast::Receiver<()>) {
+    let client_addr = socket.peer_addr().ok();
+    // 每个响应都会单独 flush, 关闭 Nagle 算法以免 pipeline 的响应被延迟发送
+    let _ = socket.set_nodelay(true);
     let mut connection = Connection::new(socket);
-    while let Some(frame) = connection.read_frame().await.unwrap() {
-        let response = match Command::from_frame(frame).unwrap() {
-            Set(cmd) => {
-                let mut db = db.lock().unwrap();
-                db.insert(cmd.key().to_string(), cmd.value().clone());
-                Frame::Simple("OK".to_string())
-            }
-            Get(cmd) => {
-                let db = db.lock().unwrap();
-                if let Some(value) = db.get(cmd.key()) {
-                    Frame::Bulk(value.clone())
-                } else {
-                    Frame::Null
+    // 开启 client tracking 后的连接 id 和失效通知
+    let mut client: Option<(u64, mpsc::UnboundedReceiver<String>)> = None;
+
+    loop {
+        let frame = tokio::select! {
+            res = connection.read_frame() => match res {
+                Ok(Some(frame)) => frame,
+                Ok(None) => break,
+                Err(err) => {
+                    eprintln!("read frame error: {}", err);
+                    break;
+                }
+            },
+            Some(key) = next_invalidation(&mut client) => {
+                if connection.write_frame(&invalidate_frame(key)).await.is_err() {
+                    break;
                 }
+                continue;
             }
-            cmd => panic!("unimplemented {:?}", cmd),
+            // 收到停止信号后关闭连接
+            _ = shutdown.recv() => break,
         };
 
-        connection.write_frame(&response).await.unwrap();
+        let args = frame_args(&frame);
+        let name = args.first().map(|s| s.to_lowercase()).unwrap_or_default();
+
+        let start = Instant::now();
+        // mini_redis 不支持的命令在 Command::from_frame 中会丢失参数，因此先按命令名分发
+        let response = match &name[..] {
+            "slowlog" => slowlog(&args[1..], &shared.stats),
+            "info" => info(&args[1..], &shared.stats),
+            "client" => client_cmd(&args[1..], &shared.tracking, &mut client),
+            "cluster" => cluster_cmd(&args[1..]),
+            _ => apply(frame, &shared, client.as_ref().map(|(id, _)| *id)),
+        };
+        let elapsed = start.elapsed();
+        shared
+            .stats
+            .lock()
+            .unwrap()
+            .record(&name, &args, elapsed, client_addr);
+
+        if let Err(err) = connection.write_frame(&response).await {
+            eprintln!("write frame error: {}", err);
+            break;
+        }
+    }
+
+    if let Some((id, _)) = client {
+        shared.tracking.lock().unwrap().unregister(id);
+    }
+}
+
+fn apply(frame: Frame, shared: &Shared, client_id: Option<u64>) -> Frame {
+    use mini_redis::Command::{self, Get, Set};
+
+    let cmd = match Command::from_frame(frame) {
+        Ok(cmd) => cmd,
+        Err(err) => return Frame::Error(format!("ERR {}", err)),
+    };
+    // 集群模式下，key 不属于当前节点时返回 MOVED, 由客户端重定向
+    let key = match &cmd {
+        Set(cmd) => Some(cmd.key()),
+        Get(cmd) => Some(cmd.key()),
+        _ => None,
+    };
+    if let Some(moved) = key
+        .zip(shared.cluster.as_ref())
+        .and_then(|(k, c)| c.redirect(k))
+    {
+        return Frame::Error(moved);
+    }
+
+    match cmd {
+        Set(cmd) => {
+            let mut db = shared.db.lock().unwrap();
+            db.insert(cmd.key().to_string(), cmd.value().clone());
+            shared.tracking.lock().unwrap().invalidate(cmd.key());
+            Frame::Simple("OK".to_string())
+        }
+        Get(cmd) => {
+            if let Some(id) = client_id {
+                shared.tracking.lock().unwrap().remember(id, cmd.key());
+            }
+            let db = shared.db.lock().unwrap();
+            if let Some(value) = db.get(cmd.key()) {
+                Frame::Bulk(value.clone())
+            } else {
+                Frame::Null
+            }
+        }
+        cmd => Frame::Error(format!("ERR unimplemented {:?}", cmd)),
+    }
+}
+
+// SLOWLOG GET [count] | LEN | RESET
+fn slowlog(args: &[String], stats: &SharedStats) -> Frame {
+    let sub = args.first().map(|s| s.to_lowercase()).unwrap_or_default();
+    match (&sub[..], args.len()) {
+        ("get", 1 | 2) => {
+            let count = match args.get(1).map(|s| s.parse::<i64>()) {
+                None => SLOWLOG_DEFAULT_COUNT,
+                Some(Ok(n)) if n < 0 => usize::MAX,
+                Some(Ok(n)) => n as usize,
+                Some(Err(_)) => {
+                    return Frame::Error("ERR value is not an integer or out of range".into())
+                }
+            };
+
+            // mini_redis 的 Connection 不支持写入嵌套的 Array, 因此每条记录格式化为一个 Bulk 字符串
+            let stats = stats.lock().unwrap();
+            let entries = stats
+                .slowlog()
+                .get(count)
+                .map(|entry| {
+                    let client = entry
+                        .client_addr
+                        .map(|addr| addr.to_string())
+                        .unwrap_or_default();
+                    Frame::Bulk(Bytes::from(format!(
+                        "id={} time={} duration={} client={} args={}",
+                        entry.id,
+                        entry.timestamp,
+                        entry.duration.as_micros(),
+                        client,
+                        entry.args.join(" "),
+                    )))
+                })
+                .collect();
+            Frame::Array(entries)
+        }
+        ("len", 1) => Frame::Integer(stats.lock().unwrap().slowlog().len() as u64),
+        ("reset", 1) => {
+            stats.lock().unwrap().slowlog_mut().reset();
+            Frame::Simple("OK".to_string())
+        }
+        _ => Frame::Error(format!(
+            "ERR unknown subcommand or wrong number of arguments for 'slowlog {}'",
+            sub
+        )),
+    }
+}
+
+// CLIENT TRACKING ON|OFF
+fn client_cmd(
+    args: &[String],
+    tracking: &SharedTracking,
+    client: &mut Option<(u64, mpsc::UnboundedReceiver<String>)>,
+) -> Frame {
+    let args: Vec<String> = args.iter().map(|s| s.to_lowercase()).collect();
+    match args.iter().map(|s| &s[..]).collect::<Vec<_>>()[..] {
+        ["tracking", "on"] => {
+            if client.is_none() {
+                *client = Some(tracking.lock().unwrap().register());
+            }
+            Frame::Simple("OK".to_string())
+        }
+        ["tracking", "off"] => {
+            if let Some((id, _)) = client.take() {
+                tracking.lock().unwrap().unregister(id);
+            }
+            Frame::Simple("OK".to_string())
+        }
+        _ => Frame::Error(format!(
+            "ERR unknown subcommand or wrong number of arguments for 'client {}'",
+            args.join(" ")
+        )),
+    }
+}
+
+// CLUSTER KEYSLOT key
+fn cluster_cmd(args: &[String]) -> Frame {
+    match args {
+        [sub, key] if sub.eq_ignore_ascii_case("keyslot") => {
+            Frame::Integer(cluster::key_slot(key) as u64)
+        }
+        _ => Frame::Error(format!(
+            "ERR unknown subcommand or wrong number of arguments for 'cluster {}'",
+            args.join(" ")
+        )),
+    }
+}
+
+// 未开启 tracking 时一直 pending, 使 select! 只等待其它分支
+async fn next_invalidation(
+    client: &mut Option<(u64, mpsc::UnboundedReceiver<String>)>,
+) -> Option<String> {
+    match client {
+        Some((_, receiver)) => receiver.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+// 失效通知与命令的响应在同一个连接上发送，格式为 ["invalidate", key]
+// mini_redis 不支持 RESP3 的 push 类型，客户端需要根据首个元素区分通知和响应
+fn invalidate_frame(key: String) -> Frame {
+    Frame::Array(vec![
+        Frame::Bulk(Bytes::from("invalidate")),
+        Frame::Bulk(Bytes::from(key)),
+    ])
+}
+
+// INFO [section], 目前只支持 commandstats
+fn info(args: &[String], stats: &SharedStats) -> Frame {
+    let section = args.first().map(|s| s.to_lowercase());
+    let contents = match section.as_deref() {
+        None | Some("commandstats" | "all" | "everything") => {
+            stats.lock().unwrap().info_commandstats()
+        }
+        Some(_) => String::new(),
+    };
+    Frame::Bulk(Bytes::from(contents))
+}
+
+fn frame_args(frame: &Frame) -> Vec<String> {
+    match frame {
+        Frame::Array(items) => items
+            .iter()
+            .map(|item| match item {
+                Frame::Bulk(b) => String::from_utf8_lossy(b).into_owned(),
+                Frame::Simple(s) => s.clone(),
+                Frame::Integer(n) => n.to_string(),
+                _ => String::new(),
+            })
+            .collect(),
+        _ => Vec::new(),
     }
 }
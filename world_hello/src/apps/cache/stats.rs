@@ -0,0 +1,288 @@
+use crate::apps::cache::config::Config;
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// 命令执行耗时统计: slowlog 和每个命令的延迟直方图
+// refer: https://redis.io/commands/slowlog-get/
+
+pub type SharedStats = Arc<Mutex<Stats>>;
+
+// 与 redis 一致，slowlog 中每条记录最多保留 32 个参数，每个参数最多 128 字节
+const SLOWLOG_MAX_ARGC: usize = 32;
+const SLOWLOG_MAX_ARG_LEN: usize = 128;
+
+const HISTOGRAM_BUCKETS: usize = 64;
+
+/// 延迟直方图，第 i 个桶记录耗时在 [2^(i-1), 2^i) us 区间内的样本
+#[derive(Debug, Clone)]
+pub struct Histogram {
+    buckets: [u64; HISTOGRAM_BUCKETS],
+    count: u64,
+    total: Duration,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram {
+            buckets: [0; HISTOGRAM_BUCKETS],
+            count: 0,
+            total: Duration::ZERO,
+        }
+    }
+}
+
+impl Histogram {
+    pub fn record(&mut self, elapsed: Duration) {
+        let us = elapsed.as_micros().min(u64::MAX as u128) as u64;
+        let idx = (u64::BITS - us.leading_zeros()) as usize;
+        self.buckets[idx.min(HISTOGRAM_BUCKETS - 1)] += 1;
+        self.count += 1;
+        self.total += elapsed;
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn total(&self) -> Duration {
+        self.total
+    }
+
+    /// 返回百分位 p (0-100) 所在桶的上界（单位 us），没有样本时返回 0
+    pub fn percentile(&self, p: f64) -> u64 {
+        if self.count == 0 {
+            return 0;
+        }
+
+        let target = ((p / 100.0) * self.count as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (idx, n) in self.buckets.iter().enumerate() {
+            seen += n;
+            if seen >= target {
+                return 1 << idx;
+            }
+        }
+        1 << (HISTOGRAM_BUCKETS - 1)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SlowLogEntry {
+    pub id: u64,
+    /// 命令开始执行的 unix 时间戳（秒）
+    pub timestamp: u64,
+    pub duration: Duration,
+    pub args: Vec<String>,
+    pub client_addr: Option<SocketAddr>,
+}
+
+/// 有界的 slowlog, 新记录在队头，超出 max_len 时丢弃队尾最旧的记录
+#[derive(Debug)]
+pub struct SlowLog {
+    entries: VecDeque<SlowLogEntry>,
+    next_id: u64,
+    slower_than: Duration,
+    max_len: usize,
+}
+
+impl SlowLog {
+    pub fn new(slower_than: Duration, max_len: usize) -> Self {
+        SlowLog {
+            entries: VecDeque::with_capacity(max_len),
+            next_id: 0,
+            slower_than,
+            max_len,
+        }
+    }
+
+    pub fn push(&mut self, args: &[String], elapsed: Duration, client_addr: Option<SocketAddr>) {
+        if elapsed < self.slower_than || self.max_len == 0 {
+            return;
+        }
+
+        let mut entry_args: Vec<String> = args
+            .iter()
+            .take(SLOWLOG_MAX_ARGC)
+            .map(|arg| truncate_arg(arg))
+            .collect();
+        if args.len() > SLOWLOG_MAX_ARGC {
+            let more = args.len() - SLOWLOG_MAX_ARGC + 1;
+            entry_args[SLOWLOG_MAX_ARGC - 1] = format!("... ({more} more arguments)");
+        }
+
+        let timestamp = SystemTime::now()
+            .checked_sub(elapsed)
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+
+        self.entries.push_front(SlowLogEntry {
+            id: self.next_id,
+            timestamp,
+            duration: elapsed,
+            args: entry_args,
+            client_addr,
+        });
+        self.next_id += 1;
+        self.entries.truncate(self.max_len);
+    }
+
+    /// 返回最新的 count 条记录，按从新到旧排序
+    pub fn get(&self, count: usize) -> impl Iterator<Item = &SlowLogEntry> {
+        self.entries.iter().take(count)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn reset(&mut self) {
+        self.entries.clear();
+    }
+}
+
+fn truncate_arg(arg: &str) -> String {
+    if arg.len() <= SLOWLOG_MAX_ARG_LEN {
+        return arg.to_string();
+    }
+
+    let mut end = SLOWLOG_MAX_ARG_LEN;
+    while !arg.is_char_boundary(end) {
+        end -= 1;
+    }
+    let more = arg.len() - end;
+    format!("{}... ({more} more bytes)", &arg[..end])
+}
+
+#[derive(Debug)]
+pub struct Stats {
+    slowlog: SlowLog,
+    // 使用 BTreeMap 使 INFO 输出按命令名有序
+    commands: BTreeMap<String, Histogram>,
+}
+
+impl Stats {
+    pub fn new(config: &Config) -> Self {
+        Stats {
+            slowlog: SlowLog::new(config.slowlog_slower_than, config.slowlog_max_len),
+            commands: BTreeMap::new(),
+        }
+    }
+
+    /// 记录一次命令执行，name 为小写的命令名，args 包含命令名本身
+    pub fn record(
+        &mut self,
+        name: &str,
+        args: &[String],
+        elapsed: Duration,
+        client_addr: Option<SocketAddr>,
+    ) {
+        self.commands
+            .entry(name.to_string())
+            .or_default()
+            .record(elapsed);
+        self.slowlog.push(args, elapsed, client_addr);
+    }
+
+    pub fn slowlog(&self) -> &SlowLog {
+        &self.slowlog
+    }
+
+    pub fn slowlog_mut(&mut self) -> &mut SlowLog {
+        &mut self.slowlog
+    }
+
+    /// 生成 INFO commandstats 的内容，每个命令一行:
+    /// cmdstat_get:calls=2,usec=30,usec_per_call=15.00,p50=16,p99=16,p99.9=16
+    pub fn info_commandstats(&self) -> String {
+        let mut info = String::from("# Commandstats\r\n");
+        for (name, hist) in &self.commands {
+            let usec = hist.total().as_micros();
+            let per_call = usec as f64 / hist.count() as f64;
+            let _ = write!(
+                info,
+                "cmdstat_{name}:calls={},usec={usec},usec_per_call={per_call:.2},p50={},p99={},p99.9={}\r\n",
+                hist.count(),
+                hist.percentile(50.0),
+                hist.percentile(99.0),
+                hist.percentile(99.9),
+            );
+        }
+        info
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(items: &[&str]) -> Vec<String> {
+        items.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_histogram_percentile() {
+        let mut hist = Histogram::default();
+        assert_eq!(hist.percentile(99.0), 0);
+
+        for _ in 0..98 {
+            hist.record(Duration::from_micros(3));
+        }
+        hist.record(Duration::from_micros(100));
+        hist.record(Duration::from_millis(5));
+
+        assert_eq!(hist.count(), 100);
+        assert_eq!(hist.percentile(50.0), 4);
+        assert_eq!(hist.percentile(99.0), 128);
+        assert_eq!(hist.percentile(100.0), 8192);
+    }
+
+    #[test]
+    fn test_slowlog_bounded() {
+        let mut slowlog = SlowLog::new(Duration::from_millis(1), 2);
+        slowlog.push(&args(&["get", "fast"]), Duration::from_micros(10), None);
+        assert_eq!(slowlog.len(), 0);
+
+        for key in ["a", "b", "c"] {
+            slowlog.push(&args(&["get", key]), Duration::from_millis(2), None);
+        }
+        assert_eq!(slowlog.len(), 2);
+
+        let got: Vec<_> = slowlog
+            .get(10)
+            .map(|e| (e.id, e.args[1].as_str()))
+            .collect();
+        assert_eq!(got, vec![(2, "c"), (1, "b")]);
+
+        slowlog.reset();
+        assert_eq!(slowlog.len(), 0);
+    }
+
+    #[test]
+    fn test_slowlog_truncate_args() {
+        let mut slowlog = SlowLog::new(Duration::ZERO, 1);
+        let mut items = vec!["x".repeat(200)];
+        items.extend((0..40).map(|i| i.to_string()));
+        slowlog.push(&items, Duration::from_millis(1), None);
+
+        let entry = slowlog.get(1).next().unwrap();
+        assert_eq!(entry.args.len(), SLOWLOG_MAX_ARGC);
+        assert!(entry.args[0].ends_with("... (72 more bytes)"));
+        assert_eq!(entry.args[31], "... (10 more arguments)");
+    }
+
+    #[test]
+    fn test_info_commandstats() {
+        let mut stats = Stats::new(&Config::default());
+        stats.record("get", &args(&["GET", "k"]), Duration::from_micros(10), None);
+        stats.record("get", &args(&["GET", "k"]), Duration::from_micros(20), None);
+
+        let info = stats.info_commandstats();
+        assert!(info.starts_with("# Commandstats\r\n"));
+        assert!(info.contains("cmdstat_get:calls=2,usec=30,usec_per_call=15.00,p50=16,"));
+        assert_eq!(stats.slowlog().len(), 0);
+    }
+}
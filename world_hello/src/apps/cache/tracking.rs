@@ -0,0 +1,103 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+
+// client tracking: 记录开启了 tracking 的连接读过哪些 key, key 被修改时通知这些连接
+// refer: https://redis.io/docs/manual/client-side-caching/
+
+pub type SharedTracking = Arc<Mutex<Tracking>>;
+
+struct TrackedClient {
+    sender: mpsc::UnboundedSender<String>,
+    keys: HashSet<String>,
+}
+
+#[derive(Default)]
+pub struct Tracking {
+    next_id: u64,
+    clients: HashMap<u64, TrackedClient>,
+    // key -> 读过该 key 的连接 id
+    keys: HashMap<String, HashSet<u64>>,
+}
+
+impl Tracking {
+    /// 为连接开启 tracking, 返回连接 id 和接收失效 key 的 receiver
+    pub fn register(&mut self) -> (u64, mpsc::UnboundedReceiver<String>) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let id = self.next_id;
+        self.next_id += 1;
+        self.clients.insert(
+            id,
+            TrackedClient {
+                sender,
+                keys: HashSet::new(),
+            },
+        );
+        (id, receiver)
+    }
+
+    /// 关闭 tracking 或连接断开时，清除该连接记录的所有 key
+    pub fn unregister(&mut self, id: u64) {
+        let Some(client) = self.clients.remove(&id) else {
+            return;
+        };
+        for key in client.keys {
+            if let Some(ids) = self.keys.get_mut(&key) {
+                ids.remove(&id);
+                if ids.is_empty() {
+                    self.keys.remove(&key);
+                }
+            }
+        }
+    }
+
+    pub fn remember(&mut self, id: u64, key: &str) {
+        let Some(client) = self.clients.get_mut(&id) else {
+            return;
+        };
+        client.keys.insert(key.to_string());
+        self.keys.entry(key.to_string()).or_default().insert(id);
+    }
+
+    /// key 被修改时通知所有读过它的连接，每次读取只会收到一次通知
+    pub fn invalidate(&mut self, key: &str) {
+        let Some(ids) = self.keys.remove(key) else {
+            return;
+        };
+        for id in ids {
+            if let Some(client) = self.clients.get_mut(&id) {
+                client.keys.remove(key);
+                // 连接已关闭时忽略错误
+                let _ = client.sender.send(key.to_string());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tracking_invalidate() {
+        let mut tracking = Tracking::default();
+        let (id1, mut rx1) = tracking.register();
+        let (id2, mut rx2) = tracking.register();
+
+        tracking.remember(id1, "foo");
+        tracking.remember(id2, "foo");
+        tracking.remember(id2, "bar");
+        tracking.invalidate("foo");
+        assert_eq!(rx1.try_recv().unwrap(), "foo");
+        assert_eq!(rx2.try_recv().unwrap(), "foo");
+
+        // 通知后需要重新读取才会再次被通知
+        tracking.invalidate("foo");
+        assert!(rx1.try_recv().is_err());
+
+        tracking.unregister(id2);
+        tracking.invalidate("bar");
+        assert!(tracking.keys.is_empty());
+        assert!(rx2.try_recv().is_err());
+    }
+}
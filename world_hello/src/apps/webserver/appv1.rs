@@ -1,3 +1,6 @@
+use crate::apps::webserver::config::Config;
+use crate::apps::webserver::http::{self, Method, Request, Response, StatusCode};
+use crate::apps::webserver::static_files::StaticFiles;
 use std::fs;
 use std::io::{prelude::*, BufReader};
 use std::net::{TcpListener, TcpStream};
@@ -5,36 +8,71 @@ use std::net::{TcpListener, TcpStream};
 // 单线程 web server
 // refer: https://course.rs/advance-practice1/web-server.html
 
-pub fn tcp_srv() {
-    let host = "127.0.0.1:7878";
-    println!("http serve at: {host}");
+pub fn tcp_srv(config: &Config) {
+    let host = &config.addr;
+    println!(
+        "http serve at: {host}, doc root: {}",
+        config.doc_root.display()
+    );
     let listener = TcpListener::bind(host).unwrap();
+    let files = StaticFiles::new(&config.doc_root);
 
     // 阻塞等待请求的进入
     for stream in listener.incoming() {
         let stream = stream.unwrap();
-        handle_connection(stream);
+        handle_connection(stream, &files, config);
     }
 }
 
-fn handle_connection(mut stream: TcpStream) {
-    let buf_reader = BufReader::new(&mut stream);
-    let req_line = buf_reader.lines().next().unwrap().unwrap();
-    let (status_line, filename) = if req_line == "GET / HTTP/1.1" {
-        ("HTTP/1.1 200 OK", "hello.html")
-    } else {
-        ("HTTP/1.1 404 NOT FOUND", "404.html")
-    };
+fn handle_connection(mut stream: TcpStream, files: &StaticFiles, config: &Config) {
+    // 客户端不读取响应时，写超时后关闭连接
+    if stream
+        .set_write_timeout(Some(config.write_timeout))
+        .is_err()
+    {
+        return;
+    }
 
-    // 读取文件内容写入连接缓存中
-    let mut file_path = String::from("/tmp/test/");
-    file_path.push_str(filename);
-    let contents = fs::read_to_string(file_path).unwrap();
-    let length = contents.len();
+    // buf 中保留已读取但未处理的数据，pipeline 的请求按顺序逐个处理
+    // 连接空闲超时后关闭，请求头或请求体没有在限定时间内读完时返回 408
+    let limits = config.limits();
+    let mut buf = Vec::new();
+    loop {
+        let req = match http::read_request(&mut stream, &mut buf, &limits) {
+            Ok(Some(req)) => req,
+            Ok(None) => return,
+            Err(err) => {
+                // 请求格式错误时返回 4xx/5xx, io 错误时直接关闭连接
+                if let Some(status) = err.status() {
+                    let resp = Response::new(status).with_header("Connection", "close");
+                    let _ = resp.write_to(&mut stream);
+                }
+                return;
+            }
+        };
 
-    let resp = format!("{status_line}\r\nContent-Length: {length}\r\n\r\n{contents}");
-    stream.write_all(resp.as_bytes()).unwrap();
-    stream.flush().unwrap();
+        let mut resp = handle_request(&req, files);
+        let keep_alive = resp.keep_alive(req.version, req.keep_alive());
+        if resp.write_to(&mut stream).is_err() || !keep_alive {
+            return;
+        }
+    }
+}
+
+fn handle_request(req: &Request, files: &StaticFiles) -> Response {
+    let resp = match (req.method, &req.path[..]) {
+        (Method::Get | Method::Head, "/") => files.serve(req, "hello.html"),
+        (Method::Get | Method::Head, path) => match files.serve(req, path) {
+            resp if resp.status == StatusCode::NotFound => files.not_found(req),
+            resp => resp,
+        },
+        _ => Response::new(StatusCode::MethodNotAllowed).with_header("Allow", "GET, HEAD"),
+    };
+    if req.method == Method::Head {
+        resp.without_body()
+    } else {
+        resp
+    }
 }
 
 #[allow(dead_code)]
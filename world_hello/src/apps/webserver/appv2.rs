@@ -1,51 +1,385 @@
-use crate::apps::webserver::pool;
+use crate::apps::webserver::config::Config;
+use crate::apps::webserver::conn_limit::ConnLimiter;
+use crate::apps::webserver::http::{
+    self, Body, Limits, Method, ReadTimeout, Request, Response, StatusCode,
+};
+use crate::apps::webserver::middleware::Middlewares;
+use crate::apps::webserver::pool::{PoolConfig, PoolError, QueuePolicy, ThreadPool};
+use crate::apps::webserver::router::Router;
+use crate::apps::webserver::static_files::StaticFiles;
+use crate::apps::webserver::tls;
+use native_tls::TlsAcceptor;
 use std::{
-    fs,
-    io::{prelude::*, BufReader},
-    net::{TcpListener, TcpStream},
+    io::{self, Read, Write},
+    net::{Ipv4Addr, Shutdown, SocketAddr, TcpListener, TcpStream},
+    path::Path,
+    sync::atomic::{AtomicBool, Ordering},
+    sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError},
+    sync::Arc,
     thread,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 // 多线程 web server
 // refer: https://course.rs/advance-practice1/multi-threads.html
 
-pub fn tcp_srv() {
-    let host = "127.0.0.1:7878";
-    println!("http serve at: {host}");
+/// 同时等待关闭的被拒绝连接的最大数量，超过时新的被拒绝连接直接关闭
+pub const MAX_REJECTED: usize = 256;
+/// 被拒绝的连接写回响应之后，最多等待客户端关闭的时间
+pub const REJECT_LINGER: Duration = Duration::from_millis(500);
 
-    let listener = TcpListener::bind(host).unwrap();
-    let pool = pool::ThreadPool::new(4);
+pub fn tcp_srv(config: &Config) {
+    // 队列满时直接返回 503, 避免请求在队列中等待过久
+    let pool = ThreadPool::with_config(PoolConfig {
+        min_workers: config.workers,
+        max_workers: config.max_workers,
+        idle_timeout: Duration::from_secs(60),
+        queue_capacity: config.queue_capacity,
+        queue_policy: QueuePolicy::Reject,
+    });
+    let server = Server::bind(config, pool).unwrap();
+    println!(
+        "http serve at: {}, doc root: {}, input 'quit' to stop",
+        config.addr,
+        config.doc_root.display()
+    );
 
-    // 测试，只接收前两个请求，然后就结束监听，随后 ThreadPool 也将超出作用域并自动触发 drop
-    for stream in listener.incoming().take(2) {
-        let stream = stream.unwrap();
-        pool.execute(move || {
-            handle_connection(stream);
-        });
-    }
+    let handle = server.shutdown_handle();
+    thread::spawn(move || {
+        for line in io::stdin().lines() {
+            match line {
+                Ok(line) if line.trim() == "quit" => {
+                    handle.shutdown();
+                    break;
+                }
+                Ok(_) => {}
+                Err(_) => break,
+            }
+        }
+    });
+
+    server.run();
     println!("Shutting down");
 }
 
-fn handle_connection(mut stream: TcpStream) {
-    let buf_reader = BufReader::new(&mut stream);
-    let request_line = buf_reader.lines().next().unwrap().unwrap();
-    // 由于 match 不会像方法那样自动做引用或者解引用，因此我们需要显式调用 match &request_line[..], 来获取所需的 &str 类型
-    let (status_line, filename) = match &request_line[..] {
-        "GET / HTTP/1.1" => ("HTTP/1.1 200 OK", "hello.html"),
-        "GET /sleep HTTP/1.1" => {
-            thread::sleep(Duration::from_secs(5));
-            ("HTTP/1.1 200 OK", "hello.html")
+pub struct Server {
+    listener: TcpListener,
+    app: Arc<App>,
+    pool: ThreadPool,
+    rejector: Rejector,
+}
+
+impl Server {
+    pub fn bind(config: &Config, pool: ThreadPool) -> io::Result<Server> {
+        let listener = TcpListener::bind(&config.addr)?;
+        Ok(Server {
+            listener,
+            // 路由表和 middleware 在所有 worker 线程间共享
+            app: Arc::new(app(config)?),
+            pool,
+            rejector: Rejector::spawn()?,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        let mut addr = self.listener.local_addr().expect("listener local addr");
+        // 监听所有地址时通过 loopback 唤醒 accept
+        if addr.ip().is_unspecified() {
+            addr.set_ip(Ipv4Addr::LOCALHOST.into());
+        }
+        ShutdownHandle {
+            shutdown: Arc::clone(&self.app.shutdown),
+            addr,
+        }
+    }
+
+    /// 处理连接直到收到 shutdown 信号，返回前等待已接收的连接处理完
+    pub fn run(mut self) {
+        for stream in self.listener.incoming() {
+            if self.app.shutdown.load(Ordering::SeqCst) {
+                break;
+            }
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(err) => {
+                    eprintln!("accept error: {err}");
+                    continue;
+                }
+            };
+
+            // 同一个 ip 的连接过多时返回 429, guard 在连接处理完后释放
+            let Ok(peer) = stream.peer_addr() else {
+                continue;
+            };
+            let Some(guard) = self.app.conns.acquire(peer.ip()) else {
+                let resp = Response::text(StatusCode::TooManyRequests, "too many connections");
+                self.reject(stream, resp);
+                continue;
+            };
+
+            // 保留一个连接的副本，任务被拒绝时用于返回 503
+            let rejected = stream.try_clone();
+            let app = Arc::clone(&self.app);
+            let job = move || {
+                let _guard = guard;
+                serve_connection(stream, &app);
+            };
+            match self.pool.execute(job) {
+                Ok(()) => {}
+                Err(PoolError::Full) => {
+                    if let Ok(stream) = rejected {
+                        let resp = Response::text(StatusCode::ServiceUnavailable, "server busy");
+                        self.reject(stream, resp);
+                    }
+                }
+                Err(PoolError::Closed) => break,
+            }
+        }
+
+        // 先关闭 listener 不再接收新连接，再等待队列中的连接处理完
+        drop(self.listener);
+        self.pool.shutdown();
+    }
+
+    // https 连接在握手之前无法返回响应，直接关闭
+    fn reject(&self, stream: TcpStream, resp: Response) {
+        if self.app.tls.is_none() {
+            self.rejector.reject(stream, resp);
+        }
+    }
+}
+
+// 被拒绝的连接交给单独的线程写回响应并关闭，accept 线程不读取也不等待这些连接
+// 否则客户端缓慢地发送数据就能拖住 accept, 限流反而让所有客户端都无法连接
+struct Rejector {
+    tx: SyncSender<(TcpStream, Response)>,
+}
+
+impl Rejector {
+    fn spawn() -> io::Result<Rejector> {
+        let (tx, rx) = mpsc::sync_channel(MAX_REJECTED);
+        thread::Builder::new()
+            .name(String::from("reject"))
+            .spawn(move || reap_rejected(rx))?;
+        Ok(Rejector { tx })
+    }
+
+    // 不会阻塞，处理不过来时直接关闭连接
+    fn reject(&self, stream: TcpStream, resp: Response) {
+        if let Err(TrySendError::Full((stream, _))) = self.tx.try_send((stream, resp)) {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+// 请求还未读取，直接关闭连接会发送 RST, 客户端可能收不到响应
+// 写回响应后关闭写端，在 REJECT_LINGER 内读取并丢弃客户端发送的数据，所有连接在同一个循环中处理
+fn reap_rejected(rx: Receiver<(TcpStream, Response)>) {
+    let mut lingering: Vec<(TcpStream, Instant)> = Vec::new();
+    loop {
+        // 没有等待关闭的连接时阻塞等待下一个被拒绝的连接
+        let next = if lingering.is_empty() {
+            rx.recv().map_err(|_| RecvTimeoutError::Disconnected)
+        } else {
+            rx.recv_timeout(Duration::from_millis(10))
+        };
+        match next {
+            Ok((stream, resp)) if lingering.len() < MAX_REJECTED => {
+                if let Some(stream) = write_rejection(stream, resp) {
+                    lingering.push((stream, Instant::now() + REJECT_LINGER));
+                }
+            }
+            Ok(_) | Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) if lingering.is_empty() => return,
+            Err(RecvTimeoutError::Disconnected) => thread::sleep(Duration::from_millis(10)),
+        }
+        let now = Instant::now();
+        lingering.retain_mut(|(stream, deadline)| now < *deadline && drain(stream));
+    }
+}
+
+// 写回响应后返回非阻塞的连接，写入失败时返回 None
+fn write_rejection(mut stream: TcpStream, resp: Response) -> Option<TcpStream> {
+    let resp = resp
+        .with_header("Retry-After", "1")
+        .with_header("Connection", "close");
+    stream
+        .set_write_timeout(Some(Duration::from_millis(100)))
+        .ok()?;
+    resp.write_to(&mut stream).ok()?;
+    stream.shutdown(Shutdown::Write).ok()?;
+    stream.set_nonblocking(true).ok()?;
+    Some(stream)
+}
+
+// 丢弃已经收到的数据，客户端关闭连接或出错时返回 false
+// 每次最多读取 16 次，持续发送数据的客户端不会占住循环
+fn drain(stream: &mut TcpStream) -> bool {
+    let mut buf = [0u8; 1024];
+    for _ in 0..16 {
+        match stream.read(&mut buf) {
+            Ok(0) => return false,
+            Ok(_) => {}
+            Err(err) => return err.kind() == io::ErrorKind::WouldBlock,
         }
-        _ => ("HTTP/1.1 404 NOT FOUND", "404.html"),
+    }
+    true
+}
+
+/// 可以在其他线程中停止 Server
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    shutdown: Arc<AtomicBool>,
+    addr: SocketAddr,
+}
+
+impl ShutdownHandle {
+    pub fn shutdown(&self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        // accept 是阻塞的，建立一个连接来唤醒它
+        let _ = TcpStream::connect(self.addr);
+    }
+}
+
+type Handler = Box<dyn Fn(&Request) -> Response + Send + Sync>;
+
+struct App {
+    router: Router<Handler>,
+    middlewares: Middlewares,
+    shutdown: Arc<AtomicBool>,
+    limits: Limits,
+    write_timeout: Duration,
+    conns: ConnLimiter,
+    tls: Option<TlsAcceptor>,
+}
+
+fn app(config: &Config) -> io::Result<App> {
+    Ok(App {
+        router: router(&config.doc_root),
+        middlewares: Middlewares::from_config(config),
+        shutdown: Arc::new(AtomicBool::new(false)),
+        limits: config.limits(),
+        write_timeout: config.write_timeout,
+        conns: ConnLimiter::new(config.max_conns_per_ip),
+        tls: tls::from_config(config)?,
+    })
+}
+
+fn router(doc_root: &Path) -> Router<Handler> {
+    let files = Arc::new(StaticFiles::new(doc_root));
+    let index = Arc::clone(&files);
+    let sleep = Arc::clone(&files);
+    let assets = Arc::clone(&files);
+    Router::<Handler>::new()
+        .get("/", Box::new(move |req| index.serve(req, "hello.html")))
+        .get(
+            "/sleep",
+            Box::new(move |req| {
+                thread::sleep(Duration::from_secs(5));
+                sleep.serve(req, "hello.html")
+            }),
+        )
+        .get(
+            "/hello/:name",
+            Box::new(|req| {
+                let name = req.param("name").unwrap_or_default();
+                Response::text(StatusCode::Ok, format!("Hello, {name}!"))
+            }),
+        )
+        .get(
+            "/stream",
+            Box::new(|_| {
+                // 在单独的线程中逐行生成输出，worker 线程负责以 chunked 编码写出
+                let (mut sender, body) = Body::channel(1);
+                thread::spawn(move || {
+                    for i in 0..5 {
+                        if sender.send_blocking(format!("line {i}\n")).is_err() {
+                            break;
+                        }
+                        thread::sleep(Duration::from_millis(500));
+                    }
+                });
+                Response::new(StatusCode::Ok)
+                    .with_header("Content-Type", "text/plain; charset=utf-8")
+                    .with_body(body)
+            }),
+        )
+        .get(
+            "/static/*path",
+            Box::new(move |req| assets.serve(req, req.param("path").unwrap_or_default())),
+        )
+        .fallback(Box::new(move |req| files.not_found(req)))
+}
+
+fn serve_connection(stream: TcpStream, app: &App) {
+    let remote_addr = stream.peer_addr().ok();
+    // 客户端不读取响应时，写超时后关闭连接
+    if stream.set_write_timeout(Some(app.write_timeout)).is_err() {
+        return;
+    }
+    let Some(tls) = &app.tls else {
+        handle_connection(stream, app, remote_addr);
+        return;
     };
 
-    let mut file_path = String::from("/tmp/test/");
-    file_path.push_str(filename);
-    let contents = fs::read_to_string(file_path).unwrap();
-    let length = contents.len();
+    // 握手与读取请求头使用相同的超时时间
+    if stream
+        .set_read_timeout(Some(app.limits.header_timeout))
+        .is_err()
+    {
+        return;
+    }
+    match tls.accept(stream) {
+        Ok(stream) => handle_connection(stream, app, remote_addr),
+        Err(err) => eprintln!("tls handshake error: {err}"),
+    }
+}
+
+fn handle_connection<S: ReadTimeout + Write>(
+    mut stream: S,
+    app: &App,
+    remote_addr: Option<SocketAddr>,
+) {
+    let mut buf = Vec::new();
+    loop {
+        // 收到 shutdown 信号后不再处理 keep-alive 连接上的新请求
+        if app.shutdown.load(Ordering::SeqCst) {
+            return;
+        }
+        // 空闲连接和发送很慢的请求都会超时，worker 线程不会被一个连接一直占用
+        let mut req = match http::read_request(&mut stream, &mut buf, &app.limits) {
+            Ok(Some(req)) => req,
+            Ok(None) => return,
+            Err(err) => {
+                if let Some(status) = err.status() {
+                    let resp = Response::new(status).with_header("Connection", "close");
+                    let _ = resp.write_to(&mut stream);
+                }
+                return;
+            }
+        };
+
+        req.remote_addr = remote_addr;
 
-    let resp = format!("{status_line}\r\nContent-Length: {length}\r\n\r\n{contents}");
-    stream.write_all(resp.as_bytes()).unwrap();
-    stream.flush().unwrap();
+        let (ctx, intercepted) = app.middlewares.before(&mut req);
+        let mut resp = intercepted.unwrap_or_else(|| match app.router.resolve(&mut req) {
+            Ok(handler) => handler(&req),
+            Err(resp) => resp,
+        });
+        app.middlewares.after(&ctx, &mut resp);
+        let mut resp = if req.method == Method::Head {
+            resp.without_body()
+        } else {
+            resp
+        };
+        let requested = req.keep_alive() && !app.shutdown.load(Ordering::SeqCst);
+        let keep_alive = resp.keep_alive(req.version, requested);
+        if resp.write_to(&mut stream).is_err() || !keep_alive {
+            return;
+        }
+    }
 }
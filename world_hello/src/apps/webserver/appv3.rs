@@ -1,79 +1,948 @@
-use async_std::io::{Read, Write};
+use crate::apps::webserver::config::Config;
+use crate::apps::webserver::conn_limit::ConnLimiter;
+use crate::apps::webserver::http::{
+    self, Body, BodyReader, Limits, Method, Request, RequestError, Response, StatusCode,
+    UploadLimits,
+};
+use crate::apps::webserver::middleware::{Context, Middlewares};
+use crate::apps::webserver::proxy::Proxy;
+use crate::apps::webserver::router::Router;
+use crate::apps::webserver::sse::{self, Event, Sse};
+use crate::apps::webserver::static_files::StaticFiles;
+use crate::apps::webserver::tls;
+use crate::apps::webserver::websocket::{self, Conn, Message, SocketHandler, WebSocket};
+use async_native_tls::TlsAcceptor;
+use async_std::future;
+use async_std::io::Write;
 use async_std::net::TcpListener;
 use async_std::task;
+use bytes::Bytes;
+use futures::channel::mpsc;
+use futures::future::{BoxFuture, Either, FutureExt};
+use futures::sink::SinkExt;
 use futures::stream::StreamExt;
-use futures::{AsyncReadExt, AsyncWriteExt};
-use std::marker::Unpin;
-use std::{fs, time::Duration};
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context as TaskContext, Poll};
+use std::time::Duration;
 
 // 异步 web server
 // refer: https://course.rs/advance/async/web-server.html
 
-pub async fn tcp_srv() {
-    let host = "127.0.0.1:7878";
-    println!("http serve at: {host}");
+// 上传文件的路由，请求体边读边解析
+const UPLOAD_PATH: &str = "/upload";
+
+pub async fn tcp_srv(config: &Config) {
+    let host = &config.addr;
+    println!(
+        "http serve at: {host}, doc root: {}",
+        config.doc_root.display()
+    );
     let listener = TcpListener::bind(host).await.unwrap();
+    serve(listener, config).await.unwrap();
+}
+
+/// 在 listener 上处理连接，配置了证书时使用 https
+pub async fn serve(listener: TcpListener, config: &Config) -> io::Result<()> {
+    let app = &app(config)?;
 
     // 使用 for_each_concurrent 并发地处理从 Stream 获取的元素
     listener
         .incoming()
         .for_each_concurrent(None, |stream| async move {
-            let stream = stream.unwrap();
-            // 一个线程并发处理
-            handle_connection(stream).await;
-            // 使用多线程并行处理请求
-            // task::spawn(handle_connection(stream));
+            let Ok(mut stream) = stream else {
+                return;
+            };
+            let remote_addr = stream.peer_addr().ok();
+            // 同一个 ip 的连接过多时返回 429, guard 在连接处理完后释放
+            let Some(_guard) = remote_addr.and_then(|addr| app.conns.acquire(addr.ip())) else {
+                // https 连接在握手之前无法返回响应，直接关闭
+                if app.tls.is_none() {
+                    let resp = Response::text(StatusCode::TooManyRequests, "too many connections")
+                        .with_header("Retry-After", "1")
+                        .with_header("Connection", "close");
+                    let _ =
+                        future::timeout(app.write_timeout, resp.write_to_async(&mut stream)).await;
+                }
+                return;
+            };
+
+            let Some(tls) = &app.tls else {
+                // 一个线程并发处理
+                handle_connection(stream, app, remote_addr).await;
+                // 使用多线程并行处理请求
+                // task::spawn(handle_connection(stream, app, remote_addr));
+                return;
+            };
+            // 握手与读取请求头使用相同的超时时间
+            match future::timeout(app.limits.header_timeout, tls.accept(stream)).await {
+                Ok(Ok(stream)) => handle_connection(stream, app, remote_addr).await,
+                Ok(Err(err)) => eprintln!("tls handshake error: {err}"),
+                Err(_) => {}
+            }
         })
         .await;
+    Ok(())
+}
+
+// 异步 handler 返回 boxed future, 以便不同的 handler 保存在同一个路由表中
+type Handler = Box<dyn Fn(Request) -> BoxFuture<'static, Response> + Send + Sync>;
+
+struct App {
+    router: Router<Handler>,
+    // websocket 的路由表，只用于请求升级为 websocket 的连接
+    sockets: Router<SocketHandler>,
+    middlewares: Arc<Middlewares>,
+    limits: Limits,
+    write_timeout: Duration,
+    conns: ConnLimiter,
+    tls: Option<TlsAcceptor>,
+    // 配置了 upstream 时转发的路径前缀，已规范化并以 / 结尾
+    proxy_prefix: Option<String>,
+}
+
+impl App {
+    // 转发和上传的请求体边读边交给 handler, 其他请求读完请求体后再处理
+    fn streams_body(&self, req: &Request) -> bool {
+        let proxied = self
+            .proxy_prefix
+            .as_deref()
+            .is_some_and(|prefix| req.path.starts_with(prefix));
+        let upload = req.method == Method::Post && req.path == UPLOAD_PATH;
+        (proxied || upload) && !websocket::is_upgrade(req)
+    }
+}
+
+fn app(config: &Config) -> io::Result<App> {
+    Ok(App {
+        router: router(config),
+        sockets: sockets(),
+        middlewares: Arc::new(Middlewares::from_config(config)),
+        limits: config.limits(),
+        write_timeout: config.write_timeout,
+        conns: ConnLimiter::new(config.max_conns_per_ip),
+        tls: tls::from_config(config)?.map(TlsAcceptor::from),
+        proxy_prefix: (!config.upstreams.is_empty()).then(|| {
+            let prefix = http::normalize_path(&config.proxy_prefix)
+                .unwrap_or_else(|| config.proxy_prefix.clone());
+            format!("{}/", prefix.trim_end_matches('/'))
+        }),
+    })
+}
+
+fn router(config: &Config) -> Router<Handler> {
+    let files = Arc::new(StaticFiles::new(&config.doc_root));
+    let index = Arc::clone(&files);
+    let sleep = Arc::clone(&files);
+    let assets = Arc::clone(&files);
+    proxy_routes(Router::<Handler>::new(), config)
+        .get(
+            "/",
+            Box::new(move |req| {
+                let files = Arc::clone(&index);
+                async move { files.serve(&req, "hello.html") }.boxed()
+            }),
+        )
+        .get(
+            "/sleep",
+            Box::new(move |req| {
+                let files = Arc::clone(&sleep);
+                async move {
+                    // 它仅会让当前的任务陷入睡眠，然后该任务会让出线程的控制权
+                    task::sleep(Duration::from_secs(3)).await;
+                    files.serve(&req, "hello.html")
+                }
+                .boxed()
+            }),
+        )
+        .get(
+            "/hello/:name",
+            Box::new(|req| {
+                async move {
+                    let name = req.param("name").unwrap_or_default();
+                    Response::text(StatusCode::Ok, format!("Hello, {name}!"))
+                }
+                .boxed()
+            }),
+        )
+        .post(
+            "/echo",
+            Box::new(|req| {
+                // chunked 编码的请求体已由解析器 decode
+                async move { Response::new(StatusCode::Ok).with_body(req.body) }.boxed()
+            }),
+        )
+        .post(
+            UPLOAD_PATH,
+            Box::new(|mut req| {
+                async move {
+                    // 边读请求体边写临时文件，都是阻塞操作，放到单独的线程中执行
+                    let parsed =
+                        task::spawn_blocking(move || req.multipart(&UploadLimits::default()))
+                            .await;
+                    let multipart = match parsed {
+                        Ok(multipart) => multipart,
+                        Err(err) => return err.into(),
+                    };
+                    // 只返回上传的内容摘要，临时文件随 multipart drop 删除
+                    let files: Vec<_> = multipart
+                        .files
+                        .iter()
+                        .map(|f| serde_json::json!({"name": f.name, "filename": f.filename, "size": f.size}))
+                        .collect();
+                    let body = serde_json::json!({"fields": multipart.fields, "files": files});
+                    Response::json(StatusCode::Ok, &body)
+                }
+                .boxed()
+            }),
+        )
+        .get(
+            "/stream",
+            Box::new(|req| {
+                async move {
+                    let lines: usize = req
+                        .query_param("lines")
+                        .and_then(|n| n.parse().ok())
+                        .unwrap_or(5);
+                    // 逐行生成输出，不需要先构造完整的 body
+                    let (mut sender, body) = Body::channel(1);
+                    task::spawn(async move {
+                        for i in 0..lines {
+                            if sender.send(format!("line {i}\n")).await.is_err() {
+                                break;
+                            }
+                            task::sleep(Duration::from_millis(10)).await;
+                        }
+                    });
+                    Response::new(StatusCode::Ok)
+                        .with_header("Content-Type", "text/plain; charset=utf-8")
+                        .with_body(body)
+                }
+                .boxed()
+            }),
+        )
+        .get(
+            "/events",
+            Box::new(|req| {
+                async move {
+                    // 重连时从 Last-Event-ID 之后的事件继续
+                    let start = sse::last_event_id(&req)
+                        .and_then(|id| id.parse::<u64>().ok())
+                        .map_or(0, |id| id + 1);
+                    let count: u64 = req
+                        .query_param("count")
+                        .and_then(|n| n.parse().ok())
+                        .unwrap_or(u64::MAX);
+                    let interval: u64 = req
+                        .query_param("ms")
+                        .and_then(|ms| ms.parse().ok())
+                        .unwrap_or(1000);
+                    let events = futures::stream::iter(start..start.saturating_add(count)).then(
+                        move |id| async move {
+                            task::sleep(Duration::from_millis(interval)).await;
+                            Event::data(format!("count {id}"))
+                                .event("count")
+                                .id(id.to_string())
+                        },
+                    );
+                    Sse::new(events).into_response()
+                }
+                .boxed()
+            }),
+        )
+        .get(
+            "/static/*path",
+            Box::new(move |req| {
+                let files = Arc::clone(&assets);
+                async move { files.serve(&req, req.param("path").unwrap_or_default()) }.boxed()
+            }),
+        )
+        .fallback(Box::new(move |req| {
+            let files = Arc::clone(&files);
+            async move { files.not_found(&req) }.boxed()
+        }))
+}
+
+async fn handle_connection(mut stream: impl Conn, app: &App, remote_addr: Option<SocketAddr>) {
+    // buf 中保留已读取但未处理的数据，pipeline 的请求按顺序逐个处理
+    let mut buf = Vec::new();
+    loop {
+        // 连接空闲超时后关闭，请求头或请求体没有在限定时间内读完时返回 408
+        let (mut req, body) =
+            match http::read_request_head_async(&mut stream, &mut buf, &app.limits).await {
+                Ok(Some(head)) => head,
+                Ok(None) => return,
+                Err(err) => return reject(&mut stream, app, err).await,
+            };
+
+        req.remote_addr = remote_addr;
+        let body = if app.streams_body(&req) && !body.is_done() {
+            Some(body)
+        } else {
+            if let Err(err) = body
+                .read_to_end_async(&mut stream, &mut buf, &mut req)
+                .await
+            {
+                return reject(&mut stream, app, err).await;
+            }
+            None
+        };
+
+        let method = req.method;
+        let version = req.version;
+        let requested_keep_alive = req.keep_alive();
+        // 没有读完的请求体无法与下一个请求区分，响应之后关闭连接
+        let mut body_unread = body.is_some();
+        let (ctx, intercepted) = app.middlewares.before(&mut req);
+        // 升级后连接不再用于 http, 没有对应的 websocket 路由时按普通请求处理
+        if intercepted.is_none() && websocket::is_upgrade(&req) {
+            if let Ok(handler) = app.sockets.resolve(&mut req) {
+                upgrade(stream, buf, req, &ctx, handler, app).await;
+                return;
+            }
+        }
+        let resp = match intercepted {
+            Some(resp) => resp,
+            None => match app.router.resolve(&mut req) {
+                Ok(handler) => match body {
+                    Some(body) => {
+                        let (tx, rx) = mpsc::channel(1);
+                        req.body_stream = Some(rx);
+                        let pump = Box::pin(pump_body(&mut stream, &mut buf, body, tx));
+                        // handler 先返回时不再读取剩余的请求体
+                        let handled = match futures::future::select(handler(req), pump).await {
+                            Either::Left((resp, _)) => Ok(resp),
+                            Either::Right((Ok(done), handled)) => {
+                                body_unread = !done;
+                                Ok(handled.await)
+                            }
+                            Either::Right((Err(err), _)) => Err(err),
+                        };
+                        match handled {
+                            Ok(resp) => resp,
+                            Err(err) => return reject(&mut stream, app, err).await,
+                        }
+                    }
+                    None => handler(req).await,
+                },
+                Err(resp) => resp,
+            },
+        };
+        let resp = after(app, &ctx, resp).await;
+        let mut resp = if method == Method::Head {
+            resp.without_body()
+        } else {
+            resp
+        };
+        let keep_alive = resp.keep_alive(version, requested_keep_alive && !body_unread);
+        // 客户端不读取响应时，写超时后关闭连接
+        // 超时针对每次写入而不是整个响应，stream 和 sse 的响应可以一直保持
+        let mut writer = WriteTimeout::new(&mut stream, app.write_timeout);
+        if resp.write_to_async(&mut writer).await.is_err() || !keep_alive {
+            return;
+        }
+    }
+}
+
+// 请求格式错误或请求体读取失败时返回 4xx/5xx 并关闭连接，io 错误时直接关闭
+async fn reject(stream: &mut impl Conn, app: &App, err: RequestError) {
+    if let Some(status) = err.status() {
+        let resp = Response::new(status).with_header("Connection", "close");
+        let _ = future::timeout(app.write_timeout, resp.write_to_async(stream)).await;
+    }
+}
+
+// 边读边把请求体交给 handler, 与 handler 并发执行
+// handler 不再读取请求体时返回 false, 剩余的请求体没有读取
+async fn pump_body(
+    stream: &mut impl Conn,
+    buf: &mut Vec<u8>,
+    mut body: BodyReader,
+    mut tx: mpsc::Sender<io::Result<Bytes>>,
+) -> Result<bool, RequestError> {
+    loop {
+        let chunk = match body.read_async(stream, buf).await {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => return Ok(true),
+            Err(err) => {
+                // 以错误结束请求体，handler 据此知道请求体不完整
+                let aborted = io::Error::new(io::ErrorKind::InvalidData, err.to_string());
+                let _ = tx.send(Err(aborted)).await;
+                return Err(err);
+            }
+        };
+        if tx.send(Ok(Bytes::from(chunk))).await.is_err() {
+            return Ok(false);
+        }
+    }
+}
+
+// 压缩文件 body 时 middleware 会读取文件，在阻塞线程中执行，避免阻塞 executor
+async fn after(app: &App, ctx: &Context, mut resp: Response) -> Response {
+    if !matches!(resp.body, Body::File { .. }) {
+        app.middlewares.after(ctx, &mut resp);
+        return resp;
+    }
+    let middlewares = Arc::clone(&app.middlewares);
+    let ctx = ctx.clone();
+    task::spawn_blocking(move || {
+        middlewares.after(&ctx, &mut resp);
+        resp
+    })
+    .await
+}
+
+/// 一次写入在 timeout 内没有完成时返回 TimedOut 错误
+struct WriteTimeout<W> {
+    inner: W,
+    timeout: Duration,
+    // 当前写入开始等待时启动的计时器
+    timer: Option<BoxFuture<'static, ()>>,
+}
+
+impl<W: Write + Unpin> WriteTimeout<W> {
+    fn new(inner: W, timeout: Duration) -> Self {
+        WriteTimeout {
+            inner,
+            timeout,
+            timer: None,
+        }
+    }
+
+    fn poll_timeout<T>(
+        &mut self,
+        cx: &mut TaskContext<'_>,
+        poll: Poll<io::Result<T>>,
+    ) -> Poll<io::Result<T>> {
+        if poll.is_ready() {
+            self.timer = None;
+            return poll;
+        }
+        let timeout = self.timeout;
+        let timer = self
+            .timer
+            .get_or_insert_with(|| task::sleep(timeout).boxed());
+        match timer.poll_unpin(cx) {
+            Poll::Ready(()) => {
+                self.timer = None;
+                Poll::Ready(Err(io::ErrorKind::TimedOut.into()))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<W: Write + Unpin> Write for WriteTimeout<W> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut self.inner).poll_write(cx, buf);
+        self.poll_timeout(cx, poll)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        let poll = Pin::new(&mut self.inner).poll_flush(cx);
+        self.poll_timeout(cx, poll)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        let poll = Pin::new(&mut self.inner).poll_close(cx);
+        self.poll_timeout(cx, poll)
+    }
 }
 
-async fn handle_connection(mut stream: impl Read + Write + Unpin) {
-    let mut buf = [0; 1024];
-    stream.read(&mut buf).await.unwrap();
-
-    let get = b"GET / HTTP/1.1\r\n";
-    let sleep = b"GET /sleep HTTP/1.1\r\n";
-    let (status_line, filename) = if buf.starts_with(get) {
-        ("HTTP/1.1 200 OK\r\n\r\n", "hello.html")
-    } else if buf.starts_with(sleep) {
-        // 它仅会让当前的任务陷入睡眠，然后该任务会让出线程的控制权
-        task::sleep(Duration::from_secs(3)).await;
-        ("HTTP/1.1 200 OK\r\n\r\n", "hello.html")
-    } else {
-        ("HTTP/1.1 404 NOT FOUND\r\n\r\n", "404.html")
+// 配置了 upstream 时，proxy_prefix 下所有方法的请求都转发给 upstream, 优先于其他路由
+fn proxy_routes(mut router: Router<Handler>, config: &Config) -> Router<Handler> {
+    let Some(proxy) = Proxy::from_config(config) else {
+        return router;
     };
+    let proxy = Arc::new(proxy);
+    let pattern = format!("{}/*path", config.proxy_prefix.trim_end_matches('/'));
+    for method in [
+        Method::Get,
+        Method::Head,
+        Method::Post,
+        Method::Put,
+        Method::Delete,
+        Method::Patch,
+        Method::Options,
+    ] {
+        let proxy = Arc::clone(&proxy);
+        router = router.route(
+            method,
+            &pattern,
+            Box::new(move |req| {
+                let proxy = Arc::clone(&proxy);
+                async move { proxy.forward(req).await }.boxed()
+            }),
+        );
+    }
+    router
+}
 
-    let mut file_path = String::from("/tmp/test/");
-    file_path.push_str(filename);
-    let content = fs::read_to_string(file_path).unwrap();
-    let resp = format!("{status_line}{content}");
-    stream.write_all(resp.as_bytes()).await.unwrap();
-    stream.flush().await.unwrap();
+fn sockets() -> Router<SocketHandler> {
+    Router::<SocketHandler>::new()
+        .get(
+            "/ws/echo",
+            websocket::handler(|_, mut ws| {
+                async move {
+                    while let Some(Ok(msg)) = ws.recv().await {
+                        let reply = match msg {
+                            Message::Text(_) | Message::Binary(_) => msg,
+                            _ => continue,
+                        };
+                        if ws.send(reply).await.is_err() {
+                            break;
+                        }
+                    }
+                }
+                .boxed()
+            }),
+        )
+        .get(
+            "/ws/ticks",
+            websocket::handler(|req, mut ws| {
+                async move {
+                    let interval: u64 = req
+                        .query_param("ms")
+                        .and_then(|ms| ms.parse().ok())
+                        .unwrap_or(1000);
+                    // 定时推送消息，同时读取客户端的消息以便回复 ping 和处理关闭
+                    let sender = ws.sender();
+                    let push = async move {
+                        for i in 0.. {
+                            task::sleep(Duration::from_millis(interval)).await;
+                            if sender
+                                .send(Message::Text(format!("tick {i}")))
+                                .await
+                                .is_err()
+                            {
+                                break;
+                            }
+                        }
+                    };
+                    let read = async move { while let Some(Ok(_)) = ws.recv().await {} };
+                    futures::future::select(push.boxed(), read.boxed()).await;
+                }
+                .boxed()
+            }),
+        )
+}
+
+// 完成 websocket 握手后把连接交给 handler, buf 中是握手请求之后已经读取的数据
+async fn upgrade(
+    mut stream: impl Conn,
+    buf: Vec<u8>,
+    req: Request,
+    ctx: &Context,
+    handler: &SocketHandler,
+    app: &App,
+) {
+    let (mut resp, upgraded) = match websocket::handshake(&req) {
+        Ok(resp) => (resp, true),
+        Err(resp) => (resp.with_header("Connection", "close"), false),
+    };
+    app.middlewares.after(ctx, &mut resp);
+    let written = future::timeout(app.write_timeout, resp.write_to_async(&mut stream)).await;
+    if !upgraded || !matches!(written, Ok(Ok(()))) {
+        return;
+    }
+
+    let ws = WebSocket::new(stream, buf, app.limits.max_body_size);
+    let sender = ws.sender();
+    handler(req, ws).await;
+    // handler 返回时没有关闭连接，发送 close
+    let _ = sender.close(websocket::CLOSE_NORMAL, "").await;
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::apps::webserver::mock_stream::MockTcpStream;
+    use crate::apps::webserver::static_files::test_root;
+
+    const HELLO: &str = "<h1>Hello!</h1>";
+
+    fn test_app(name: &str) -> App {
+        let script = "console.log('hello');\n".repeat(100);
+        let root = test_root(
+            name,
+            &[
+                ("hello.html", HELLO),
+                ("404.html", "<h1>Oops!</h1>"),
+                ("css/site.css", "body {}"),
+                ("js/app.js", &script),
+            ],
+        );
+        app(&Config {
+            doc_root: root,
+            ..Config::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_streams_body() {
+        let app = app(&Config {
+            upstreams: vec![String::from("127.0.0.1:1")],
+            proxy_prefix: String::from("/app"),
+            ..Config::default()
+        })
+        .unwrap();
+        let cases = [
+            ("/app/x", true),
+            ("//app/x", true),
+            ("/%61pp/x", true),
+            ("/./app/x", true),
+            ("/apple", false),
+            ("/static/app/x", false),
+            ("/upload", true),
+            ("/upload/x", false),
+        ];
+        for (target, streams) in cases {
+            let raw = format!("POST {target} HTTP/1.1\r\nHost: a\r\n\r\n");
+            let req = http::parse_request(raw.as_bytes()).unwrap().unwrap().0;
+            assert_eq!(app.streams_body(&req), streams, "target: {target}");
+        }
+    }
 
     #[async_std::test]
     async fn test_handle_connection() {
-        let input_bytes = b"GET / HTTP/1.1\r\n";
-        let mut content = vec![0u8; 1024]; // 初始化 byte 数组
-        content[..input_bytes.len()].clone_from_slice(input_bytes);
+        let input_bytes = b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        let mut stream = MockTcpStream {
+            read_data: input_bytes.to_vec(),
+            write_data: Vec::new(),
+        };
+
+        handle_connection(&mut stream, &test_app("appv3_index"), None).await;
+
+        let expected_body = HELLO;
+        let resp = String::from_utf8(stream.write_data).unwrap();
+        assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(resp.contains(&format!("\r\nContent-Length: {}\r\n", expected_body.len())));
+        assert!(resp.contains("\r\nX-Response-Time: "));
+        assert!(resp.ends_with(&format!("\r\n\r\n{}", expected_body)));
+    }
+
+    #[async_std::test]
+    async fn test_handle_connection_compress_file() {
+        let mut stream = MockTcpStream {
+            read_data: b"GET /static/js/app.js HTTP/1.1\r\nHost: localhost\r\n\
+                Accept-Encoding: gzip\r\n\r\n"
+                .to_vec(),
+            write_data: Vec::new(),
+        };
+        handle_connection(&mut stream, &test_app("appv3_compress"), None).await;
+
+        let split = stream.write_data.windows(4).position(|w| w == b"\r\n\r\n");
+        let (head, body) = stream.write_data.split_at(split.unwrap() + 4);
+        let head = String::from_utf8_lossy(head);
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "head: {head}");
+        assert!(head.contains("\r\nContent-Encoding: gzip\r\n"));
+        let mut decoded = String::new();
+        std::io::Read::read_to_string(&mut flate2::read::GzDecoder::new(body), &mut decoded)
+            .unwrap();
+        assert_eq!(decoded, "console.log('hello');\n".repeat(100));
+    }
+
+    #[async_std::test]
+    async fn test_handle_connection_static_files() {
+        let app = test_app("appv3_static");
+        let cases: Vec<(&[u8], &str, &str)> = vec![
+            (
+                b"GET /static/css/site.css HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                "HTTP/1.1 200 OK\r\n",
+                "\r\n\r\nbody {}",
+            ),
+            (
+                b"HEAD /static/css/site.css HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                "HTTP/1.1 200 OK\r\n",
+                "\r\nContent-Length: 7\r\n\r\n",
+            ),
+            (
+                b"GET /static/missing.css HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                "HTTP/1.1 404 Not Found\r\n",
+                "\r\n\r\nnot found",
+            ),
+            // 路径中的 .. 在解析请求时拒绝
+            (
+                b"GET /static/../hello.html HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                "HTTP/1.1 400 Bad Request\r\n",
+                "\r\n\r\n",
+            ),
+            // 规范化之后的路径
+            (
+                b"GET //static/./css/%73ite.css HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                "HTTP/1.1 200 OK\r\n",
+                "\r\n\r\nbody {}",
+            ),
+            (
+                b"GET /missing HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                "HTTP/1.1 404 Not Found\r\n",
+                "\r\n\r\n<h1>Oops!</h1>",
+            ),
+        ];
+        for (input, status_line, suffix) in cases {
+            let mut stream = MockTcpStream {
+                read_data: input.to_vec(),
+                write_data: Vec::new(),
+            };
+            handle_connection(&mut stream, &app, None).await;
+            let resp = String::from_utf8(stream.write_data).unwrap();
+            assert!(resp.starts_with(status_line), "resp: {resp}");
+            assert!(resp.ends_with(suffix), "resp: {resp}");
+        }
+    }
+
+    #[async_std::test]
+    async fn test_handle_connection_bad_request() {
+        let mut stream = MockTcpStream {
+            read_data: b"GET / HTTP/1.1\r\n\r\n".to_vec(),
+            write_data: Vec::new(),
+        };
+
+        handle_connection(&mut stream, &test_app("appv3_router"), None).await;
+        let resp = String::from_utf8(stream.write_data).unwrap();
+        assert!(resp.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+        assert!(resp.contains("\r\nContent-Length: 0\r\n"));
+        assert!(resp.ends_with("\r\n\r\n"));
+    }
+
+    #[async_std::test]
+    async fn test_handle_connection_router() {
+        let mut stream = MockTcpStream {
+            read_data: b"GET /hello/rust HTTP/1.1\r\nHost: localhost\r\n\r\n".to_vec(),
+            write_data: Vec::new(),
+        };
+        handle_connection(&mut stream, &test_app("appv3_router"), None).await;
+        let resp = String::from_utf8(stream.write_data).unwrap();
+        assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(resp.ends_with("\r\n\r\nHello, rust!"));
+
+        let mut stream = MockTcpStream {
+            read_data: b"POST / HTTP/1.1\r\nHost: localhost\r\n\r\n".to_vec(),
+            write_data: Vec::new(),
+        };
+        handle_connection(&mut stream, &test_app("appv3_router"), None).await;
+        let resp = String::from_utf8(stream.write_data).unwrap();
+        assert!(resp.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+        assert!(resp.contains("\r\nAllow: GET, HEAD\r\n"));
+    }
+
+    #[async_std::test]
+    async fn test_handle_connection_pipelining() {
+        // 多个请求一次性到达，按顺序返回响应，Connection: close 之后的请求不再处理
+        let mut input = Vec::new();
+        input.extend_from_slice(b"GET /hello/a HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        input.extend_from_slice(b"HEAD /hello/b HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        input.extend_from_slice(
+            b"GET /hello/c HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        );
+        input.extend_from_slice(b"GET /hello/d HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        let mut stream = MockTcpStream {
+            read_data: input,
+            write_data: Vec::new(),
+        };
+        handle_connection(&mut stream, &test_app("appv3_pipelining"), None).await;
+
+        let resp = String::from_utf8(stream.write_data).unwrap();
+        let responses: Vec<&str> = resp.split("HTTP/1.1 ").filter(|s| !s.is_empty()).collect();
+        assert_eq!(responses.len(), 3, "resp: {resp}");
+        assert!(responses[0].contains("\r\nConnection: keep-alive\r\n"));
+        assert!(responses[0].ends_with("\r\n\r\nHello, a!"));
+        assert!(responses[1].contains("\r\nContent-Length: 9\r\n"));
+        assert!(responses[1].ends_with("\r\n\r\n"));
+        assert!(responses[2].contains("\r\nConnection: close\r\n"));
+        assert!(responses[2].ends_with("\r\n\r\nHello, c!"));
+        assert!(!resp.contains("Hello, d!"));
+    }
+
+    #[async_std::test]
+    async fn test_handle_connection_http10() {
+        // http/1.0 默认不保持连接
+        let mut input = b"GET /hello/a HTTP/1.0\r\n\r\n".to_vec();
+        input.extend_from_slice(b"GET /hello/b HTTP/1.0\r\n\r\n");
+        let mut stream = MockTcpStream {
+            read_data: input,
+            write_data: Vec::new(),
+        };
+        handle_connection(&mut stream, &test_app("appv3_http10"), None).await;
+
+        let resp = String::from_utf8(stream.write_data).unwrap();
+        assert!(resp.contains("\r\nConnection: close\r\n"));
+        assert!(resp.ends_with("Hello, a!"));
+    }
+
+    #[async_std::test]
+    async fn test_handle_connection_chunked() {
+        let mut input = Vec::new();
+        input.extend_from_slice(
+            b"POST /echo HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n",
+        );
+        input.extend_from_slice(b"4\r\nping\r\n0\r\n\r\n");
+        input.extend_from_slice(b"GET /stream?lines=2 HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        let mut stream = MockTcpStream {
+            read_data: input,
+            write_data: Vec::new(),
+        };
+        handle_connection(&mut stream, &test_app("appv3_chunked"), None).await;
+
+        let resp = String::from_utf8(stream.write_data).unwrap();
+        let (echo, streamed) = resp
+            .split_once("HTTP/1.1 200 OK\r\n")
+            .unwrap()
+            .1
+            .split_once("HTTP/1.1 200 OK\r\n")
+            .unwrap();
+        assert!(echo.contains("\r\nContent-Length: 4\r\n"));
+        assert!(echo.ends_with("\r\n\r\nping"));
+        assert!(streamed.contains("\r\nTransfer-Encoding: chunked\r\n"));
+        assert!(streamed.ends_with("\r\n\r\n7\r\nline 0\n\r\n7\r\nline 1\n\r\n0\r\n\r\n"));
+    }
+
+    #[async_std::test]
+    async fn test_handle_connection_upload() {
+        let body = "--b\r\nContent-Disposition: form-data; name=\"title\"\r\n\r\nhi\r\n\
+            --b\r\nContent-Disposition: form-data; name=\"f\"; filename=\"a.txt\"\r\n\r\nabc\r\n\
+            --b--\r\n";
+        let input = format!(
+            "POST /upload HTTP/1.1\r\nHost: localhost\r\n\
+            Content-Type: multipart/form-data; boundary=b\r\nContent-Length: {}\r\n\r\n{body}\
+            POST /upload HTTP/1.1\r\nHost: localhost\r\nContent-Type: text/plain\r\n\
+            Content-Length: 2\r\nConnection: close\r\n\r\nhi",
+            body.len()
+        );
+        let mut stream = MockTcpStream {
+            read_data: input.into_bytes(),
+            write_data: Vec::new(),
+        };
+        handle_connection(&mut stream, &test_app("appv3_upload"), None).await;
+
+        let resp = String::from_utf8(stream.write_data).unwrap();
+        let (uploaded, rejected) = resp.split_once("HTTP/1.1 415 ").unwrap();
+        assert!(uploaded.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(uploaded.contains("\r\nContent-Type: application/json\r\n"));
+        assert!(uploaded.ends_with(
+            r#"{"fields":[["title","hi"]],"files":[{"filename":"a.txt","name":"f","size":3}]}"#
+        ));
+        assert!(rejected.ends_with("unsupported media type, expect multipart/form-data"));
+    }
+
+    #[async_std::test]
+    async fn test_handle_connection_websocket() {
+        use crate::apps::webserver::websocket::tests::{client_frame, server_frames};
+
+        let mut input = b"GET /ws/echo HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\n\
+            Connection: Upgrade\r\nSec-WebSocket-Version: 13\r\n\
+            Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n"
+            .to_vec();
+        input.extend(client_frame(true, 0x1, b"hello"));
+        input.extend(client_frame(true, 0x9, b"p"));
+        input.extend(client_frame(true, 0x2, &[1, 2, 3]));
+        input.extend(client_frame(true, 0x8, &[0x03, 0xe8]));
+        let mut stream = MockTcpStream {
+            read_data: input,
+            write_data: Vec::new(),
+        };
+        handle_connection(&mut stream, &test_app("appv3_websocket"), None).await;
+
+        let resp = stream.write_data;
+        let end = resp.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+        let head = std::str::from_utf8(&resp[..end]).unwrap();
+        assert!(head.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+        assert!(head.contains("\r\nSec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+        assert!(head.contains("\r\nConnection: Upgrade\r\n"));
+        assert_eq!(
+            server_frames(&resp[end..]),
+            vec![
+                (0x1, b"hello".to_vec()),
+                (0xA, b"p".to_vec()),
+                (0x2, vec![1, 2, 3]),
+                (0x8, vec![0x03, 0xe8]),
+            ]
+        );
+
+        // 握手失败时返回错误并关闭连接，没有 websocket 路由的路径按普通请求处理
+        let mut stream = MockTcpStream {
+            read_data: b"GET /ws/echo HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\n\
+                Connection: Upgrade\r\nSec-WebSocket-Version: 13\r\n\r\n\
+                GET /hello/a HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\n\r\n"
+                .to_vec(),
+            write_data: Vec::new(),
+        };
+        handle_connection(&mut stream, &test_app("appv3_websocket_bad"), None).await;
+        let resp = String::from_utf8(stream.write_data).unwrap();
+        assert!(resp.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+        assert!(!resp.contains("Hello, a!"));
+
+        let mut stream = MockTcpStream {
+            read_data: b"GET /hello/a HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\n\
+                Connection: close\r\n\r\n"
+                .to_vec(),
+            write_data: Vec::new(),
+        };
+        handle_connection(&mut stream, &test_app("appv3_websocket_http"), None).await;
+        let resp = String::from_utf8(stream.write_data).unwrap();
+        assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(resp.ends_with("Hello, a!"));
+    }
+
+    #[async_std::test]
+    async fn test_handle_connection_sse() {
         let mut stream = MockTcpStream {
-            read_data: content,
+            read_data: b"GET /events?count=2&ms=1 HTTP/1.1\r\nHost: localhost\r\n\
+                Last-Event-ID: 5\r\nConnection: close\r\n\r\n"
+                .to_vec(),
             write_data: Vec::new(),
         };
+        handle_connection(&mut stream, &test_app("appv3_sse"), None).await;
+
+        let resp = String::from_utf8(stream.write_data).unwrap();
+        assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(resp.contains("\r\nContent-Type: text/event-stream\r\n"));
+        assert!(resp.contains("\r\nTransfer-Encoding: chunked\r\n"));
+        let first = "event: count\nid: 6\ndata: count 6\n\n";
+        let second = "event: count\nid: 7\ndata: count 7\n\n";
+        assert!(resp.ends_with(&format!(
+            "\r\n\r\n{:x}\r\n{first}\r\n{:x}\r\n{second}\r\n0\r\n\r\n",
+            first.len(),
+            second.len()
+        )));
+    }
+
+    #[async_std::test]
+    async fn test_write_timeout() {
+        use futures::AsyncWriteExt;
+
+        // 客户端不读取时写入一直无法完成
+        struct Stalled;
+        impl Write for Stalled {
+            fn poll_write(
+                self: Pin<&mut Self>,
+                _: &mut TaskContext<'_>,
+                _: &[u8],
+            ) -> Poll<io::Result<usize>> {
+                Poll::Pending
+            }
+            fn poll_flush(self: Pin<&mut Self>, _: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+                Poll::Ready(Ok(()))
+            }
+            fn poll_close(self: Pin<&mut Self>, _: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+                Poll::Ready(Ok(()))
+            }
+        }
 
-        handle_connection(&mut stream).await;
-        let mut buf = [0u8; 1024];
-        stream.read(&mut buf).await.unwrap();
+        let mut writer = WriteTimeout::new(Stalled, Duration::from_millis(20));
+        let err = writer.write_all(b"data").await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
 
-        let file_path = "/tmp/test/hello.html";
-        let expected_body = fs::read_to_string(file_path).unwrap();
-        let expected_resp = format!("HTTP/1.1 200 OK\r\n\r\n{}", expected_body);
-        assert!(stream.write_data.starts_with(expected_resp.as_bytes()));
-        // println!("resp: {}", expected_resp);
+        let mut data = Vec::new();
+        let mut writer = WriteTimeout::new(&mut data, Duration::from_millis(20));
+        writer.write_all(b"data").await.unwrap();
+        assert_eq!(data, b"data");
     }
 }
@@ -0,0 +1,393 @@
+use crate::apps::webserver::http::Limits;
+use crate::apps::webserver::proxy::Balance;
+use crate::apps::webserver::rate_limit::RateKey;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+// web server config
+
+/// server 的实现方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// appv1: 单线程，逐个处理连接
+    Single,
+    /// appv2: 线程池
+    Pool,
+    /// appv3: async-std 异步
+    Async,
+}
+
+impl FromStr for Mode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "single" => Ok(Mode::Single),
+            "pool" => Ok(Mode::Pool),
+            "async" => Ok(Mode::Async),
+            _ => Err(format!("unknown mode [{s}], expect single, pool or async")),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub addr: String,
+    pub mode: Mode,
+    /// 线程池常驻的 worker 数，仅用于 pool 模式
+    pub workers: usize,
+    /// 任务积压时线程池最多增加到的 worker 数，仅用于 pool 模式
+    pub max_workers: usize,
+    /// 线程池队列容量，队列满时返回 503, 仅用于 pool 模式
+    pub queue_capacity: usize,
+    pub doc_root: PathBuf,
+    /// 连接空闲超过该时间后关闭
+    pub keep_alive_timeout: Duration,
+    /// 从收到请求的第一个字节开始，读完请求头的最长时间，超时返回 408
+    pub header_timeout: Duration,
+    /// 读完请求体的最长时间，超时返回 408
+    pub body_timeout: Duration,
+    /// 一次写入的最长时间，客户端长时间不读取响应时关闭连接
+    pub write_timeout: Duration,
+    pub max_headers: usize,
+    pub max_header_size: usize,
+    pub max_body_size: usize,
+    /// 每个客户端 ip 同时打开的最大连接数，超过时返回 429, 不用于 single 模式
+    pub max_conns_per_ip: usize,
+    /// PEM 格式的证书链，与 tls_key 同时设置时使用 https, 不用于 single 模式
+    pub tls_cert: Option<PathBuf>,
+    /// PEM 格式的 PKCS#8 私钥
+    pub tls_key: Option<PathBuf>,
+    /// 反向代理的 upstream 地址，不为空时把 proxy_prefix 下的请求转发给它们，仅用于 async 模式
+    pub upstreams: Vec<String>,
+    pub balance: Balance,
+    /// 转发时去掉该前缀
+    pub proxy_prefix: String,
+    /// 每个客户端每秒允许的请求数，超过时返回 429, 不用于 single 模式
+    pub rate_limit: Option<f64>,
+    /// 每个客户端最多可以连续发送的请求数，默认为 rate_limit 向上取整
+    pub rate_burst: Option<usize>,
+    pub rate_limit_key: RateKey,
+    /// http basic 认证的用户名和密码，不为空时 auth_prefix 下的请求需要认证，不用于 single 模式
+    pub auth_users: Vec<(String, String)>,
+    pub auth_prefix: String,
+    /// 允许跨域请求的 origin, * 表示允许所有 origin, 为空时不处理跨域请求，不用于 single 模式
+    pub cors_origins: Vec<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let limits = Limits::default();
+        Config {
+            addr: String::from("127.0.0.1:7878"),
+            mode: Mode::Pool,
+            workers: 4,
+            max_workers: 8,
+            queue_capacity: 64,
+            doc_root: PathBuf::from("/tmp/test"),
+            keep_alive_timeout: limits.keep_alive_timeout,
+            header_timeout: limits.header_timeout,
+            body_timeout: limits.body_timeout,
+            write_timeout: Duration::from_secs(30),
+            max_headers: limits.max_headers,
+            max_header_size: limits.max_header_size,
+            max_body_size: limits.max_body_size,
+            max_conns_per_ip: 32,
+            tls_cert: None,
+            tls_key: None,
+            upstreams: Vec::new(),
+            balance: Balance::RoundRobin,
+            proxy_prefix: String::from("/"),
+            rate_limit: None,
+            rate_burst: None,
+            rate_limit_key: RateKey::Ip,
+            auth_users: Vec::new(),
+            auth_prefix: String::from("/"),
+            cors_origins: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    /// 从命令行参数构建配置:
+    /// [--config server.conf] [--addr host:port] [--mode single|pool|async] [--workers n]
+    /// [--max-workers n] [--queue-capacity n] [--doc-root dir] [--keep-alive-timeout secs]
+    /// [--header-timeout secs] [--body-timeout secs] [--write-timeout secs] [--max-headers n]
+    /// [--max-header-size bytes] [--max-body-size bytes] [--max-conns-per-ip n]
+    /// [--tls-cert server.crt --tls-key server.key]
+    /// [--upstream host:port,host:port] [--balance round-robin|least-conn] [--proxy-prefix /api]
+    /// [--rate-limit n] [--rate-burst n] [--rate-limit-key ip|header:X-Api-Key]
+    /// [--auth-user name:password] [--auth-prefix /admin] [--cors-origin https://a.com,https://b.com]
+    ///
+    /// 参数按顺序生效，--config 之后的参数会覆盖配置文件中的值
+    pub fn build(mut args: impl Iterator<Item = String>) -> Result<Config, String> {
+        args.next();
+
+        let mut config = Config::default();
+        while let Some(flag) = args.next() {
+            let value = match args.next() {
+                Some(value) => value,
+                None => return Err(format!("missing value for {flag}")),
+            };
+            match flag.strip_prefix("--") {
+                Some("config") => config.load(&value)?,
+                Some(key) => config.set(key, &value)?,
+                None => return Err(format!("unknown option {flag}")),
+            }
+        }
+
+        if config.workers > config.max_workers {
+            config.max_workers = config.workers;
+        }
+        match (&config.tls_cert, &config.tls_key) {
+            (Some(_), None) | (None, Some(_)) => {
+                return Err(String::from("tls-cert and tls-key must be set together"))
+            }
+            (Some(_), Some(_)) if config.mode == Mode::Single => {
+                return Err(String::from("tls is not supported in single mode"))
+            }
+            _ => {}
+        }
+        if !config.upstreams.is_empty() && config.mode != Mode::Async {
+            return Err(String::from("proxy is only supported in async mode"));
+        }
+        Ok(config)
+    }
+
+    /// 配置文件每行一个 `key = value`, key 与命令行参数同名（不带 --）, # 开头的行为注释
+    pub fn load(&mut self, path: &str) -> Result<(), String> {
+        let contents =
+            fs::read_to_string(path).map_err(|err| format!("read config {path}: {err}"))?;
+        self.parse(&contents)
+            .map_err(|err| format!("config {path}: {err}"))
+    }
+
+    fn parse(&mut self, contents: &str) -> Result<(), String> {
+        for (lineno, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| format!("invalid line {}: {}", lineno + 1, line))?;
+            self.set(key.trim(), value.trim())
+                .map_err(|err| format!("line {}: {err}", lineno + 1))?;
+        }
+        Ok(())
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let invalid = || format!("invalid value [{value}] for {key}");
+        match key {
+            "addr" => self.addr = value.to_string(),
+            "mode" => self.mode = value.parse()?,
+            "workers" => self.workers = positive(value).ok_or_else(invalid)?,
+            "max-workers" => self.max_workers = positive(value).ok_or_else(invalid)?,
+            "queue-capacity" => self.queue_capacity = positive(value).ok_or_else(invalid)?,
+            "doc-root" => self.doc_root = PathBuf::from(value),
+            "keep-alive-timeout" => self.keep_alive_timeout = secs(value).ok_or_else(invalid)?,
+            "header-timeout" => self.header_timeout = secs(value).ok_or_else(invalid)?,
+            "body-timeout" => self.body_timeout = secs(value).ok_or_else(invalid)?,
+            "write-timeout" => self.write_timeout = secs(value).ok_or_else(invalid)?,
+            "max-headers" => self.max_headers = positive(value).ok_or_else(invalid)?,
+            "max-header-size" => self.max_header_size = positive(value).ok_or_else(invalid)?,
+            "max-body-size" => self.max_body_size = positive(value).ok_or_else(invalid)?,
+            "max-conns-per-ip" => self.max_conns_per_ip = positive(value).ok_or_else(invalid)?,
+            "tls-cert" => self.tls_cert = Some(PathBuf::from(value)),
+            "tls-key" => self.tls_key = Some(PathBuf::from(value)),
+            // 可以用逗号分隔多个地址，也可以多次设置
+            "upstream" => self.upstreams.extend(
+                value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|addr| !addr.is_empty())
+                    .map(String::from),
+            ),
+            "balance" => self.balance = value.parse()?,
+            "proxy-prefix" if value.starts_with('/') => self.proxy_prefix = value.to_string(),
+            "proxy-prefix" => return Err(invalid()),
+            "rate-limit" => {
+                let rate = value
+                    .parse()
+                    .ok()
+                    .filter(|r: &f64| r.is_finite() && *r > 0.0);
+                self.rate_limit = Some(rate.ok_or_else(invalid)?);
+            }
+            "rate-burst" => self.rate_burst = Some(positive(value).ok_or_else(invalid)?),
+            "rate-limit-key" => self.rate_limit_key = value.parse()?,
+            // 可以多次设置，密码中可以包含冒号
+            "auth-user" => match value.split_once(':') {
+                Some((name, password)) if !name.is_empty() => self
+                    .auth_users
+                    .push((name.to_string(), password.to_string())),
+                _ => return Err(invalid()),
+            },
+            "auth-prefix" if value.starts_with('/') => self.auth_prefix = value.to_string(),
+            "auth-prefix" => return Err(invalid()),
+            "cors-origin" => self.cors_origins.extend(
+                value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|origin| !origin.is_empty())
+                    .map(String::from),
+            ),
+            _ => return Err(format!("unknown option {key}")),
+        }
+        Ok(())
+    }
+
+    /// 读取请求时的限制
+    pub fn limits(&self) -> Limits {
+        Limits {
+            max_header_size: self.max_header_size,
+            max_headers: self.max_headers,
+            max_body_size: self.max_body_size,
+            keep_alive_timeout: self.keep_alive_timeout,
+            header_timeout: self.header_timeout,
+            body_timeout: self.body_timeout,
+        }
+    }
+}
+
+fn positive(value: &str) -> Option<usize> {
+    value.parse().ok().filter(|n| *n > 0)
+}
+
+// 超时时间为正整数秒
+fn secs(value: &str) -> Option<Duration> {
+    value
+        .parse()
+        .ok()
+        .filter(|n| *n > 0)
+        .map(Duration::from_secs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> impl Iterator<Item = String> {
+        std::iter::once("webserver")
+            .chain(args.iter().copied())
+            .map(String::from)
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    #[test]
+    fn test_config_build() {
+        let config = Config::build(args(&[])).unwrap();
+        assert_eq!(config.addr, "127.0.0.1:7878");
+        assert_eq!(config.mode, Mode::Pool);
+
+        let config = Config::build(args(&[
+            "--addr",
+            "0.0.0.0:8080",
+            "--mode",
+            "async",
+            "--workers",
+            "16",
+            "--keep-alive-timeout",
+            "30",
+            "--max-conns-per-ip",
+            "2",
+            "--upstream",
+            "127.0.0.1:9001, 127.0.0.1:9002",
+            "--upstream",
+            "127.0.0.1:9003",
+            "--balance",
+            "least-conn",
+            "--rate-limit",
+            "0.5",
+            "--rate-limit-key",
+            "header:X-Api-Key",
+            "--auth-user",
+            "admin:se:cret",
+            "--auth-prefix",
+            "/admin",
+            "--cors-origin",
+            "https://a.com, https://b.com",
+        ]))
+        .unwrap();
+        assert_eq!(config.addr, "0.0.0.0:8080");
+        assert_eq!(config.mode, Mode::Async);
+        assert_eq!(config.workers, 16);
+        assert_eq!(config.max_workers, 16);
+        assert_eq!(config.keep_alive_timeout, Duration::from_secs(30));
+        assert_eq!(config.max_conns_per_ip, 2);
+        assert_eq!(config.limits().keep_alive_timeout, Duration::from_secs(30));
+        assert_eq!(
+            config.upstreams,
+            ["127.0.0.1:9001", "127.0.0.1:9002", "127.0.0.1:9003"]
+        );
+        assert_eq!(config.balance, Balance::LeastConnections);
+        assert_eq!(config.rate_limit, Some(0.5));
+        assert_eq!(config.rate_burst, None);
+        assert_eq!(
+            config.auth_users,
+            [(String::from("admin"), String::from("se:cret"))]
+        );
+        assert_eq!(config.auth_prefix, "/admin");
+        assert_eq!(config.cors_origins, ["https://a.com", "https://b.com"]);
+        assert_eq!(
+            config.rate_limit_key,
+            RateKey::Header(String::from("x-api-key"))
+        );
+
+        for bad in [
+            &["--mode", "fork"][..],
+            &["--workers", "0"],
+            &["--header-timeout", "0"],
+            &["--workers"],
+            &["--port", "80"],
+            &["addr", "x"],
+            &["--tls-cert", "a.crt"],
+            &["--upstream", "127.0.0.1:9001"],
+            &["--mode", "async", "--balance", "random"],
+            &["--mode", "async", "--proxy-prefix", "api"],
+            &["--rate-limit", "0"],
+            &["--rate-limit", "NaN"],
+            &["--rate-burst", "0"],
+            &["--rate-limit-key", "cookie"],
+            &["--auth-user", "admin"],
+            &["--auth-user", ":secret"],
+            &["--auth-prefix", "admin"],
+            &[
+                "--mode",
+                "single",
+                "--tls-cert",
+                "a.crt",
+                "--tls-key",
+                "a.key",
+            ],
+        ] {
+            assert!(Config::build(args(bad)).is_err(), "args: {bad:?}");
+        }
+    }
+
+    #[test]
+    fn test_config_file() {
+        let path = std::env::temp_dir().join(format!("webserver_conf_{}", std::process::id()));
+        fs::write(
+            &path,
+            "# web server\naddr = 127.0.0.1:9000\nmode = single\ndoc-root = /var/www\n\nworkers = 2\n",
+        )
+        .unwrap();
+        let path = path.to_str().unwrap();
+
+        // 配置文件之后的参数覆盖文件中的值
+        let config = Config::build(args(&["--config", path, "--workers", "3"])).unwrap();
+        assert_eq!(config.addr, "127.0.0.1:9000");
+        assert_eq!(config.mode, Mode::Single);
+        assert_eq!(config.doc_root, PathBuf::from("/var/www"));
+        assert_eq!(config.workers, 3);
+
+        let mut config = Config::default();
+        let err = config.parse("addr = x\nworkers 4\n").unwrap_err();
+        assert_eq!(err, "invalid line 2: workers 4");
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,88 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+
+// 限制每个客户端 ip 同时打开的连接数
+// 防止单个客户端打开大量连接（如 slowloris）占满 worker
+
+#[derive(Clone)]
+pub struct ConnLimiter {
+    max_per_ip: usize,
+    conns: Arc<Mutex<HashMap<IpAddr, usize>>>,
+}
+
+impl ConnLimiter {
+    pub fn new(max_per_ip: usize) -> Self {
+        ConnLimiter {
+            max_per_ip,
+            conns: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// 连接数未超过限制时返回 guard, guard drop 时释放该连接
+    pub fn acquire(&self, ip: IpAddr) -> Option<ConnGuard> {
+        let mut conns = self.conns.lock().unwrap();
+        let count = conns.entry(ip).or_insert(0);
+        if *count >= self.max_per_ip {
+            return None;
+        }
+        *count += 1;
+        Some(ConnGuard {
+            ip,
+            conns: Arc::clone(&self.conns),
+        })
+    }
+
+    /// ip 当前打开的连接数
+    pub fn count(&self, ip: IpAddr) -> usize {
+        self.conns
+            .lock()
+            .unwrap()
+            .get(&ip)
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+pub struct ConnGuard {
+    ip: IpAddr,
+    conns: Arc<Mutex<HashMap<IpAddr, usize>>>,
+}
+
+impl Drop for ConnGuard {
+    fn drop(&mut self) {
+        let mut conns = self.conns.lock().unwrap_or_else(|err| err.into_inner());
+        if let Some(count) = conns.get_mut(&self.ip) {
+            *count -= 1;
+            // 没有连接的 ip 从表中移除，避免表无限增长
+            if *count == 0 {
+                conns.remove(&self.ip);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn test_conn_limiter() {
+        let limiter = ConnLimiter::new(2);
+        let a = IpAddr::from(Ipv4Addr::new(10, 0, 0, 1));
+        let b = IpAddr::from(Ipv4Addr::new(10, 0, 0, 2));
+
+        let first = limiter.acquire(a).unwrap();
+        let _second = limiter.acquire(a).unwrap();
+        assert!(limiter.acquire(a).is_none());
+        assert!(limiter.acquire(b).is_some());
+        assert_eq!(limiter.count(a), 2);
+
+        drop(first);
+        assert_eq!(limiter.count(a), 1);
+        assert!(limiter.acquire(a).is_some());
+        assert_eq!(limiter.count(b), 0);
+        assert!(limiter.conns.lock().unwrap().get(&b).is_none());
+    }
+}
@@ -0,0 +1,674 @@
+use crate::apps::webserver::http::{Headers, Request, Response, StatusCode};
+use serde::de::DeserializeOwned;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// request body 解析: application/x-www-form-urlencoded, multipart/form-data 和 json
+// refer: https://www.rfc-editor.org/rfc/rfc7578
+
+/// multipart 中单个文件的最大字节数
+pub const MAX_FILE_SIZE: usize = 512 * 1024;
+/// multipart 中最多的 part 数
+pub const MAX_PARTS: usize = 32;
+/// multipart 中非文件字段的最大字节数
+pub const MAX_FIELD_SIZE: usize = 8 * 1024;
+
+/// multipart 中单个 part 的 headers 的最大字节数
+pub const MAX_PART_HEADER_SIZE: usize = 8 * 1024;
+
+#[derive(Debug, thiserror::Error)]
+pub enum BodyError {
+    #[error("unsupported media type, expect {0}")]
+    UnsupportedMediaType(&'static str),
+    #[error("bad request: {0}")]
+    BadRequest(String),
+    #[error("payload too large: {0}")]
+    PayloadTooLarge(&'static str),
+    #[error("io error: {0}")]
+    Io(#[from] io::Error),
+}
+
+impl BodyError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            BodyError::UnsupportedMediaType(_) => StatusCode::UnsupportedMediaType,
+            BodyError::BadRequest(_) => StatusCode::BadRequest,
+            BodyError::PayloadTooLarge(_) => StatusCode::PayloadTooLarge,
+            BodyError::Io(_) => StatusCode::InternalServerError,
+        }
+    }
+}
+
+impl From<BodyError> for Response {
+    fn from(err: BodyError) -> Self {
+        Response::text(err.status(), err.to_string())
+    }
+}
+
+/// multipart 的限制，以及保存上传文件的目录
+#[derive(Debug, Clone)]
+pub struct UploadLimits {
+    pub dir: PathBuf,
+    pub max_file_size: usize,
+    pub max_parts: usize,
+    pub max_field_size: usize,
+}
+
+impl Default for UploadLimits {
+    fn default() -> Self {
+        UploadLimits {
+            dir: std::env::temp_dir(),
+            max_file_size: MAX_FILE_SIZE,
+            max_parts: MAX_PARTS,
+            max_field_size: MAX_FIELD_SIZE,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Multipart {
+    /// 非文件字段，按出现顺序
+    pub fields: Vec<(String, String)>,
+    pub files: Vec<FilePart>,
+}
+
+impl Multipart {
+    pub fn field(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn file(&self, name: &str) -> Option<&FilePart> {
+        self.files.iter().find(|f| f.name == name)
+    }
+}
+
+/// 保存在临时文件中的上传文件，没有 persist 时 drop 会删除临时文件
+#[derive(Debug)]
+pub struct FilePart {
+    pub name: String,
+    /// 客户端提供的文件名，不能直接用作路径
+    pub filename: String,
+    pub content_type: Option<String>,
+    pub size: u64,
+    path: PathBuf,
+    persisted: bool,
+}
+
+impl FilePart {
+    /// 临时文件的路径
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// 把临时文件移动到 to, 之后不再自动删除
+    pub fn persist(mut self, to: impl AsRef<Path>) -> io::Result<()> {
+        let to = to.as_ref();
+        if fs::rename(&self.path, to).is_err() {
+            // 不在同一个文件系统时 rename 会失败，改为复制
+            fs::copy(&self.path, to)?;
+            let _ = fs::remove_file(&self.path);
+        }
+        self.persisted = true;
+        Ok(())
+    }
+}
+
+impl Drop for FilePart {
+    fn drop(&mut self) {
+        if !self.persisted {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+impl Request {
+    /// 返回 Content-Type 的 media type（小写，不包含参数）
+    pub fn media_type(&self) -> Option<String> {
+        let value = self.header("content-type")?;
+        let media_type = value.split(';').next().unwrap_or_default().trim();
+        Some(media_type.to_ascii_lowercase())
+    }
+
+    /// 把 application/x-www-form-urlencoded 的 body 解析为 T
+    /// T 可以是结构体，也可以是 Vec<(String, String)> 或 HashMap<String, String>
+    pub fn form<T: DeserializeOwned>(&self) -> Result<T, BodyError> {
+        self.expect_media_type(
+            |t| t == "application/x-www-form-urlencoded",
+            "application/x-www-form-urlencoded",
+        )?;
+        serde_urlencoded::from_bytes(&self.body)
+            .map_err(|err| BodyError::BadRequest(format!("invalid form: {err}")))
+    }
+
+    /// 把 json body 解析为 T, 支持 application/json 和 application/*+json
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T, BodyError> {
+        self.expect_media_type(
+            |t| t == "application/json" || (t.starts_with("application/") && t.ends_with("+json")),
+            "application/json",
+        )?;
+        serde_json::from_slice(&self.body)
+            .map_err(|err| BodyError::BadRequest(format!("invalid json: {err}")))
+    }
+
+    /// 解析 multipart/form-data, 文件边解析边写入 limits.dir 下的临时文件
+    ///
+    /// 有 body_stream 时从中边读边解析，会阻塞等待请求体，需要在阻塞线程中调用；
+    /// 否则解析已读取的 body。内存中只保留非文件字段和单个 part 的 headers,
+    /// 大小分别受 max_field_size 和 MAX_PART_HEADER_SIZE 限制
+    pub fn multipart(&mut self, limits: &UploadLimits) -> Result<Multipart, BodyError> {
+        self.expect_media_type(|t| t == "multipart/form-data", "multipart/form-data")?;
+        let boundary = self
+            .header("content-type")
+            .and_then(|value| header_param(value, "boundary"))
+            .filter(|b| !b.is_empty() && b.len() <= 70)
+            .ok_or_else(|| BodyError::BadRequest(String::from("missing multipart boundary")))?;
+        let mut parser = MultipartParser::new(&boundary, limits);
+        match self.body_stream.take() {
+            Some(stream) => {
+                // 读完整个请求体，连接可以继续用于下一个请求
+                for chunk in futures::executor::block_on_stream(stream) {
+                    parser.feed(&chunk?)?;
+                }
+            }
+            None => parser.feed(&self.body)?,
+        }
+        parser.finish()
+    }
+
+    fn expect_media_type(
+        &self,
+        matches: impl Fn(&str) -> bool,
+        expected: &'static str,
+    ) -> Result<(), BodyError> {
+        match self.media_type() {
+            Some(media_type) if matches(&media_type) => Ok(()),
+            _ => Err(BodyError::UnsupportedMediaType(expected)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    // 第一个分隔符之前的 preamble
+    Preamble,
+    // 分隔符之后，等待 "--" 或 CRLF
+    Boundary,
+    Headers,
+    Content,
+    Done,
+}
+
+// part 的内容，文件边解析边写入临时文件
+enum Part {
+    Field(String, Vec<u8>),
+    File(FilePart, File),
+}
+
+/// 增量解析 multipart, 每次传入一段数据，已经确定属于 part 内容的部分立即写入文件
+/// 内存中只保留不完整的 part headers、非文件字段和可能是分隔符前缀的数据
+struct MultipartParser<'a> {
+    limits: &'a UploadLimits,
+    delimiter: Vec<u8>,
+    buf: Vec<u8>,
+    state: State,
+    part: Option<Part>,
+    multipart: Multipart,
+}
+
+impl<'a> MultipartParser<'a> {
+    fn new(boundary: &str, limits: &'a UploadLimits) -> Self {
+        MultipartParser {
+            limits,
+            delimiter: format!("\r\n--{boundary}").into_bytes(),
+            buf: Vec::new(),
+            state: State::Preamble,
+            part: None,
+            multipart: Multipart::default(),
+        }
+    }
+
+    fn feed(&mut self, data: &[u8]) -> Result<(), BodyError> {
+        // 结束分隔符之后的 epilogue 会被忽略
+        if self.state == State::Done {
+            return Ok(());
+        }
+        self.buf.extend_from_slice(data);
+        while self.step()? {}
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<Multipart, BodyError> {
+        match self.state {
+            State::Done => Ok(std::mem::take(&mut self.multipart)),
+            State::Preamble => Err(bad("missing boundary")),
+            State::Boundary => Err(bad("invalid boundary line")),
+            State::Headers => Err(bad("incomplete part headers")),
+            State::Content => Err(bad("missing closing boundary")),
+        }
+    }
+
+    // 处理 buf 中的数据，需要更多数据时返回 false
+    fn step(&mut self) -> Result<bool, BodyError> {
+        match self.state {
+            State::Preamble => {
+                // preamble 中的分隔符前面没有 CRLF
+                let boundary = &self.delimiter[2..];
+                match find(&self.buf, boundary) {
+                    Some(start) => {
+                        self.buf.drain(..start + boundary.len());
+                        self.state = State::Boundary;
+                        Ok(true)
+                    }
+                    None => {
+                        let keep = self.buf.len().min(boundary.len() - 1);
+                        self.buf.drain(..self.buf.len() - keep);
+                        Ok(false)
+                    }
+                }
+            }
+            State::Boundary => {
+                if self.buf.len() < 2 {
+                    return Ok(false);
+                }
+                if self.buf.starts_with(b"--") {
+                    self.buf.clear();
+                    self.state = State::Done;
+                    return Ok(false);
+                }
+                if !self.buf.starts_with(b"\r\n") {
+                    return Err(bad("invalid boundary line"));
+                }
+                self.buf.drain(..2);
+                let parts = self.multipart.fields.len() + self.multipart.files.len();
+                if parts >= self.limits.max_parts {
+                    return Err(BodyError::PayloadTooLarge("too many parts"));
+                }
+                self.state = State::Headers;
+                Ok(true)
+            }
+            State::Headers => {
+                let Some(head_end) = find(&self.buf, b"\r\n\r\n") else {
+                    if self.buf.len() > MAX_PART_HEADER_SIZE {
+                        return Err(BodyError::PayloadTooLarge("part headers too large"));
+                    }
+                    return Ok(false);
+                };
+                self.part = Some(self.start_part(head_end)?);
+                self.buf.drain(..head_end + 4);
+                self.state = State::Content;
+                Ok(true)
+            }
+            State::Content => match find(&self.buf, &self.delimiter) {
+                Some(end) => {
+                    self.write(end)?;
+                    self.buf.drain(..self.delimiter.len());
+                    self.finish_part()?;
+                    self.state = State::Boundary;
+                    Ok(true)
+                }
+                None => {
+                    // 末尾可能是被截断的分隔符，保留到下一段数据到达
+                    let keep = self.buf.len().min(self.delimiter.len() - 1);
+                    self.write(self.buf.len() - keep)?;
+                    Ok(false)
+                }
+            },
+            State::Done => Ok(false),
+        }
+    }
+
+    fn start_part(&self, head_end: usize) -> Result<Part, BodyError> {
+        let head = std::str::from_utf8(&self.buf[..head_end])
+            .map_err(|_| bad("invalid utf-8 in part headers"))?;
+        let headers = part_headers(head).ok_or_else(|| bad("invalid part header"))?;
+        let disposition = headers
+            .get("content-disposition")
+            .filter(|v| {
+                v.split(';')
+                    .next()
+                    .is_some_and(|t| t.trim().eq_ignore_ascii_case("form-data"))
+            })
+            .ok_or_else(|| bad("missing content-disposition"))?;
+        let name = header_param(disposition, "name").ok_or_else(|| bad("missing field name"))?;
+        match header_param(disposition, "filename") {
+            Some(filename) => {
+                let content_type = headers.get("content-type").map(String::from);
+                let (part, file) = create_file(name, filename, content_type, &self.limits.dir)?;
+                Ok(Part::File(part, file))
+            }
+            None => Ok(Part::Field(name, Vec::new())),
+        }
+    }
+
+    // 把 buf 的前 n 个字节写入当前 part
+    fn write(&mut self, n: usize) -> Result<(), BodyError> {
+        let content = &self.buf[..n];
+        match self.part.as_mut() {
+            Some(Part::Field(_, value)) => {
+                if value.len() + n > self.limits.max_field_size {
+                    return Err(BodyError::PayloadTooLarge("field too large"));
+                }
+                value.extend_from_slice(content);
+            }
+            Some(Part::File(part, file)) => {
+                if part.size as usize + n > self.limits.max_file_size {
+                    return Err(BodyError::PayloadTooLarge("file too large"));
+                }
+                file.write_all(content)?;
+                part.size += n as u64;
+            }
+            None => {}
+        }
+        self.buf.drain(..n);
+        Ok(())
+    }
+
+    fn finish_part(&mut self) -> Result<(), BodyError> {
+        match self.part.take() {
+            Some(Part::Field(name, value)) => {
+                let value =
+                    String::from_utf8(value).map_err(|_| bad("invalid utf-8 in field value"))?;
+                self.multipart.fields.push((name, value));
+            }
+            Some(Part::File(part, file)) => {
+                file.sync_all()?;
+                self.multipart.files.push(part);
+            }
+            None => {}
+        }
+        Ok(())
+    }
+}
+
+fn bad(msg: &str) -> BodyError {
+    BodyError::BadRequest(format!("invalid multipart: {msg}"))
+}
+
+fn part_headers(head: &str) -> Option<Headers> {
+    let mut headers = Headers::new();
+    for line in head.split("\r\n").filter(|line| !line.is_empty()) {
+        let (name, value) = line.split_once(':')?;
+        headers.append(name.trim(), value.trim());
+    }
+    Some(headers)
+}
+
+// 创建空的临时文件，出错返回时 FilePart 的 drop 会删除临时文件
+fn create_file(
+    name: String,
+    filename: String,
+    content_type: Option<String>,
+    dir: &Path,
+) -> Result<(FilePart, File), BodyError> {
+    static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let path = dir.join(format!("upload-{}-{id}", std::process::id()));
+    let file = File::create(&path)?;
+    let part = FilePart {
+        name,
+        filename,
+        content_type,
+        size: 0,
+        path,
+        persisted: false,
+    };
+    Ok((part, file))
+}
+
+/// 返回 header 值中的参数，如 `form-data; name="a"` 中的 name, 参数名不区分大小写
+fn header_param(value: &str, name: &str) -> Option<String> {
+    let mut rest = value.split_once(';')?.1;
+    loop {
+        let (key, after) = rest.split_once('=')?;
+        let key = key.trim();
+        let after = after.trim_start();
+        let (param, next) = match after.strip_prefix('"') {
+            // quoted-string 中 \ 用于转义下一个字符
+            Some(quoted) => {
+                let mut param = String::new();
+                let mut chars = quoted.char_indices();
+                let end = loop {
+                    match chars.next()? {
+                        (i, '"') => break i + 1,
+                        (_, '\\') => param.push(chars.next()?.1),
+                        (_, c) => param.push(c),
+                    }
+                };
+                let next = quoted[end..].split_once(';').map_or("", |(_, next)| next);
+                (param, next)
+            }
+            None => match after.split_once(';') {
+                Some((param, next)) => (param.trim().to_string(), next),
+                None => (after.trim().to_string(), ""),
+            },
+        };
+        if key.eq_ignore_ascii_case(name) {
+            return Some(param);
+        }
+        if next.is_empty() {
+            return None;
+        }
+        rest = next;
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apps::webserver::http::parse_request;
+    use bytes::Bytes;
+    use serde::Deserialize;
+    use std::collections::HashMap;
+
+    fn request(content_type: &str, body: &str) -> Request {
+        let raw = format!(
+            "POST / HTTP/1.1\r\nHost: a\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        );
+        parse_request(raw.as_bytes()).unwrap().unwrap().0
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct User {
+        name: String,
+        age: u32,
+    }
+
+    #[test]
+    fn test_body_form_and_json() {
+        let req = request(
+            "application/x-www-form-urlencoded",
+            "name=Hello+World&age=3",
+        );
+        let user: User = req.form().unwrap();
+        assert_eq!(user.name, "Hello World");
+        assert_eq!(user.age, 3);
+        let form: HashMap<String, String> = req.form().unwrap();
+        assert_eq!(form["age"], "3");
+
+        let req = request("application/x-www-form-urlencoded", "name=a&age=x");
+        assert_eq!(
+            req.form::<User>().unwrap_err().status(),
+            StatusCode::BadRequest
+        );
+
+        let req = request(
+            "application/json; charset=utf-8",
+            r#"{"name":"rust","age":10}"#,
+        );
+        let user: User = req.json().unwrap();
+        assert_eq!(
+            user,
+            User {
+                name: String::from("rust"),
+                age: 10
+            }
+        );
+        assert_eq!(
+            req.form::<User>().unwrap_err().status(),
+            StatusCode::UnsupportedMediaType
+        );
+
+        let req = request("application/json", r#"{"name":"rust"}"#);
+        let err = req.json::<User>().unwrap_err();
+        assert_eq!(err.status(), StatusCode::BadRequest);
+        let resp = Response::from(err);
+        assert_eq!(resp.status, StatusCode::BadRequest);
+
+        let req = request("text/plain", "{}");
+        assert_eq!(
+            req.json::<User>().unwrap_err().status(),
+            StatusCode::UnsupportedMediaType
+        );
+    }
+
+    const MULTIPART: &str = "preamble\r\n\
+        --XyZ\r\n\
+        Content-Disposition: form-data; name=\"title\"\r\n\r\n\
+        hello\r\n\
+        --XyZ\r\n\
+        Content-Disposition: form-data; name=\"file\"; filename=\"a \\\"b\\\".txt\"\r\n\
+        Content-Type: text/plain\r\n\r\n\
+        line 1\r\nline 2\r\n\
+        --XyZ--\r\n";
+
+    #[test]
+    fn test_body_multipart() {
+        let dir = std::env::temp_dir().join(format!("webserver_upload_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let limits = UploadLimits {
+            dir: dir.clone(),
+            ..UploadLimits::default()
+        };
+
+        let mut req = request("multipart/form-data; boundary=XyZ", MULTIPART);
+        let mut multipart = req.multipart(&limits).unwrap();
+        assert_eq!(multipart.field("title"), Some("hello"));
+        let file = multipart.file("file").unwrap();
+        assert_eq!(file.filename, "a \"b\".txt");
+        assert_eq!(file.content_type.as_deref(), Some("text/plain"));
+        assert_eq!(file.size, 14);
+        assert_eq!(fs::read_to_string(file.path()).unwrap(), "line 1\r\nline 2");
+
+        // 没有 persist 的文件在 drop 时删除
+        let file = multipart.files.pop().unwrap();
+        let tmp = file.path().to_path_buf();
+        let saved = dir.join("saved.txt");
+        file.persist(&saved).unwrap();
+        assert!(!tmp.exists());
+        assert!(saved.exists());
+        let tmp = req.multipart(&limits).unwrap().files[0]
+            .path()
+            .to_path_buf();
+        assert!(!tmp.exists());
+
+        let small = UploadLimits {
+            max_file_size: 4,
+            ..limits.clone()
+        };
+        let err = req.multipart(&small).unwrap_err();
+        assert_eq!(err.status(), StatusCode::PayloadTooLarge);
+        let few = UploadLimits {
+            max_parts: 1,
+            ..limits.clone()
+        };
+        assert_eq!(
+            req.multipart(&few).unwrap_err().status(),
+            StatusCode::PayloadTooLarge
+        );
+
+        let mut req = request("multipart/form-data", MULTIPART);
+        assert_eq!(
+            req.multipart(&limits).unwrap_err().status(),
+            StatusCode::BadRequest
+        );
+        let truncated = &MULTIPART[..MULTIPART.len() - 10];
+        let mut req = request("multipart/form-data; boundary=XyZ", truncated);
+        assert_eq!(
+            req.multipart(&limits).unwrap_err().status(),
+            StatusCode::BadRequest
+        );
+        let mut req = request("text/plain", MULTIPART);
+        assert_eq!(
+            req.multipart(&limits).unwrap_err().status(),
+            StatusCode::UnsupportedMediaType
+        );
+
+        // 出错时已经保存的临时文件也会删除
+        let uploads = fs::read_dir(&dir).unwrap().count();
+        assert_eq!(uploads, 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_body_multipart_stream() {
+        let dir =
+            std::env::temp_dir().join(format!("webserver_upload_stream_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let limits = UploadLimits {
+            dir: dir.clone(),
+            ..UploadLimits::default()
+        };
+
+        // 每次只到达一个字节，分隔符被拆开时也能正确解析
+        let mut req = request("multipart/form-data; boundary=XyZ", "");
+        let (mut tx, rx) = futures::channel::mpsc::channel(MULTIPART.len());
+        for b in MULTIPART.bytes() {
+            tx.try_send(Ok(Bytes::from(vec![b]))).unwrap();
+        }
+        drop(tx);
+        req.body_stream = Some(rx);
+        let multipart = req.multipart(&limits).unwrap();
+        assert_eq!(multipart.field("title"), Some("hello"));
+        let file = multipart.file("file").unwrap();
+        assert_eq!(file.size, 14);
+        assert_eq!(fs::read_to_string(file.path()).unwrap(), "line 1\r\nline 2");
+        drop(multipart);
+
+        // 文件超过限制时不需要等待整个请求体
+        let small = UploadLimits {
+            max_file_size: 4,
+            ..limits.clone()
+        };
+        let (mut tx, rx) = futures::channel::mpsc::channel(1);
+        let head = MULTIPART.find("line 1").unwrap();
+        tx.try_send(Ok(Bytes::from(&MULTIPART[..head + 14])))
+            .unwrap();
+        req.body_stream = Some(rx);
+        let err = req.multipart(&small).unwrap_err();
+        assert_eq!(err.status(), StatusCode::PayloadTooLarge);
+
+        // 请求体读取失败时返回错误
+        let (mut tx, rx) = futures::channel::mpsc::channel(1);
+        tx.try_send(Err(io::Error::other("aborted"))).unwrap();
+        req.body_stream = Some(rx);
+        assert!(matches!(req.multipart(&limits), Err(BodyError::Io(_))));
+
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_header_param() {
+        let value = "form-data; name=\"a;b\"; filename=x.txt";
+        assert_eq!(header_param(value, "name").as_deref(), Some("a;b"));
+        assert_eq!(header_param(value, "FILENAME").as_deref(), Some("x.txt"));
+        assert_eq!(header_param(value, "size"), None);
+        assert_eq!(
+            header_param("multipart/form-data; boundary=abc", "boundary").as_deref(),
+            Some("abc")
+        );
+    }
+}
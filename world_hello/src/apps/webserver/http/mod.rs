@@ -0,0 +1,20 @@
+mod body;
+mod headers;
+mod request;
+mod response;
+
+pub use body::{
+    BodyError, FilePart, Multipart, UploadLimits, MAX_FIELD_SIZE, MAX_FILE_SIZE, MAX_PARTS,
+    MAX_PART_HEADER_SIZE,
+};
+pub use headers::Headers;
+pub use request::{
+    normalize_path, parse_query, parse_request, parse_request_with, percent_decode, read_request,
+    read_request_async, read_request_head_async, BodyReader, BodyStream, Limits, Method,
+    ReadTimeout, Request, RequestError, RequestParser, Version, BODY_TIMEOUT, HEADER_TIMEOUT,
+    KEEP_ALIVE_TIMEOUT, MAX_BODY_SIZE, MAX_HEADERS, MAX_HEADER_SIZE,
+};
+pub use response::{
+    content_type, format_http_date, http_date, parse_http_date, Body, BodySender, Response,
+    StatusCode,
+};
@@ -0,0 +1,1233 @@
+use crate::apps::webserver::http::{Headers, StatusCode};
+use async_std::future;
+use bytes::Bytes;
+use futures::channel::mpsc;
+use futures::{AsyncRead, AsyncReadExt};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Read};
+use std::net::{SocketAddr, TcpStream};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+// http/1.1 request parser
+// refer: https://www.rfc-editor.org/rfc/rfc9112
+
+/// 请求头（请求行 + headers）的最大字节数
+pub const MAX_HEADER_SIZE: usize = 8 * 1024;
+/// 请求体的最大字节数
+pub const MAX_BODY_SIZE: usize = 1024 * 1024;
+/// 请求头的最大数量
+pub const MAX_HEADERS: usize = 100;
+/// keep-alive 连接等待下一个请求的最长时间
+pub const KEEP_ALIVE_TIMEOUT: Duration = Duration::from_secs(5);
+/// 从收到请求的第一个字节开始，读完请求头的最长时间
+pub const HEADER_TIMEOUT: Duration = Duration::from_secs(10);
+/// 读完请求头之后，读完请求体的最长时间
+pub const BODY_TIMEOUT: Duration = Duration::from_secs(30);
+
+const READ_CHUNK_SIZE: usize = 4096;
+// chunk size 行（包括 chunk extension）的最大字节数
+const MAX_CHUNK_LINE_SIZE: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Method {
+    Get,
+    Head,
+    Post,
+    Put,
+    Delete,
+    Patch,
+    Options,
+    Connect,
+    Trace,
+}
+
+impl Method {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Method::Get => "GET",
+            Method::Head => "HEAD",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+            Method::Delete => "DELETE",
+            Method::Patch => "PATCH",
+            Method::Options => "OPTIONS",
+            Method::Connect => "CONNECT",
+            Method::Trace => "TRACE",
+        }
+    }
+}
+
+impl FromStr for Method {
+    type Err = RequestError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // method 区分大小写
+        let method = match s {
+            "GET" => Method::Get,
+            "HEAD" => Method::Head,
+            "POST" => Method::Post,
+            "PUT" => Method::Put,
+            "DELETE" => Method::Delete,
+            "PATCH" => Method::Patch,
+            "OPTIONS" => Method::Options,
+            "CONNECT" => Method::Connect,
+            "TRACE" => Method::Trace,
+            _ if !s.is_empty() && s.bytes().all(is_token_char) => {
+                return Err(RequestError::NotImplemented("method"))
+            }
+            _ => return Err(RequestError::BadRequest("invalid method")),
+        };
+        Ok(method)
+    }
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Version {
+    Http10,
+    Http11,
+}
+
+impl Version {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Version::Http10 => "HTTP/1.0",
+            Version::Http11 => "HTTP/1.1",
+        }
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// 按到达的顺序产生 decode 后的请求体，读取出错时以错误结束
+pub type BodyStream = mpsc::Receiver<io::Result<Bytes>>;
+
+#[derive(Debug)]
+pub struct Request {
+    pub method: Method,
+    /// 请求路径，不包含 query, 已经由 normalize_path 规范化，未做 percent-decode
+    pub path: String,
+    /// `?` 之后的原始 query string
+    pub query: Option<String>,
+    pub version: Version,
+    pub headers: Headers,
+    pub body: Vec<u8>,
+    /// chunked 编码的 body 之后的 trailer headers
+    pub trailers: Headers,
+    /// 路由匹配得到的路径参数，如 `/users/:id` 中的 id
+    pub params: HashMap<String, String>,
+    /// 客户端地址，由 server 在读取请求后设置
+    pub remote_addr: Option<SocketAddr>,
+    /// 边读边交给 handler 的请求体，此时 body 为空，只用于转发等需要流式处理请求体的路由
+    pub body_stream: Option<BodyStream>,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    /// 请求是否要求保持连接：http/1.1 默认保持，http/1.0 需要 `Connection: keep-alive`
+    pub fn keep_alive(&self) -> bool {
+        let has_token = |token: &str| {
+            self.headers
+                .get_all("connection")
+                .flat_map(|v| v.split(','))
+                .any(|v| v.trim().eq_ignore_ascii_case(token))
+        };
+        match self.version {
+            Version::Http11 => !has_token("close"),
+            Version::Http10 => has_token("keep-alive"),
+        }
+    }
+
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params.get(name).map(|v| v.as_str())
+    }
+
+    /// 解析 query string, 按出现顺序返回 decode 后的 (name, value)
+    pub fn query_params(&self) -> Vec<(String, String)> {
+        self.query.as_deref().map(parse_query).unwrap_or_default()
+    }
+
+    /// 返回第一个同名 query 参数的值
+    pub fn query_param(&self, name: &str) -> Option<String> {
+        self.query_params()
+            .into_iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v)
+    }
+}
+
+/// 解析 `a=1&b=x+y` 格式的字符串，`+` 视为空格
+pub fn parse_query(query: &str) -> Vec<(String, String)> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let decode = |s: &str| percent_decode(&s.replace('+', " "));
+            (decode(name), decode(value))
+        })
+        .collect()
+}
+
+/// percent-decode, 非法的转义序列原样保留
+pub fn percent_decode(s: &str) -> String {
+    String::from_utf8_lossy(&percent_decode_bytes(s)).into_owned()
+}
+
+/// 规范化请求路径：逐段 percent-decode, 去掉空段和 `.`, 再重新编码，结尾的 `/` 保留
+/// `/a/%70`、`//a/p`、`/a/./p` 都规范化为 `/a/p`, 鉴权、路由和静态文件看到的是同一个路径
+/// 路径中有 `..`（包括编码后的 `%2e%2e`）时返回 None
+pub fn normalize_path(path: &str) -> Option<String> {
+    let mut normalized = String::with_capacity(path.len());
+    for segment in path.split('/') {
+        let decoded = percent_decode_bytes(segment);
+        match &decoded[..] {
+            b"" | b"." => continue,
+            b".." => return None,
+            _ => {}
+        }
+        normalized.push('/');
+        for b in decoded {
+            // 保留 pchar 中不需要编码的字符，其他字节（包括 `/` 和 `%`）重新编码
+            // refer: https://www.rfc-editor.org/rfc/rfc3986#section-3.3
+            if b.is_ascii_alphanumeric() || b"-._~!$&'()*+,;=:@".contains(&b) {
+                normalized.push(b as char);
+            } else {
+                normalized.push_str(&format!("%{b:02X}"));
+            }
+        }
+    }
+    if normalized.is_empty() || path.ends_with('/') {
+        normalized.push('/');
+    }
+    Some(normalized)
+}
+
+fn percent_decode_bytes(s: &str) -> Vec<u8> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).unwrap_or_default();
+        if bytes[i] == b'%' && hex.len() == 2 && hex.iter().all(u8::is_ascii_hexdigit) {
+            let hex = std::str::from_utf8(hex).unwrap();
+            out.push(u8::from_str_radix(hex, 16).unwrap());
+            i += 3;
+            continue;
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    out
+}
+
+/// 读取和解析请求时的限制
+/// 分阶段的超时可以防止客户端很慢地发送数据 (slowloris), 长时间占用连接
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    pub max_header_size: usize,
+    pub max_headers: usize,
+    pub max_body_size: usize,
+    pub keep_alive_timeout: Duration,
+    pub header_timeout: Duration,
+    pub body_timeout: Duration,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_header_size: MAX_HEADER_SIZE,
+            max_headers: MAX_HEADERS,
+            max_body_size: MAX_BODY_SIZE,
+            keep_alive_timeout: KEEP_ALIVE_TIMEOUT,
+            header_timeout: HEADER_TIMEOUT,
+            body_timeout: BODY_TIMEOUT,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RequestError {
+    #[error("io error: {0}")]
+    Io(#[from] io::Error),
+    #[error("bad request: {0}")]
+    BadRequest(&'static str),
+    #[error("request timeout")]
+    Timeout,
+    #[error("request header fields too large")]
+    HeaderTooLarge,
+    #[error("payload too large")]
+    PayloadTooLarge,
+    #[error("not implemented: {0}")]
+    NotImplemented(&'static str),
+    #[error("http version not supported")]
+    VersionNotSupported,
+}
+
+impl RequestError {
+    /// 返回应答给客户端的状态码，io 错误时连接已不可用，返回 None
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            RequestError::Io(_) => None,
+            RequestError::BadRequest(_) => Some(StatusCode::BadRequest),
+            RequestError::Timeout => Some(StatusCode::RequestTimeout),
+            RequestError::HeaderTooLarge => Some(StatusCode::RequestHeaderFieldsTooLarge),
+            RequestError::PayloadTooLarge => Some(StatusCode::PayloadTooLarge),
+            RequestError::NotImplemented(_) => Some(StatusCode::NotImplemented),
+            RequestError::VersionNotSupported => Some(StatusCode::HttpVersionNotSupported),
+        }
+    }
+}
+
+/// 从 buf 中解析一个完整的请求，返回请求和消费的字节数；数据不完整时返回 None, 需要继续读取
+pub fn parse_request(buf: &[u8]) -> Result<Option<(Request, usize)>, RequestError> {
+    parse_request_with(buf, &Limits::default())
+}
+
+/// 按 limits 中的大小限制解析请求
+pub fn parse_request_with(
+    buf: &[u8],
+    limits: &Limits,
+) -> Result<Option<(Request, usize)>, RequestError> {
+    RequestParser::new(*limits).parse(buf)
+}
+
+/// 增量解析请求，每次读到新数据后用同一个 buf 调用 parse
+/// 从上次停止的位置继续，已经找到的请求头和已经 decode 的 chunk 不会重复解析
+/// 两次 parse 之间 buf 只能在末尾追加数据，返回请求后可以从 buf 中移除消费的字节
+#[derive(Debug)]
+pub struct RequestParser {
+    limits: Limits,
+    state: ParseState,
+}
+
+#[derive(Debug)]
+enum ParseState {
+    // 已经查找过请求头结束标记的字节数
+    Head {
+        scanned: usize,
+    },
+    // 请求头已经解析，body 从 body_start 开始
+    Body {
+        req: Box<Request>,
+        body_start: usize,
+        framing: BodyFraming,
+    },
+}
+
+#[derive(Debug)]
+enum BodyFraming {
+    Length(usize),
+    Chunked(ChunkedDecoder),
+}
+
+impl RequestParser {
+    pub fn new(limits: Limits) -> Self {
+        RequestParser {
+            limits,
+            state: ParseState::Head { scanned: 0 },
+        }
+    }
+
+    /// 请求头是否已经读完
+    pub fn in_body(&self) -> bool {
+        matches!(self.state, ParseState::Body { .. })
+    }
+
+    /// 返回完整的请求和消费的字节数，之后 parser 可以用于解析下一个请求；数据不完整时返回 None
+    pub fn parse(&mut self, buf: &[u8]) -> Result<Option<(Request, usize)>, RequestError> {
+        if !self.parse_request_head(buf)? {
+            return Ok(None);
+        }
+        let limits = self.limits;
+        let ParseState::Body {
+            req,
+            body_start,
+            framing,
+        } = &mut self.state
+        else {
+            unreachable!("request head is parsed above");
+        };
+        let body_end = match framing {
+            BodyFraming::Length(len) => {
+                let body_end = *body_start + *len;
+                if buf.len() < body_end {
+                    return Ok(None);
+                }
+                req.body = buf[*body_start..body_end].to_vec();
+                body_end
+            }
+            BodyFraming::Chunked(decoder) => {
+                let body = &buf[*body_start..];
+                match decoder.decode(body, &mut req.body, &mut req.trailers, &limits)? {
+                    Some(consumed) => *body_start + consumed,
+                    None => return Ok(None),
+                }
+            }
+        };
+        let ParseState::Body { req, .. } =
+            std::mem::replace(&mut self.state, ParseState::Head { scanned: 0 })
+        else {
+            unreachable!("request body is parsed above");
+        };
+        Ok(Some((*req, body_end)))
+    }
+
+    /// 只解析请求头，返回没有 body 的请求、读取请求体的 BodyReader 和请求头的字节数
+    /// 用于边读边处理请求体；数据不完整时返回 None
+    pub fn parse_head(
+        &mut self,
+        buf: &[u8],
+    ) -> Result<Option<(Request, BodyReader, usize)>, RequestError> {
+        if !self.parse_request_head(buf)? {
+            return Ok(None);
+        }
+        let ParseState::Body {
+            req,
+            body_start,
+            framing,
+        } = std::mem::replace(&mut self.state, ParseState::Head { scanned: 0 })
+        else {
+            unreachable!("request head is parsed above");
+        };
+        Ok(Some((
+            *req,
+            BodyReader::new(framing, self.limits),
+            body_start,
+        )))
+    }
+
+    // 请求头完整时进入 Body 状态并返回 true
+    fn parse_request_head(&mut self, buf: &[u8]) -> Result<bool, RequestError> {
+        let limits = self.limits;
+        if let ParseState::Head { scanned } = &mut self.state {
+            // 结束标记可能跨越上次读到的末尾
+            let from = scanned.saturating_sub(3);
+            let head_end = match find_subslice(&buf[from..], b"\r\n\r\n") {
+                Some(pos) if from + pos <= limits.max_header_size => from + pos,
+                Some(_) => return Err(RequestError::HeaderTooLarge),
+                None if buf.len() > limits.max_header_size => {
+                    return Err(RequestError::HeaderTooLarge)
+                }
+                None => {
+                    *scanned = buf.len();
+                    return Ok(false);
+                }
+            };
+            let req = parse_head(&buf[..head_end], &limits)?;
+            let framing = if is_chunked(&req.headers)? {
+                // 同时存在时无法确定 body 的边界，可能被用于请求走私
+                if req.headers.contains("content-length") {
+                    return Err(RequestError::BadRequest(
+                        "both transfer-encoding and content-length",
+                    ));
+                }
+                BodyFraming::Chunked(ChunkedDecoder::default())
+            } else {
+                let content_length = content_length(&req.headers)?;
+                if content_length > limits.max_body_size {
+                    return Err(RequestError::PayloadTooLarge);
+                }
+                BodyFraming::Length(content_length)
+            };
+            self.state = ParseState::Body {
+                req: Box::new(req),
+                body_start: head_end + 4,
+                framing,
+            };
+        }
+        Ok(true)
+    }
+}
+
+/// 请求头之后还没有读取的请求体，按 framing 逐段 decode
+/// 整个请求体需要在读完请求头之后的 body_timeout 内读完
+#[derive(Debug)]
+pub struct BodyReader {
+    // Length 为剩余的字节数
+    framing: BodyFraming,
+    limits: Limits,
+    trailers: Headers,
+    deadline: Instant,
+}
+
+impl BodyReader {
+    fn new(framing: BodyFraming, limits: Limits) -> Self {
+        BodyReader {
+            framing,
+            limits,
+            trailers: Headers::new(),
+            deadline: Instant::now() + limits.body_timeout,
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        matches!(self.framing, BodyFraming::Length(0))
+    }
+
+    /// chunked 编码的 body 之后的 trailer headers, 读完请求体之后才完整
+    pub fn trailers(&self) -> &Headers {
+        &self.trailers
+    }
+
+    // decode buf 中已有的数据追加到 out, 并从 buf 中移除 decode 过的字节
+    fn decode(&mut self, buf: &mut Vec<u8>, out: &mut Vec<u8>) -> Result<(), RequestError> {
+        match &mut self.framing {
+            BodyFraming::Length(remaining) => {
+                let n = (*remaining).min(buf.len());
+                out.extend(buf.drain(..n));
+                *remaining -= n;
+            }
+            BodyFraming::Chunked(decoder) => {
+                match decoder.decode(buf, out, &mut self.trailers, &self.limits)? {
+                    Some(consumed) => {
+                        buf.drain(..consumed);
+                        self.framing = BodyFraming::Length(0);
+                    }
+                    None => decoder.discard(buf),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// 返回下一段已经收到的请求体，请求体读完时返回 None
+    pub async fn read_async<R: AsyncRead + Unpin>(
+        &mut self,
+        stream: &mut R,
+        buf: &mut Vec<u8>,
+    ) -> Result<Option<Vec<u8>>, RequestError> {
+        let mut chunk = [0u8; READ_CHUNK_SIZE];
+        loop {
+            if self.is_done() {
+                return Ok(None);
+            }
+            let mut out = Vec::new();
+            self.decode(buf, &mut out)?;
+            if !out.is_empty() {
+                return Ok(Some(out));
+            }
+            if self.is_done() {
+                continue;
+            }
+
+            let Some(timeout) = self
+                .deadline
+                .checked_duration_since(Instant::now())
+                .filter(|d| !d.is_zero())
+            else {
+                return Err(RequestError::Timeout);
+            };
+            let n = match future::timeout(timeout, stream.read(&mut chunk)).await {
+                Ok(n) => n?,
+                Err(_) => return Err(RequestError::Timeout),
+            };
+            if n == 0 {
+                let err = io::Error::new(io::ErrorKind::UnexpectedEof, "incomplete request");
+                return Err(RequestError::Io(err));
+            }
+            buf.extend_from_slice(&chunk[..n]);
+        }
+    }
+
+    /// 读取剩余的请求体到 req.body
+    pub async fn read_to_end_async<R: AsyncRead + Unpin>(
+        mut self,
+        stream: &mut R,
+        buf: &mut Vec<u8>,
+        req: &mut Request,
+    ) -> Result<(), RequestError> {
+        while let Some(chunk) = self.read_async(stream, buf).await? {
+            req.body.extend_from_slice(&chunk);
+        }
+        req.trailers = self.trailers;
+        Ok(())
+    }
+}
+
+// 解析请求行和 headers, 返回没有 body 的请求
+fn parse_head(head: &[u8], limits: &Limits) -> Result<Request, RequestError> {
+    let head = std::str::from_utf8(head)
+        .map_err(|_| RequestError::BadRequest("invalid utf-8 in request head"))?;
+
+    let mut lines = head.split("\r\n");
+    let request_line = lines.next().unwrap_or_default();
+    let (method, target, version) = match request_line.split(' ').collect::<Vec<_>>()[..] {
+        [method, target, version] => (method.parse::<Method>()?, target, version),
+        _ => return Err(RequestError::BadRequest("invalid request line")),
+    };
+    let version = parse_version(version)?;
+    if !(target.starts_with('/') || (method == Method::Options && target == "*")) {
+        return Err(RequestError::BadRequest("invalid request target"));
+    }
+    let (path, query) = match target.split_once('?') {
+        Some((path, query)) => (path, Some(query.to_string())),
+        None => (target, None),
+    };
+    let path = match path {
+        "*" => path.to_string(),
+        path => normalize_path(path).ok_or(RequestError::BadRequest("invalid request path"))?,
+    };
+
+    let mut headers = Headers::new();
+    for (i, line) in lines.enumerate() {
+        if i >= limits.max_headers {
+            return Err(RequestError::HeaderTooLarge);
+        }
+        let (name, value) = parse_header_line(line)?;
+        headers.append(name, value);
+    }
+    if version == Version::Http11 && !headers.contains("host") {
+        return Err(RequestError::BadRequest("missing host header"));
+    }
+
+    Ok(Request {
+        method,
+        path,
+        query,
+        version,
+        headers,
+        body: Vec::new(),
+        trailers: Headers::new(),
+        params: HashMap::new(),
+        remote_addr: None,
+        body_stream: None,
+    })
+}
+
+/// 可以设置读超时的 stream, read_request 按剩余时间设置每次 read 的超时
+pub trait ReadTimeout: Read {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()>;
+}
+
+impl ReadTimeout for TcpStream {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+}
+
+// 内存中的数据不会阻塞，用于测试
+impl ReadTimeout for &[u8] {
+    fn set_read_timeout(&mut self, _: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// 从 stream 中读取一个完整的请求，buf 中保留读到的多余数据，供同一连接上的下一个请求使用
+/// 连接在请求开始前被关闭或空闲超时时返回 None, 请求头或请求体没有在限定时间内读完时返回 Timeout
+pub fn read_request<S: ReadTimeout>(
+    stream: &mut S,
+    buf: &mut Vec<u8>,
+    limits: &Limits,
+) -> Result<Option<Request>, RequestError> {
+    let mut chunk = [0u8; READ_CHUNK_SIZE];
+    let mut deadlines = Deadlines::default();
+    let mut parser = RequestParser::new(*limits);
+    loop {
+        if let Some((req, consumed)) = parser.parse(buf)? {
+            buf.drain(..consumed);
+            return Ok(Some(req));
+        }
+
+        let Some(timeout) = deadlines.remaining(buf, parser.in_body(), limits) else {
+            return timed_out(buf);
+        };
+        stream.set_read_timeout(Some(timeout))?;
+        let n = match stream.read(&mut chunk) {
+            Ok(n) => n,
+            Err(err) if is_timeout(&err) => return timed_out(buf),
+            Err(err) => return Err(err.into()),
+        };
+        if n == 0 {
+            return eof(buf);
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+}
+
+/// read_request 的异步版本
+pub async fn read_request_async<R: AsyncRead + Unpin>(
+    stream: &mut R,
+    buf: &mut Vec<u8>,
+    limits: &Limits,
+) -> Result<Option<Request>, RequestError> {
+    let Some((mut req, body)) = read_request_head_async(stream, buf, limits).await? else {
+        return Ok(None);
+    };
+    body.read_to_end_async(stream, buf, &mut req).await?;
+    Ok(Some(req))
+}
+
+/// 只读取请求头，返回没有 body 的请求和读取请求体的 BodyReader, buf 中保留请求头之后已读取的数据
+/// 连接在请求开始前被关闭或空闲超时时返回 None
+pub async fn read_request_head_async<R: AsyncRead + Unpin>(
+    stream: &mut R,
+    buf: &mut Vec<u8>,
+    limits: &Limits,
+) -> Result<Option<(Request, BodyReader)>, RequestError> {
+    let mut chunk = [0u8; READ_CHUNK_SIZE];
+    let mut deadlines = Deadlines::default();
+    let mut parser = RequestParser::new(*limits);
+    loop {
+        if let Some((req, body, consumed)) = parser.parse_head(buf)? {
+            buf.drain(..consumed);
+            return Ok(Some((req, body)));
+        }
+
+        let Some(timeout) = deadlines.remaining(buf, false, limits) else {
+            return timed_out(buf);
+        };
+        let n = match future::timeout(timeout, stream.read(&mut chunk)).await {
+            Ok(n) => n?,
+            Err(_) => return timed_out(buf),
+        };
+        if n == 0 {
+            return eof(buf);
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+}
+
+// 读取一个请求时各阶段的截止时间，在进入该阶段时确定
+#[derive(Default)]
+struct Deadlines {
+    header: Option<Instant>,
+    body: Option<Instant>,
+}
+
+impl Deadlines {
+    // 返回下一次 read 的超时时间，已经超时返回 None
+    fn remaining(&mut self, buf: &[u8], in_body: bool, limits: &Limits) -> Option<Duration> {
+        let now = Instant::now();
+        let deadline = if buf.is_empty() {
+            // 等待请求的第一个字节
+            return Some(limits.keep_alive_timeout);
+        } else if let Some(body) = self.body {
+            body
+        } else if in_body {
+            *self.body.insert(now + limits.body_timeout)
+        } else {
+            *self.header.get_or_insert(now + limits.header_timeout)
+        };
+        deadline
+            .checked_duration_since(now)
+            .filter(|d| !d.is_zero())
+    }
+}
+
+fn is_timeout(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+// 没有收到任何数据时直接关闭空闲连接，否则返回 408
+fn timed_out<T>(buf: &[u8]) -> Result<Option<T>, RequestError> {
+    if buf.is_empty() {
+        Ok(None)
+    } else {
+        Err(RequestError::Timeout)
+    }
+}
+
+fn eof<T>(buf: &[u8]) -> Result<Option<T>, RequestError> {
+    if buf.is_empty() {
+        Ok(None)
+    } else {
+        let err = io::Error::new(io::ErrorKind::UnexpectedEof, "incomplete request");
+        Err(RequestError::Io(err))
+    }
+}
+
+fn parse_version(version: &str) -> Result<Version, RequestError> {
+    match version {
+        "HTTP/1.1" => Ok(Version::Http11),
+        "HTTP/1.0" => Ok(Version::Http10),
+        v if v.starts_with("HTTP/") => Err(RequestError::VersionNotSupported),
+        _ => Err(RequestError::BadRequest("invalid http version")),
+    }
+}
+
+fn content_length(headers: &Headers) -> Result<usize, RequestError> {
+    let mut length = None;
+    for value in headers.get_all("content-length") {
+        if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(RequestError::BadRequest("invalid content-length"));
+        }
+        let value = value
+            .parse::<usize>()
+            .map_err(|_| RequestError::PayloadTooLarge)?;
+        // 多个 content-length 的值必须一致
+        if length.is_some_and(|len| len != value) {
+            return Err(RequestError::BadRequest("conflicting content-length"));
+        }
+        length = Some(value);
+    }
+    Ok(length.unwrap_or(0))
+}
+
+fn parse_header_line(line: &str) -> Result<(&str, &str), RequestError> {
+    let (name, value) = line
+        .split_once(':')
+        .ok_or(RequestError::BadRequest("invalid header line"))?;
+    // header name 与冒号之间不允许有空白，也不支持 obs-fold 折行
+    if name.is_empty() || !name.bytes().all(is_token_char) {
+        return Err(RequestError::BadRequest("invalid header name"));
+    }
+    Ok((name, value.trim_matches(|c| c == ' ' || c == '\t')))
+}
+
+// 只支持 chunked 一种 transfer-coding
+fn is_chunked(headers: &Headers) -> Result<bool, RequestError> {
+    let codings: Vec<&str> = headers
+        .get_all("transfer-encoding")
+        .flat_map(|v| v.split(','))
+        .map(|v| v.trim())
+        .filter(|v| !v.is_empty())
+        .collect();
+    match codings[..] {
+        [] => Ok(false),
+        [coding] if coding.eq_ignore_ascii_case("chunked") => Ok(true),
+        _ => Err(RequestError::NotImplemented("transfer-encoding")),
+    }
+}
+
+/// chunked 编码的 body 的解析状态，pos 为下一个要解析的字节在 body 中的位置
+/// refer: https://www.rfc-editor.org/rfc/rfc9112#section-7.1
+#[derive(Debug, Default)]
+struct ChunkedDecoder {
+    pos: usize,
+    phase: ChunkPhase,
+    trailer_start: usize,
+    // 已经 decode 的 chunk 数据的总字节数
+    decoded: usize,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum ChunkPhase {
+    #[default]
+    Size,
+    // chunk 剩余的数据
+    Data(usize),
+    // chunk 数据之后的 CRLF
+    DataEnd,
+    // 最后一个 chunk 之后是可选的 trailer, 以空行结束
+    Trailers,
+}
+
+impl ChunkedDecoder {
+    /// decode 新收到的数据追加到 out, 完成时返回 body 消费的字节数；数据不完整时返回 None
+    fn decode(
+        &mut self,
+        buf: &[u8],
+        out: &mut Vec<u8>,
+        trailers: &mut Headers,
+        limits: &Limits,
+    ) -> Result<Option<usize>, RequestError> {
+        loop {
+            match self.phase {
+                ChunkPhase::Size => {
+                    let Some(line_end) = find_subslice(&buf[self.pos..], b"\r\n") else {
+                        if buf.len() - self.pos > MAX_CHUNK_LINE_SIZE {
+                            return Err(RequestError::BadRequest("chunk size line too long"));
+                        }
+                        return Ok(None);
+                    };
+                    let line = std::str::from_utf8(&buf[self.pos..self.pos + line_end])
+                        .map_err(|_| RequestError::BadRequest("invalid chunk size"))?;
+                    // 忽略 chunk extension
+                    let size = line.split(';').next().unwrap_or_default().trim();
+                    if size.is_empty() || !size.bytes().all(|b| b.is_ascii_hexdigit()) {
+                        return Err(RequestError::BadRequest("invalid chunk size"));
+                    }
+                    let size = usize::from_str_radix(size, 16)
+                        .map_err(|_| RequestError::PayloadTooLarge)?;
+                    self.pos += line_end + 2;
+                    if size == 0 {
+                        self.phase = ChunkPhase::Trailers;
+                        self.trailer_start = self.pos;
+                        continue;
+                    }
+                    self.decoded = self.decoded.saturating_add(size);
+                    if self.decoded > limits.max_body_size {
+                        return Err(RequestError::PayloadTooLarge);
+                    }
+                    self.phase = ChunkPhase::Data(size);
+                }
+                ChunkPhase::Data(size) => {
+                    // 已经收到的部分先输出，不需要等待整个 chunk
+                    let n = size.min(buf.len() - self.pos);
+                    if n == 0 {
+                        return Ok(None);
+                    }
+                    out.extend_from_slice(&buf[self.pos..self.pos + n]);
+                    self.pos += n;
+                    self.phase = if n == size {
+                        ChunkPhase::DataEnd
+                    } else {
+                        ChunkPhase::Data(size - n)
+                    };
+                }
+                ChunkPhase::DataEnd => {
+                    if buf.len() < self.pos + 2 {
+                        return Ok(None);
+                    }
+                    if &buf[self.pos..self.pos + 2] != b"\r\n" {
+                        return Err(RequestError::BadRequest("invalid chunk data"));
+                    }
+                    self.pos += 2;
+                    self.phase = ChunkPhase::Size;
+                }
+                ChunkPhase::Trailers => {
+                    let Some(line_end) = find_subslice(&buf[self.pos..], b"\r\n") else {
+                        if buf.len() - self.trailer_start > limits.max_header_size {
+                            return Err(RequestError::HeaderTooLarge);
+                        }
+                        return Ok(None);
+                    };
+                    let line = std::str::from_utf8(&buf[self.pos..self.pos + line_end])
+                        .map_err(|_| RequestError::BadRequest("invalid utf-8 in trailer"))?;
+                    self.pos += line_end + 2;
+                    if self.pos - self.trailer_start > limits.max_header_size {
+                        return Err(RequestError::HeaderTooLarge);
+                    }
+                    if line.is_empty() {
+                        return Ok(Some(self.pos));
+                    }
+                    let (name, value) = parse_header_line(line)?;
+                    trailers.append(name, value);
+                }
+            }
+        }
+    }
+
+    // 从 buf 中移除已经 decode 的字节，之后的位置相应前移
+    fn discard(&mut self, buf: &mut Vec<u8>) {
+        buf.drain(..self.pos);
+        self.trailer_start = self.trailer_start.saturating_sub(self.pos);
+        self.pos = 0;
+    }
+}
+
+fn is_token_char(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
+fn find_subslice(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_request() {
+        let buf = b"GET /users?id=1&name=a HTTP/1.1\r\nHost: localhost\r\nX-Empty:\r\nAccept:  text/html \r\n\r\n";
+        let (req, consumed) = parse_request(buf).unwrap().unwrap();
+        assert_eq!(consumed, buf.len());
+        assert_eq!(req.method, Method::Get);
+        assert_eq!(req.path, "/users");
+        assert_eq!(req.query.as_deref(), Some("id=1&name=a"));
+        assert_eq!(req.version, Version::Http11);
+        assert_eq!(req.header("host"), Some("localhost"));
+        assert_eq!(req.header("ACCEPT"), Some("text/html"));
+        assert_eq!(req.header("x-empty"), Some(""));
+        assert!(req.body.is_empty());
+    }
+
+    #[test]
+    fn test_normalize_path() {
+        let cases = [
+            ("/", "/"),
+            ("//", "/"),
+            ("/static/private/s.txt", "/static/private/s.txt"),
+            ("/static/%70rivate/s.txt", "/static/private/s.txt"),
+            ("//static//private/s.txt", "/static/private/s.txt"),
+            ("/static/./private/./s.txt", "/static/private/s.txt"),
+            ("/static/private/", "/static/private/"),
+            ("/a%2fb/c d", "/a%2Fb/c%20d"),
+            ("/%e4%b8%ad", "/%E4%B8%AD"),
+            ("/users/~a:1@b", "/users/~a:1@b"),
+        ];
+        for (path, normalized) in cases {
+            assert_eq!(
+                normalize_path(path).as_deref(),
+                Some(normalized),
+                "path: {path}"
+            );
+        }
+        for path in ["/..", "/a/../b", "/a/%2e%2e/b", "/a/.%2E"] {
+            assert_eq!(normalize_path(path), None, "path: {path}");
+        }
+
+        let req = parse_request(b"GET //a/./%62?x=1 HTTP/1.1\r\nHost: a\r\n\r\n").unwrap();
+        assert_eq!(req.unwrap().0.path, "/a/b");
+        let err = parse_request(b"GET /a/../b HTTP/1.1\r\nHost: a\r\n\r\n").unwrap_err();
+        assert_eq!(err.status(), Some(StatusCode::BadRequest));
+    }
+
+    #[test]
+    fn test_request_keep_alive() {
+        let cases: Vec<(&[u8], bool)> = vec![
+            (b"GET / HTTP/1.1\r\nHost: a\r\n\r\n", true),
+            (
+                b"GET / HTTP/1.1\r\nHost: a\r\nConnection: Close\r\n\r\n",
+                false,
+            ),
+            (b"GET / HTTP/1.0\r\n\r\n", false),
+            (
+                b"GET / HTTP/1.0\r\nConnection: Upgrade, keep-alive\r\n\r\n",
+                true,
+            ),
+        ];
+        for (buf, keep_alive) in cases {
+            let (req, _) = parse_request(buf).unwrap().unwrap();
+            assert_eq!(req.keep_alive(), keep_alive, "request: {:?}", buf);
+        }
+    }
+
+    #[test]
+    fn test_parse_request_incremental() {
+        let buf = b"POST /echo HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\n\r\nhello";
+        for end in 0..buf.len() {
+            assert!(parse_request(&buf[..end]).unwrap().is_none());
+        }
+
+        // 多个请求连续发送时，只消费第一个请求
+        let mut pipelined = buf.to_vec();
+        pipelined.extend_from_slice(b"GET / HTTP/1.0\r\n\r\n");
+        let (req, consumed) = parse_request(&pipelined).unwrap().unwrap();
+        assert_eq!(req.body, b"hello");
+        assert_eq!(consumed, buf.len());
+
+        let mut stream = &pipelined[..];
+        let mut read_buf = Vec::new();
+        let req = read_request(&mut stream, &mut read_buf, &Limits::default())
+            .unwrap()
+            .unwrap();
+        assert_eq!(req.method, Method::Post);
+        let req = read_request(&mut stream, &mut read_buf, &Limits::default())
+            .unwrap()
+            .unwrap();
+        assert_eq!(req.version, Version::Http10);
+        assert!(read_request(&mut stream, &mut read_buf, &Limits::default())
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_parse_request_chunked() {
+        let buf = b"POST /upload HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n\
+            5;ext=1\r\nhello\r\n7\r\n, world\r\n0\r\nX-Checksum: abc\r\n\r\nGET /";
+        let (req, consumed) = parse_request(buf).unwrap().unwrap();
+        assert_eq!(req.body, b"hello, world");
+        assert_eq!(req.trailers.get("x-checksum"), Some("abc"));
+        assert_eq!(&buf[consumed..], b"GET /");
+
+        // 数据不完整时需要继续读取
+        for end in [60, 70, 80, buf.len() - 10] {
+            assert!(parse_request(&buf[..end]).unwrap().is_none(), "end: {end}");
+        }
+
+        let head = "POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n";
+        let cases = [
+            ("zz\r\n", StatusCode::BadRequest),
+            ("3\r\nabcd\r\n", StatusCode::BadRequest),
+            ("100001\r\n", StatusCode::PayloadTooLarge),
+            ("ffffffffffffffffffff\r\n", StatusCode::PayloadTooLarge),
+        ];
+        for (body, status) in cases {
+            let buf = format!("{head}{body}");
+            let err = parse_request(buf.as_bytes()).unwrap_err();
+            assert_eq!(err.status(), Some(status), "body: {body:?}");
+        }
+
+        let buf = b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\nContent-Length: 3\r\n\r\n";
+        let err = parse_request(buf).unwrap_err();
+        assert_eq!(err.status(), Some(StatusCode::BadRequest));
+        let buf = b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: gzip, chunked\r\n\r\n";
+        let err = parse_request(buf).unwrap_err();
+        assert_eq!(err.status(), Some(StatusCode::NotImplemented));
+    }
+
+    #[async_std::test]
+    async fn test_body_reader() {
+        let head = b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhel";
+        let mut parser = RequestParser::new(Limits::default());
+        assert!(parser.parse_head(&head[..20]).unwrap().is_none());
+        let (req, mut body, consumed) = parser.parse_head(head).unwrap().unwrap();
+        assert!(req.body.is_empty());
+        let mut buf = head[consumed..].to_vec();
+
+        // 已经收到的部分 chunk 先返回
+        let mut stream: &[u8] = b"lo\r\n3\r\n, w\r\n0\r\nX-Checksum: abc\r\n\r\nGET /";
+        assert_eq!(
+            body.read_async(&mut stream, &mut buf).await.unwrap(),
+            Some(b"hel".to_vec())
+        );
+        let mut data = Vec::new();
+        while let Some(chunk) = body.read_async(&mut stream, &mut buf).await.unwrap() {
+            data.extend(chunk);
+        }
+        assert_eq!(data, b"lo, w");
+        assert!(body.is_done());
+        assert_eq!(body.trailers().get("x-checksum"), Some("abc"));
+        assert_eq!(buf, b"GET /");
+
+        // 连接在请求体读完之前关闭
+        let head = b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 10\r\n\r\nabc";
+        let (_, mut body, consumed) = parser.parse_head(head).unwrap().unwrap();
+        let mut buf = head[consumed..].to_vec();
+        let mut stream: &[u8] = b"de";
+        assert_eq!(
+            body.read_async(&mut stream, &mut buf).await.unwrap(),
+            Some(b"abc".to_vec())
+        );
+        assert_eq!(
+            body.read_async(&mut stream, &mut buf).await.unwrap(),
+            Some(b"de".to_vec())
+        );
+        let err = body.read_async(&mut stream, &mut buf).await.unwrap_err();
+        assert!(matches!(err, RequestError::Io(_)));
+    }
+
+    #[test]
+    fn test_request_parser_resume() {
+        let buf = b"POST /upload HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n\
+            5\r\nhello\r\n7\r\n, world\r\n0\r\nX-Checksum: abc\r\n\r\nGET /";
+        let head_end = find_subslice(buf, b"\r\n\r\n").unwrap() + 4;
+
+        // 每次只多收到一个字节，解析从上次停止的位置继续
+        let mut parser = RequestParser::new(Limits::default());
+        let mut result = None;
+        for end in 1..=buf.len() {
+            result = parser.parse(&buf[..end]).unwrap();
+            if result.is_some() {
+                break;
+            }
+            assert_eq!(parser.in_body(), end >= head_end, "end: {end}");
+            // 第一个 chunk 完整之后就已经 decode, 不需要等整个 body
+            if end == head_end + 10 {
+                let ParseState::Body {
+                    req,
+                    framing: BodyFraming::Chunked(decoder),
+                    ..
+                } = &parser.state
+                else {
+                    panic!("expect chunked body");
+                };
+                assert_eq!(req.body, b"hello");
+                assert_eq!((decoder.pos, decoder.phase), (10, ChunkPhase::Size));
+            }
+        }
+        let (req, consumed) = result.unwrap();
+        assert_eq!(req.body, b"hello, world");
+        assert_eq!(req.trailers.get("x-checksum"), Some("abc"));
+        assert_eq!(&buf[consumed..], b"GET /");
+        // 返回请求后 parser 可以继续解析下一个请求
+        assert!(!parser.in_body());
+        let next = b"GET / HTTP/1.0\r\n\r\n";
+        assert_eq!(parser.parse(next).unwrap().unwrap().1, next.len());
+    }
+
+    #[test]
+    fn test_parse_request_errors() {
+        let cases: Vec<(&[u8], StatusCode)> = vec![
+            (b"GET /\r\n\r\n", StatusCode::BadRequest),
+            (b"GET / HTTP/1.1\r\n\r\n", StatusCode::BadRequest),
+            (
+                b"GET / HTTP/1.1\r\nHost : a\r\n\r\n",
+                StatusCode::BadRequest,
+            ),
+            (b"GET x HTTP/1.1\r\nHost: a\r\n\r\n", StatusCode::BadRequest),
+            (
+                b"GET / HTTP/1.1\r\nHost: a\r\nContent-Length: -1\r\n\r\n",
+                StatusCode::BadRequest,
+            ),
+            (
+                b"BREW / HTTP/1.1\r\nHost: a\r\n\r\n",
+                StatusCode::NotImplemented,
+            ),
+            (
+                b"GET / HTTP/2.0\r\nHost: a\r\n\r\n",
+                StatusCode::HttpVersionNotSupported,
+            ),
+        ];
+        for (buf, status) in cases {
+            let err = parse_request(buf).unwrap_err();
+            assert_eq!(err.status(), Some(status), "request: {:?}", buf);
+        }
+
+        let mut buf = b"GET / HTTP/1.1\r\nX-Large: ".to_vec();
+        buf.extend(vec![b'a'; MAX_HEADER_SIZE]);
+        assert!(matches!(
+            parse_request(&buf),
+            Err(RequestError::HeaderTooLarge)
+        ));
+
+        let mut stream = &b"GET / HTTP/1.1\r\nHost"[..];
+        let err = read_request(&mut stream, &mut Vec::new(), &Limits::default()).unwrap_err();
+        assert_eq!(err.status(), None);
+    }
+
+    #[test]
+    fn test_read_request_limits() {
+        let limits = Limits {
+            max_headers: 2,
+            max_body_size: 4,
+            keep_alive_timeout: Duration::from_millis(100),
+            header_timeout: Duration::from_millis(100),
+            body_timeout: Duration::from_millis(100),
+            ..Limits::default()
+        };
+        let buf = b"GET / HTTP/1.1\r\nHost: a\r\nA: 1\r\n\r\n";
+        assert!(parse_request_with(buf, &limits).unwrap().is_some());
+        let buf = b"GET / HTTP/1.1\r\nHost: a\r\nA: 1\r\nB: 2\r\n\r\n";
+        let err = parse_request_with(buf, &limits).unwrap_err();
+        assert_eq!(err.status(), Some(StatusCode::RequestHeaderFieldsTooLarge));
+        let buf = b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\n\r\nhello";
+        let err = parse_request_with(buf, &limits).unwrap_err();
+        assert_eq!(err.status(), Some(StatusCode::PayloadTooLarge));
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let cases: [&[u8]; 3] = [
+            // 空闲连接直接关闭
+            b"",
+            // 请求头没有在限定时间内读完
+            b"GET / HTTP/1.1\r\nHost: a\r\n",
+            // 请求体没有在限定时间内读完
+            b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 4\r\n\r\nab",
+        ];
+        for data in cases {
+            let mut client = TcpStream::connect(addr).unwrap();
+            std::io::Write::write_all(&mut client, data).unwrap();
+            let (mut stream, _) = listener.accept().unwrap();
+            let result = read_request(&mut stream, &mut Vec::new(), &limits);
+            if data.is_empty() {
+                assert!(result.unwrap().is_none());
+            } else {
+                let err = result.unwrap_err();
+                assert_eq!(err.status(), Some(StatusCode::RequestTimeout));
+            }
+        }
+    }
+}
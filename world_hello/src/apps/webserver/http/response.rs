@@ -0,0 +1,656 @@
+use crate::apps::webserver::http::{Headers, Version};
+use bytes::Bytes;
+use futures::channel::mpsc;
+use futures::stream::{self, BoxStream, StreamExt};
+use futures::{AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt, SinkExt};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::task::Poll;
+use std::time::SystemTime;
+
+// http/1.1 response builder and serializer
+
+const WRITE_CHUNK_SIZE: usize = 8 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StatusCode {
+    SwitchingProtocols,
+    Ok,
+    Created,
+    NoContent,
+    PartialContent,
+    MovedPermanently,
+    Found,
+    NotModified,
+    BadRequest,
+    Unauthorized,
+    Forbidden,
+    NotFound,
+    MethodNotAllowed,
+    RequestTimeout,
+    PayloadTooLarge,
+    UnsupportedMediaType,
+    RangeNotSatisfiable,
+    UpgradeRequired,
+    TooManyRequests,
+    RequestHeaderFieldsTooLarge,
+    InternalServerError,
+    NotImplemented,
+    BadGateway,
+    ServiceUnavailable,
+    GatewayTimeout,
+    HttpVersionNotSupported,
+    /// 其他状态码，如反向代理从 upstream 收到的响应
+    Other(u16),
+}
+
+// 除 Other 之外的所有状态码
+const KNOWN_STATUS_CODES: [StatusCode; 26] = [
+    StatusCode::SwitchingProtocols,
+    StatusCode::Ok,
+    StatusCode::Created,
+    StatusCode::NoContent,
+    StatusCode::PartialContent,
+    StatusCode::MovedPermanently,
+    StatusCode::Found,
+    StatusCode::NotModified,
+    StatusCode::BadRequest,
+    StatusCode::Unauthorized,
+    StatusCode::Forbidden,
+    StatusCode::NotFound,
+    StatusCode::MethodNotAllowed,
+    StatusCode::RequestTimeout,
+    StatusCode::PayloadTooLarge,
+    StatusCode::UnsupportedMediaType,
+    StatusCode::RangeNotSatisfiable,
+    StatusCode::UpgradeRequired,
+    StatusCode::TooManyRequests,
+    StatusCode::RequestHeaderFieldsTooLarge,
+    StatusCode::InternalServerError,
+    StatusCode::NotImplemented,
+    StatusCode::BadGateway,
+    StatusCode::ServiceUnavailable,
+    StatusCode::GatewayTimeout,
+    StatusCode::HttpVersionNotSupported,
+];
+
+impl StatusCode {
+    pub fn as_u16(&self) -> u16 {
+        match self {
+            StatusCode::SwitchingProtocols => 101,
+            StatusCode::Ok => 200,
+            StatusCode::Created => 201,
+            StatusCode::NoContent => 204,
+            StatusCode::PartialContent => 206,
+            StatusCode::MovedPermanently => 301,
+            StatusCode::Found => 302,
+            StatusCode::NotModified => 304,
+            StatusCode::BadRequest => 400,
+            StatusCode::Unauthorized => 401,
+            StatusCode::Forbidden => 403,
+            StatusCode::NotFound => 404,
+            StatusCode::MethodNotAllowed => 405,
+            StatusCode::RequestTimeout => 408,
+            StatusCode::PayloadTooLarge => 413,
+            StatusCode::UnsupportedMediaType => 415,
+            StatusCode::RangeNotSatisfiable => 416,
+            StatusCode::UpgradeRequired => 426,
+            StatusCode::TooManyRequests => 429,
+            StatusCode::RequestHeaderFieldsTooLarge => 431,
+            StatusCode::InternalServerError => 500,
+            StatusCode::NotImplemented => 501,
+            StatusCode::BadGateway => 502,
+            StatusCode::ServiceUnavailable => 503,
+            StatusCode::GatewayTimeout => 504,
+            StatusCode::HttpVersionNotSupported => 505,
+            StatusCode::Other(code) => *code,
+        }
+    }
+
+    /// 不在枚举中的状态码返回 StatusCode::Other
+    pub fn from_u16(code: u16) -> StatusCode {
+        KNOWN_STATUS_CODES
+            .iter()
+            .copied()
+            .find(|status| status.as_u16() == code)
+            .unwrap_or(StatusCode::Other(code))
+    }
+
+    pub fn reason_phrase(&self) -> &'static str {
+        match self {
+            StatusCode::SwitchingProtocols => "Switching Protocols",
+            StatusCode::Ok => "OK",
+            StatusCode::Created => "Created",
+            StatusCode::NoContent => "No Content",
+            StatusCode::PartialContent => "Partial Content",
+            StatusCode::MovedPermanently => "Moved Permanently",
+            StatusCode::Found => "Found",
+            StatusCode::NotModified => "Not Modified",
+            StatusCode::BadRequest => "Bad Request",
+            StatusCode::Unauthorized => "Unauthorized",
+            StatusCode::Forbidden => "Forbidden",
+            StatusCode::NotFound => "Not Found",
+            StatusCode::MethodNotAllowed => "Method Not Allowed",
+            StatusCode::RequestTimeout => "Request Timeout",
+            StatusCode::PayloadTooLarge => "Payload Too Large",
+            StatusCode::UnsupportedMediaType => "Unsupported Media Type",
+            StatusCode::RangeNotSatisfiable => "Range Not Satisfiable",
+            StatusCode::UpgradeRequired => "Upgrade Required",
+            StatusCode::TooManyRequests => "Too Many Requests",
+            StatusCode::RequestHeaderFieldsTooLarge => "Request Header Fields Too Large",
+            StatusCode::InternalServerError => "Internal Server Error",
+            StatusCode::NotImplemented => "Not Implemented",
+            StatusCode::BadGateway => "Bad Gateway",
+            StatusCode::ServiceUnavailable => "Service Unavailable",
+            StatusCode::GatewayTimeout => "Gateway Timeout",
+            StatusCode::HttpVersionNotSupported => "HTTP Version Not Supported",
+            // reason phrase 可以为空
+            StatusCode::Other(_) => "",
+        }
+    }
+
+    /// 1xx, 204, 304 的响应不能包含 body
+    pub fn allows_body(&self) -> bool {
+        let code = self.as_u16();
+        !((100..200).contains(&code) || code == 204 || code == 304)
+    }
+}
+
+impl fmt::Display for StatusCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.as_u16(), self.reason_phrase())
+    }
+}
+
+pub enum Body {
+    Empty,
+    Bytes(Bytes),
+    /// 从文件的 offset 处读取 len 个字节
+    File {
+        file: File,
+        offset: u64,
+        len: u64,
+    },
+    /// 长度未知的 body, 按 stream 产生的数据依次写出
+    Stream(BoxStream<'static, io::Result<Bytes>>),
+}
+
+impl Body {
+    /// 返回 body 的长度，stream 的长度未知时返回 None
+    pub fn len(&self) -> Option<u64> {
+        match self {
+            Body::Empty => Some(0),
+            Body::Bytes(bytes) => Some(bytes.len() as u64),
+            Body::File { len, .. } => Some(*len),
+            Body::Stream(_) => None,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == Some(0)
+    }
+
+    /// 创建一个 stream body 和对应的 sender, handler 可以在其他线程或任务中通过 sender 逐步写出数据
+    /// sender 被 drop 后 body 结束；capacity 为缓存的 chunk 数量，写满后 send 会等待
+    pub fn channel(capacity: usize) -> (BodySender, Body) {
+        let (tx, rx) = mpsc::channel(capacity);
+        let error = Arc::new(Mutex::new(None));
+        // abort 的错误不经过 channel, 缓存满时也不会丢失；channel 中的数据读完后以该错误结束
+        let aborted = Arc::clone(&error);
+        let end = stream::poll_fn(move |_| Poll::Ready(aborted.lock().unwrap().take().map(Err)));
+        (
+            BodySender { tx, error },
+            Body::Stream(rx.chain(end).boxed()),
+        )
+    }
+}
+
+pub struct BodySender {
+    tx: mpsc::Sender<io::Result<Bytes>>,
+    error: Arc<Mutex<Option<io::Error>>>,
+}
+
+impl BodySender {
+    /// 连接已关闭（body 被 drop）时返回 BrokenPipe 错误
+    pub async fn send(&mut self, data: impl Into<Bytes>) -> io::Result<()> {
+        self.tx
+            .send(Ok(data.into()))
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "response body closed"))
+    }
+
+    /// send 的阻塞版本，用于同步的 handler
+    pub fn send_blocking(&mut self, data: impl Into<Bytes>) -> io::Result<()> {
+        futures::executor::block_on(self.send(data))
+    }
+
+    /// 以错误结束 body, 连接会被中断，客户端可以据此知道响应不完整
+    pub fn abort(self, err: io::Error) {
+        *self.error.lock().unwrap() = Some(err);
+    }
+}
+
+impl fmt::Debug for Body {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Body::Empty => write!(f, "Body::Empty"),
+            Body::Bytes(bytes) => write!(f, "Body::Bytes({} bytes)", bytes.len()),
+            Body::File { len, .. } => write!(f, "Body::File({} bytes)", len),
+            Body::Stream(_) => write!(f, "Body::Stream"),
+        }
+    }
+}
+
+impl From<String> for Body {
+    fn from(s: String) -> Self {
+        Body::Bytes(Bytes::from(s))
+    }
+}
+
+impl From<&'static str> for Body {
+    fn from(s: &'static str) -> Self {
+        Body::Bytes(Bytes::from(s))
+    }
+}
+
+impl From<Vec<u8>> for Body {
+    fn from(v: Vec<u8>) -> Self {
+        Body::Bytes(Bytes::from(v))
+    }
+}
+
+impl From<Bytes> for Body {
+    fn from(bytes: Bytes) -> Self {
+        Body::Bytes(bytes)
+    }
+}
+
+#[derive(Debug)]
+pub struct Response {
+    pub status: StatusCode,
+    pub headers: Headers,
+    pub body: Body,
+    // HEAD 请求的响应只发送 headers, Content-Length 仍按 body 计算
+    head_only: bool,
+    // 长度未知的 body 是否使用 chunked 编码，http/1.0 的客户端不支持
+    chunked: bool,
+}
+
+impl Response {
+    pub fn new(status: StatusCode) -> Self {
+        Response {
+            status,
+            headers: Headers::new(),
+            body: Body::Empty,
+            head_only: false,
+            chunked: true,
+        }
+    }
+
+    pub fn html(status: StatusCode, contents: impl Into<Body>) -> Self {
+        Response::new(status)
+            .with_header("Content-Type", "text/html; charset=utf-8")
+            .with_body(contents)
+    }
+
+    pub fn text(status: StatusCode, contents: impl Into<Body>) -> Self {
+        Response::new(status)
+            .with_header("Content-Type", "text/plain; charset=utf-8")
+            .with_body(contents)
+    }
+
+    /// 把 value 序列化为 json 作为 body
+    pub fn json<T: serde::Serialize>(status: StatusCode, value: &T) -> Self {
+        match serde_json::to_vec(value) {
+            Ok(body) => Response::new(status)
+                .with_header("Content-Type", "application/json")
+                .with_body(body),
+            Err(err) => Response::text(StatusCode::InternalServerError, err.to_string()),
+        }
+    }
+
+    /// 以文件内容作为 body, Content-Type 由文件扩展名决定
+    pub fn file(status: StatusCode, path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let file = File::open(path)?;
+        let len = file.metadata()?.len();
+        Ok(Response::new(status)
+            .with_header("Content-Type", content_type(path))
+            .with_body(Body::File {
+                file,
+                offset: 0,
+                len,
+            }))
+    }
+
+    pub fn stream(status: StatusCode, stream: BoxStream<'static, io::Result<Bytes>>) -> Self {
+        Response::new(status).with_body(Body::Stream(stream))
+    }
+
+    /// 设置 header, 覆盖已有的同名 header
+    pub fn with_header(mut self, name: &str, value: impl Into<String>) -> Self {
+        self.headers.set(name, value);
+        self
+    }
+
+    pub fn with_body(mut self, body: impl Into<Body>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    /// 根据请求是否要求保持连接设置 Connection header, 返回写完响应后连接能否继续使用
+    /// 长度未知的 body 对 http/1.1 使用 chunked 编码，对 http/1.0 只能通过关闭连接标识结束
+    pub fn keep_alive(&mut self, version: Version, requested: bool) -> bool {
+        self.chunked = version == Version::Http11;
+        let close = self
+            .headers
+            .get("connection")
+            .is_some_and(|v| v.eq_ignore_ascii_case("close"));
+        let keep_alive = requested && !close && (self.body.len().is_some() || self.chunked);
+        let value = if keep_alive { "keep-alive" } else { "close" };
+        self.headers.set("Connection", value);
+        keep_alive
+    }
+
+    /// 用于 HEAD 请求，序列化时不写出 body
+    pub fn without_body(mut self) -> Self {
+        self.head_only = true;
+        self
+    }
+
+    /// 生成状态行和 headers, 并补充 Date, Content-Length, Content-Type
+    fn head(&mut self) -> String {
+        if !self.status.allows_body() {
+            self.body = Body::Empty;
+        }
+        if !self.headers.contains("date") {
+            self.headers.set("Date", http_date());
+        }
+        match self.body.len() {
+            // HEAD 请求的响应可以没有 body, 但保留 handler 设置的长度，如代理转发的响应
+            Some(0) if self.head_only && self.headers.contains("content-length") => {}
+            Some(len) if self.status.allows_body() => {
+                self.headers.set("Content-Length", len.to_string());
+            }
+            Some(_) => {}
+            None if self.chunked => {
+                self.headers.remove("content-length");
+                self.headers.set("Transfer-Encoding", "chunked");
+            }
+            // 长度未知且不能使用 chunked 时，通过关闭连接标识 body 结束
+            None => {
+                self.headers.remove("content-length");
+                self.headers.set("Connection", "close");
+            }
+        }
+        if !self.body.is_empty() && !self.headers.contains("content-type") {
+            self.headers.set("Content-Type", "application/octet-stream");
+        }
+
+        let mut head = format!("HTTP/1.1 {}\r\n", self.status);
+        for (name, value) in self.headers.iter() {
+            head.push_str(&format!("{name}: {value}\r\n"));
+        }
+        head.push_str("\r\n");
+        head
+    }
+
+    pub fn write_to<W: Write>(mut self, w: &mut W) -> io::Result<()> {
+        let head = self.head();
+        w.write_all(head.as_bytes())?;
+        if self.head_only {
+            return w.flush();
+        }
+        match self.body {
+            Body::Empty => {}
+            Body::Bytes(bytes) => w.write_all(&bytes)?,
+            Body::File {
+                mut file,
+                offset,
+                len,
+            } => {
+                file.seek(SeekFrom::Start(offset))?;
+                io::copy(&mut file.take(len), w)?;
+            }
+            Body::Stream(stream) => {
+                for chunk in futures::executor::block_on_stream(stream) {
+                    let chunk = chunk?;
+                    if !self.chunked {
+                        w.write_all(&chunk)?;
+                    } else if !chunk.is_empty() {
+                        // 空的 chunk 表示 body 结束，跳过
+                        w.write_all(format!("{:x}\r\n", chunk.len()).as_bytes())?;
+                        w.write_all(&chunk)?;
+                        w.write_all(b"\r\n")?;
+                    }
+                    // 每个 chunk 都立即发送，客户端可以逐步收到输出
+                    w.flush()?;
+                }
+                if self.chunked {
+                    w.write_all(b"0\r\n\r\n")?;
+                }
+            }
+        }
+        w.flush()
+    }
+
+    pub async fn write_to_async<W: AsyncWrite + Unpin>(mut self, w: &mut W) -> io::Result<()> {
+        let head = self.head();
+        w.write_all(head.as_bytes()).await?;
+        if self.head_only {
+            return w.flush().await;
+        }
+        match self.body {
+            Body::Empty => {}
+            Body::Bytes(bytes) => w.write_all(&bytes).await?,
+            Body::File { file, offset, len } => {
+                // async_std 的 File 在阻塞线程中读文件，不会阻塞 executor
+                let mut file = async_std::fs::File::from(file);
+                file.seek(SeekFrom::Start(offset)).await?;
+                let mut file = file.take(len);
+                let mut buf = vec![0u8; WRITE_CHUNK_SIZE];
+                loop {
+                    let n = file.read(&mut buf).await?;
+                    if n == 0 {
+                        break;
+                    }
+                    w.write_all(&buf[..n]).await?;
+                }
+            }
+            Body::Stream(mut stream) => {
+                while let Some(chunk) = stream.next().await {
+                    let chunk = chunk?;
+                    if !self.chunked {
+                        w.write_all(&chunk).await?;
+                    } else if !chunk.is_empty() {
+                        w.write_all(format!("{:x}\r\n", chunk.len()).as_bytes())
+                            .await?;
+                        w.write_all(&chunk).await?;
+                        w.write_all(b"\r\n").await?;
+                    }
+                    w.flush().await?;
+                }
+                if self.chunked {
+                    w.write_all(b"0\r\n\r\n").await?;
+                }
+            }
+        }
+        w.flush().await
+    }
+}
+
+/// 根据文件扩展名返回 Content-Type
+pub fn content_type(path: &Path) -> &'static str {
+    let ext = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase());
+    match ext.as_deref() {
+        Some("html" | "htm") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js" | "mjs") => "text/javascript; charset=utf-8",
+        Some("json") => "application/json",
+        Some("txt") => "text/plain; charset=utf-8",
+        Some("xml") => "application/xml",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("svg") => "image/svg+xml",
+        Some("ico") => "image/x-icon",
+        Some("webp") => "image/webp",
+        Some("wasm") => "application/wasm",
+        Some("pdf") => "application/pdf",
+        Some("zip") => "application/zip",
+        Some("gz") => "application/gzip",
+        Some("mp4") => "video/mp4",
+        Some("mp3") => "audio/mpeg",
+        Some("woff") => "font/woff",
+        Some("woff2") => "font/woff2",
+        _ => "application/octet-stream",
+    }
+}
+
+/// 当前时间的 http-date 格式，如 Sun, 06 Nov 1994 08:49:37 GMT
+pub fn http_date() -> String {
+    format_http_date(SystemTime::now())
+}
+
+pub fn format_http_date(time: SystemTime) -> String {
+    chrono::DateTime::<chrono::Utc>::from(time)
+        .format("%a, %d %b %Y %H:%M:%S GMT")
+        .to_string()
+}
+
+/// 解析 http-date, 只支持 RFC 1123 格式
+pub fn parse_http_date(s: &str) -> Option<SystemTime> {
+    chrono::DateTime::parse_from_rfc2822(s)
+        .ok()
+        .map(SystemTime::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn serialize(resp: Response) -> String {
+        let mut out = Vec::new();
+        resp.write_to(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_response_serialize() {
+        let resp = Response::html(StatusCode::Ok, "<h1>hi</h1>").with_header("X-Id", "1");
+        let out = serialize(resp);
+        assert!(out.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(out.contains("\r\nContent-Type: text/html; charset=utf-8\r\n"));
+        assert!(out.contains("\r\nContent-Length: 11\r\n"));
+        assert!(out.contains("\r\nX-Id: 1\r\n"));
+        assert!(out.contains("\r\nDate: "));
+        assert!(out.ends_with("\r\n\r\n<h1>hi</h1>"));
+
+        let out = serialize(Response::text(StatusCode::Ok, "hello").without_body());
+        assert!(out.contains("\r\nContent-Length: 5\r\n"));
+        assert!(out.ends_with("\r\n\r\n"));
+
+        let out = serialize(Response::new(StatusCode::NotModified).with_body("ignored"));
+        assert!(out.starts_with("HTTP/1.1 304 Not Modified\r\n"));
+        assert!(!out.contains("Content-Length"));
+        assert!(out.ends_with("\r\n\r\n"));
+    }
+
+    #[test]
+    fn test_response_stream() {
+        let chunks = || {
+            let chunks = vec![
+                Ok(Bytes::from("hello ")),
+                Ok(Bytes::new()),
+                Ok(Bytes::from("world")),
+            ];
+            futures::stream::iter(chunks).boxed()
+        };
+
+        // http/1.1 使用 chunked 编码
+        let mut resp = Response::stream(StatusCode::Ok, chunks());
+        assert!(resp.keep_alive(Version::Http11, true));
+        let out = serialize(resp);
+        assert!(out.contains("\r\nTransfer-Encoding: chunked\r\n"));
+        assert!(out.contains("\r\nConnection: keep-alive\r\n"));
+        assert!(out.contains("\r\nContent-Type: application/octet-stream\r\n"));
+        assert!(!out.contains("Content-Length"));
+        assert!(out.ends_with("\r\n\r\n6\r\nhello \r\n5\r\nworld\r\n0\r\n\r\n"));
+
+        // http/1.0 通过关闭连接标识 body 结束
+        let mut resp = Response::stream(StatusCode::Ok, chunks());
+        assert!(!resp.keep_alive(Version::Http10, true));
+        let out = serialize(resp);
+        assert!(out.contains("\r\nConnection: close\r\n"));
+        assert!(!out.contains("Transfer-Encoding"));
+        assert!(out.ends_with("\r\n\r\nhello world"));
+    }
+
+    #[test]
+    fn test_body_channel() {
+        let (mut sender, body) = Body::channel(1);
+        let producer = std::thread::spawn(move || {
+            for i in 0..3 {
+                sender.send_blocking(format!("line {i}\n")).unwrap();
+            }
+        });
+        let out = serialize(Response::new(StatusCode::Ok).with_body(body));
+        producer.join().unwrap();
+        assert!(out.ends_with("7\r\nline 0\n\r\n7\r\nline 1\n\r\n7\r\nline 2\n\r\n0\r\n\r\n"));
+
+        let (mut sender, body) = Body::channel(1);
+        drop(body);
+        assert!(sender.send_blocking("closed").is_err());
+
+        // 缓存已满时 abort, body 仍然以错误结束
+        let (mut sender, body) = Body::channel(1);
+        let mut sent = 0;
+        while sender.tx.try_send(Ok(Bytes::from("x"))).is_ok() {
+            sent += 1;
+        }
+        sender.abort(io::Error::other("aborted"));
+        let Body::Stream(stream) = body else {
+            panic!("expect stream body");
+        };
+        let chunks: Vec<_> = futures::executor::block_on_stream(stream).collect();
+        assert_eq!(chunks.len(), sent + 1);
+        assert!(chunks[..sent].iter().all(|c| c.is_ok()));
+        assert_eq!(chunks[sent].as_ref().unwrap_err().to_string(), "aborted");
+    }
+
+    #[async_std::test]
+    async fn test_write_file_async() {
+        let path = std::env::temp_dir().join(format!("response_file_{}.txt", std::process::id()));
+        std::fs::write(&path, "0123456789").unwrap();
+        let resp = Response::new(StatusCode::PartialContent).with_body(Body::File {
+            file: File::open(&path).unwrap(),
+            offset: 2,
+            len: 5,
+        });
+        let mut out = Vec::new();
+        resp.write_to_async(&mut out).await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("\r\nContent-Length: 5\r\n"));
+        assert!(out.ends_with("\r\n\r\n23456"));
+    }
+
+    #[test]
+    fn test_content_type() {
+        assert_eq!(
+            content_type(Path::new("/tmp/a.HTML")),
+            "text/html; charset=utf-8"
+        );
+        assert_eq!(content_type(Path::new("a.png")), "image/png");
+        assert_eq!(
+            content_type(Path::new("Makefile")),
+            "application/octet-stream"
+        );
+    }
+}
@@ -0,0 +1,485 @@
+use crate::apps::webserver::pool::{Job, PoolError, ThreadPool};
+use std::{
+    any::Any,
+    future::Future,
+    marker::PhantomData,
+    panic::{self, AssertUnwindSafe},
+    pin::Pin,
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    task::{Context, Poll, Waker},
+    time::{Duration, Instant},
+};
+
+// 提交到线程池并返回结果的任务
+// JobHandle 可以阻塞等待 (join / join_timeout), 也可以作为 Future 在 async 代码中 await
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum JobError {
+    #[error("job panicked: {0}")]
+    Panicked(String),
+    /// 任务在执行前被取消，或者被线程池丢弃（如 QueuePolicy::DropOldest）
+    #[error("job cancelled")]
+    Cancelled,
+}
+
+enum Slot<T> {
+    Pending,
+    Running,
+    Done(Result<T, JobError>),
+    // 结果已被取走
+    Taken,
+}
+
+// JobHandle 和执行任务的 worker 共享的结果
+struct JobState<T> {
+    slot: Mutex<(Slot<T>, Option<Waker>)>,
+    done: Condvar,
+}
+
+impl<T> JobState<T> {
+    fn lock(&self) -> MutexGuard<'_, (Slot<T>, Option<Waker>)> {
+        self.slot.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn finish(&self, result: Result<T, JobError>) {
+        let mut slot = self.lock();
+        if matches!(slot.0, Slot::Pending | Slot::Running) {
+            slot.0 = Slot::Done(result);
+        }
+        let waker = slot.1.take();
+        drop(slot);
+        self.done.notify_all();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+pub struct JobHandle<T> {
+    state: Arc<JobState<T>>,
+}
+
+impl<T> JobHandle<T> {
+    /// 阻塞等待任务结束，返回任务的结果
+    pub fn join(self) -> Result<T, JobError> {
+        let mut slot = self.state.lock();
+        loop {
+            if let Some(result) = take_result(&mut slot.0) {
+                return result;
+            }
+            slot = self
+                .state
+                .done
+                .wait(slot)
+                .unwrap_or_else(|err| err.into_inner());
+        }
+    }
+
+    /// 最多等待 timeout, 超时返回 None, 之后可以继续 join
+    pub fn join_timeout(&mut self, timeout: Duration) -> Option<Result<T, JobError>> {
+        let deadline = Instant::now() + timeout;
+        let mut slot = self.state.lock();
+        loop {
+            if let Some(result) = take_result(&mut slot.0) {
+                return Some(result);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return None;
+            }
+            slot = self
+                .state
+                .done
+                .wait_timeout(slot, deadline - now)
+                .unwrap_or_else(|err| err.into_inner())
+                .0;
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        matches!(self.state.lock().0, Slot::Done(_) | Slot::Taken)
+    }
+
+    /// 取消还没有开始执行的任务，成功时返回 true
+    /// 已经开始执行的任务无法取消
+    pub fn cancel(&self) -> bool {
+        let mut slot = self.state.lock();
+        if !matches!(slot.0, Slot::Pending) {
+            return false;
+        }
+        // 任务仍然留在队列中，worker 取出后发现已取消会直接跳过
+        slot.0 = Slot::Done(Err(JobError::Cancelled));
+        let waker = slot.1.take();
+        drop(slot);
+        self.state.done.notify_all();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+        true
+    }
+}
+
+fn take_result<T>(slot: &mut Slot<T>) -> Option<Result<T, JobError>> {
+    match std::mem::replace(slot, Slot::Taken) {
+        Slot::Done(result) => Some(result),
+        Slot::Taken => panic!("job result already taken"),
+        pending => {
+            *slot = pending;
+            None
+        }
+    }
+}
+
+impl<T> Future for JobHandle<T> {
+    type Output = Result<T, JobError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut slot = self.state.lock();
+        match take_result(&mut slot.0) {
+            Some(result) => Poll::Ready(result),
+            None => {
+                slot.1 = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+// 在 worker 中执行的任务
+// 如果任务没有执行就被 drop（被取消、被丢弃或提交失败），completer 会把结果设为 Cancelled
+// 字段按声明顺序 drop, scope 必须在最后，保证通知 scope 时 f 借用的数据已经不再被使用
+struct Task<F, T> {
+    f: F,
+    completer: Completer<T>,
+    scope: ScopeGuard,
+}
+
+impl<F, T> Task<F, T>
+where
+    F: FnOnce() -> T,
+{
+    fn run(self) {
+        let Task {
+            f,
+            completer,
+            scope,
+        } = self;
+        // execute 返回或 panic 展开时 f 和 completer 都已经 drop, 之后才通知 scope
+        execute(f, completer);
+        drop(scope);
+    }
+}
+
+fn execute<F, T>(f: F, completer: Completer<T>)
+where
+    F: FnOnce() -> T,
+{
+    let state = completer.state.as_ref().unwrap();
+    {
+        let mut slot = state.lock();
+        if !matches!(slot.0, Slot::Pending) {
+            // 已经被取消，f 随函数返回 drop
+            return;
+        }
+        slot.0 = Slot::Running;
+    }
+
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(value) => completer.complete(Ok(value)),
+        Err(payload) => {
+            completer.complete(Err(JobError::Panicked(panic_message(&*payload))));
+            // 继续 panic, 由线程池统计 panicked 的任务数
+            panic::resume_unwind(payload);
+        }
+    }
+}
+
+struct Completer<T> {
+    state: Option<Arc<JobState<T>>>,
+}
+
+impl<T> Completer<T> {
+    fn complete(mut self, result: Result<T, JobError>) {
+        if let Some(state) = self.state.take() {
+            state.finish(result);
+        }
+    }
+}
+
+impl<T> Drop for Completer<T> {
+    fn drop(&mut self) {
+        if let Some(state) = self.state.take() {
+            state.finish(Err(JobError::Cancelled));
+        }
+    }
+}
+
+// drop 时通知 scope 任务已经结束，不属于 scope 的任务为 None
+struct ScopeGuard(Option<Arc<ScopeState>>);
+
+impl Drop for ScopeGuard {
+    fn drop(&mut self) {
+        if let Some(scope) = self.0.take() {
+            scope.job_done();
+        }
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        msg.to_string()
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg.clone()
+    } else {
+        String::from("unknown panic")
+    }
+}
+
+fn new_task<F, T>(f: F, scope: Option<Arc<ScopeState>>) -> (Task<F, T>, JobHandle<T>) {
+    let state = Arc::new(JobState {
+        slot: Mutex::new((Slot::Pending, None)),
+        done: Condvar::new(),
+    });
+    let task = Task {
+        f,
+        completer: Completer {
+            state: Some(Arc::clone(&state)),
+        },
+        scope: ScopeGuard(scope),
+    };
+    (task, JobHandle { state })
+}
+
+impl ThreadPool {
+    /// 提交任务，通过返回的 JobHandle 获取任务的结果
+    pub fn submit<F, T>(&self, f: F) -> Result<JobHandle<T>, PoolError>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (task, handle) = new_task(f, None);
+        self.push(Box::new(move || task.run()))?;
+        Ok(handle)
+    }
+
+    /// 在 scope 中提交的任务可以借用栈上的数据，scope 返回前会等待所有任务结束
+    /// 注意：不要在线程池的 worker 中调用，否则 worker 可能都在等待而没有线程执行任务
+    pub fn scope<'env, F, R>(&self, f: F) -> R
+    where
+        F: for<'scope> FnOnce(&'scope Scope<'scope, 'env>) -> R,
+    {
+        let scope = Scope {
+            pool: self,
+            state: Arc::new(ScopeState {
+                pending: Mutex::new(0),
+                done: Condvar::new(),
+            }),
+            scope: PhantomData,
+            env: PhantomData,
+        };
+        // f panic 时也要等待已提交的任务结束，再继续 panic
+        let result = panic::catch_unwind(AssertUnwindSafe(|| f(&scope)));
+        scope.state.wait();
+        match result {
+            Ok(result) => result,
+            Err(payload) => panic::resume_unwind(payload),
+        }
+    }
+}
+
+// scope
+
+struct ScopeState {
+    pending: Mutex<usize>,
+    done: Condvar,
+}
+
+impl ScopeState {
+    fn lock(&self) -> MutexGuard<'_, usize> {
+        self.pending.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn job_done(&self) {
+        let mut pending = self.lock();
+        *pending -= 1;
+        if *pending == 0 {
+            self.done.notify_all();
+        }
+    }
+
+    fn wait(&self) {
+        let mut pending = self.lock();
+        while *pending > 0 {
+            pending = self
+                .done
+                .wait(pending)
+                .unwrap_or_else(|err| err.into_inner());
+        }
+    }
+}
+
+// 参考 std::thread::Scope
+// 'scope 为 scope 的生命周期，'env 为任务借用的数据的生命周期
+pub struct Scope<'scope, 'env: 'scope> {
+    pool: &'scope ThreadPool,
+    state: Arc<ScopeState>,
+    scope: PhantomData<&'scope mut &'scope ()>,
+    env: PhantomData<&'env mut &'env ()>,
+}
+
+impl<'scope, 'env> Scope<'scope, 'env> {
+    pub fn submit<F, T>(&'scope self, f: F) -> Result<JobHandle<T>, PoolError>
+    where
+        F: FnOnce() -> T + Send + 'scope,
+        T: Send + 'scope,
+    {
+        *self.state.lock() += 1;
+        let (task, handle) = new_task(f, Some(Arc::clone(&self.state)));
+        let job: Box<dyn FnOnce() + Send + 'scope> = Box::new(move || task.run());
+        // SAFETY: scope 返回前会等待所有任务执行完成或被 drop（ScopeGuard 在 f 之后 drop 时通知 scope）,
+        // 所以任务不会在借用的数据失效后使用它
+        let job: Job = unsafe { std::mem::transmute(job) };
+        self.pool.push(job)?;
+        Ok(handle)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apps::webserver::pool::{PoolConfig, QueuePolicy};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::mpsc;
+    use std::thread;
+
+    #[test]
+    fn test_job_submit() {
+        let pool = ThreadPool::new(2);
+        let handle = pool.submit(|| 1 + 2).unwrap();
+        assert_eq!(handle.join(), Ok(3));
+
+        let handle = pool.submit(|| -> i32 { panic!("boom") }).unwrap();
+        assert_eq!(handle.join(), Err(JobError::Panicked(String::from("boom"))));
+
+        let (tx, rx) = mpsc::channel::<()>();
+        let mut handle = pool
+            .submit(move || {
+                rx.recv().unwrap();
+                "done"
+            })
+            .unwrap();
+        assert_eq!(handle.join_timeout(Duration::from_millis(20)), None);
+        assert!(!handle.is_finished());
+        tx.send(()).unwrap();
+        assert_eq!(
+            handle.join_timeout(Duration::from_secs(5)),
+            Some(Ok("done"))
+        );
+
+        let handle = pool.submit(|| String::from("async")).unwrap();
+        assert_eq!(
+            futures::executor::block_on(handle),
+            Ok(String::from("async"))
+        );
+    }
+
+    #[test]
+    fn test_job_cancel() {
+        let pool = ThreadPool::with_config(PoolConfig {
+            min_workers: 1,
+            max_workers: 1,
+            queue_capacity: 1,
+            queue_policy: QueuePolicy::DropOldest,
+            ..PoolConfig::default()
+        });
+        let (tx, rx) = mpsc::channel::<()>();
+        let (started_tx, started_rx) = mpsc::channel::<()>();
+        let running = pool
+            .submit(move || {
+                started_tx.send(()).unwrap();
+                rx.recv().unwrap();
+            })
+            .unwrap();
+        started_rx.recv().unwrap();
+        assert!(!running.cancel());
+
+        let queued = pool.submit(|| 1).unwrap();
+        assert!(queued.cancel());
+        assert!(!queued.cancel());
+        assert_eq!(queued.join(), Err(JobError::Cancelled));
+
+        // 队列满时最早的任务被丢弃
+        let dropped = pool.submit(|| 2).unwrap();
+        let last = pool.submit(|| 3).unwrap();
+        assert_eq!(dropped.join(), Err(JobError::Cancelled));
+
+        tx.send(()).unwrap();
+        assert_eq!(running.join(), Ok(()));
+        assert_eq!(last.join(), Ok(3));
+    }
+
+    #[test]
+    fn test_job_scope() {
+        let pool = ThreadPool::new(4);
+        let data = [1, 2, 3, 4, 5, 6, 7, 8];
+        let mut total = 0;
+        let sum = pool.scope(|s| {
+            let handles: Vec<_> = data
+                .chunks(3)
+                .map(|chunk| s.submit(move || chunk.iter().sum::<i32>()).unwrap())
+                .collect();
+            // 没有 join 的任务也会在 scope 返回前执行完
+            s.submit(|| total = data.len()).unwrap();
+            handles.into_iter().map(|h| h.join().unwrap()).sum::<i32>()
+        });
+        assert_eq!(sum, 36);
+        assert_eq!(total, 8);
+
+        // scope 中 panic 时也会等待任务结束
+        let mut finished = false;
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            pool.scope(|s| {
+                s.submit(|| {
+                    thread::sleep(Duration::from_millis(20));
+                    finished = true;
+                })
+                .unwrap();
+                panic!("scope panic");
+            })
+        }));
+        assert!(result.is_err());
+        assert!(finished);
+    }
+
+    #[test]
+    fn test_job_scope_cancel() {
+        // drop 时稍等再记录，通知 scope 早于 drop 时 scope 会在记录前返回
+        struct Borrowed<'a>(&'a AtomicUsize);
+        impl Drop for Borrowed<'_> {
+            fn drop(&mut self) {
+                thread::sleep(Duration::from_millis(50));
+                self.0.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        let pool = ThreadPool::new(1);
+        let dropped = AtomicUsize::new(0);
+        pool.scope(|s| {
+            let (tx, rx) = mpsc::channel::<()>();
+            let (started_tx, started_rx) = mpsc::channel::<()>();
+            s.submit(move || {
+                started_tx.send(()).unwrap();
+                rx.recv().unwrap();
+            })
+            .unwrap();
+            started_rx.recv().unwrap();
+
+            // worker 取出已取消的任务时跳过执行，只 drop 借用的数据
+            let borrowed = Borrowed(&dropped);
+            let handle = s.submit(move || drop(borrowed)).unwrap();
+            assert!(handle.cancel());
+            tx.send(()).unwrap();
+        });
+        assert_eq!(dropped.load(Ordering::SeqCst), 1);
+    }
+}
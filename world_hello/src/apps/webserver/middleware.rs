@@ -0,0 +1,850 @@
+use crate::apps::webserver::config::Config;
+use crate::apps::webserver::http::{
+    normalize_path, Body, Headers, Method, Request, Response, StatusCode, Version,
+};
+use crate::apps::webserver::rate_limit::RateLimit;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use flate2::write::{DeflateEncoder, GzEncoder};
+use std::collections::HashMap;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+// web server 的 middleware
+// before 在路由之前按注册顺序调用，可以修改请求或直接返回响应；after 在得到响应之后按相反顺序调用
+// handler 会消费 Request, 因此 after 只能通过 Context 访问请求的信息
+
+/// 一个请求在 middleware 之间共享的信息
+#[derive(Debug, Clone)]
+pub struct Context {
+    pub method: Method,
+    pub path: String,
+    pub query: Option<String>,
+    pub version: Version,
+    pub headers: Headers,
+    pub remote_addr: Option<SocketAddr>,
+    pub started: Instant,
+    /// 认证通过的用户名
+    pub user: Option<String>,
+}
+
+impl Context {
+    pub fn new(req: &Request) -> Self {
+        Context {
+            method: req.method,
+            path: req.path.clone(),
+            query: req.query.clone(),
+            version: req.version,
+            headers: req.headers.clone(),
+            remote_addr: req.remote_addr,
+            started: Instant::now(),
+            user: None,
+        }
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+}
+
+pub trait Middleware: Send + Sync {
+    /// 返回 Some 时请求被拦截，不再调用之后的 middleware 和 handler
+    fn before(&self, _req: &mut Request, _ctx: &mut Context) -> Option<Response> {
+        None
+    }
+
+    fn after(&self, _ctx: &Context, _resp: &mut Response) {}
+}
+
+#[derive(Default)]
+pub struct Middlewares {
+    list: Vec<Box<dyn Middleware>>,
+}
+
+impl Middlewares {
+    pub fn new() -> Self {
+        Middlewares::default()
+    }
+
+    /// 按配置组装 server 使用的 middleware
+    /// 被限流或认证失败的请求也会记录访问日志，preflight 请求不带认证信息，需要在认证之前处理
+    pub fn from_config(config: &Config) -> Self {
+        let mut middlewares = Middlewares::new().with(AccessLog::new());
+        if let Some(limit) = RateLimit::from_config(config) {
+            middlewares = middlewares.with(limit);
+        }
+        if let Some(cors) = Cors::from_config(config) {
+            middlewares = middlewares.with(cors);
+        }
+        if let Some(auth) = BasicAuth::from_config(config) {
+            middlewares = middlewares.with(auth);
+        }
+        middlewares.with(Compression::new()).with(Timing)
+    }
+
+    pub fn with(mut self, middleware: impl Middleware + 'static) -> Self {
+        self.list.push(Box::new(middleware));
+        self
+    }
+
+    /// 依次调用 before, 返回请求的 Context 和拦截请求的响应
+    pub fn before(&self, req: &mut Request) -> (Context, Option<Response>) {
+        let mut ctx = Context::new(req);
+        for middleware in &self.list {
+            if let Some(resp) = middleware.before(req, &mut ctx) {
+                return (ctx, Some(resp));
+            }
+        }
+        (ctx, None)
+    }
+
+    /// 按相反的顺序调用 after, 被拦截的请求也会调用所有 middleware 的 after
+    pub fn after(&self, ctx: &Context, resp: &mut Response) {
+        for middleware in self.list.iter().rev() {
+            middleware.after(ctx, resp);
+        }
+    }
+}
+
+/// 以 common log format 输出访问日志
+/// refer: https://httpd.apache.org/docs/current/logs.html#common
+pub struct AccessLog {
+    writer: Box<dyn Fn(String) + Send + Sync>,
+}
+
+impl AccessLog {
+    pub fn new() -> Self {
+        AccessLog::with_writer(|line| println!("{line}"))
+    }
+
+    pub fn with_writer(writer: impl Fn(String) + Send + Sync + 'static) -> Self {
+        AccessLog {
+            writer: Box::new(writer),
+        }
+    }
+
+    pub fn format(ctx: &Context, resp: &Response) -> String {
+        let host = ctx
+            .remote_addr
+            .map(|addr| addr.ip().to_string())
+            .unwrap_or_else(|| String::from("-"));
+        let user = ctx.user.as_deref().unwrap_or("-");
+        let time = chrono::Local::now().format("%d/%b/%Y:%H:%M:%S %z");
+        let target = match &ctx.query {
+            Some(query) => format!("{}?{}", ctx.path, query),
+            None => ctx.path.clone(),
+        };
+        let size = match resp.body.len() {
+            Some(len) if len > 0 => len.to_string(),
+            _ => String::from("-"),
+        };
+        format!(
+            "{host} - {user} [{time}] \"{} {target} {}\" {} {size}",
+            ctx.method,
+            ctx.version,
+            resp.status.as_u16()
+        )
+    }
+}
+
+impl Default for AccessLog {
+    fn default() -> Self {
+        AccessLog::new()
+    }
+}
+
+impl Middleware for AccessLog {
+    fn after(&self, ctx: &Context, resp: &mut Response) {
+        (self.writer)(AccessLog::format(ctx, resp));
+    }
+}
+
+/// 在响应中添加 X-Response-Time header, 单位为毫秒
+pub struct Timing;
+
+impl Middleware for Timing {
+    fn after(&self, ctx: &Context, resp: &mut Response) {
+        let ms = ctx.elapsed().as_secs_f64() * 1000.0;
+        resp.headers.set("X-Response-Time", format!("{ms:.3}ms"));
+    }
+}
+
+/// 按配置启用认证时使用的 realm
+pub const AUTH_REALM: &str = "webserver";
+
+/// http basic 认证，只保护以 prefix 开头的路径
+pub struct BasicAuth {
+    realm: String,
+    prefix: String,
+    users: HashMap<String, String>,
+}
+
+impl BasicAuth {
+    pub fn new(realm: &str) -> Self {
+        BasicAuth {
+            realm: realm.to_string(),
+            prefix: String::from("/"),
+            users: HashMap::new(),
+        }
+    }
+
+    /// 没有配置用户时不需要认证
+    pub fn from_config(config: &Config) -> Option<Self> {
+        if config.auth_users.is_empty() {
+            return None;
+        }
+        let auth = BasicAuth::new(AUTH_REALM).prefix(&config.auth_prefix);
+        let auth = config
+            .auth_users
+            .iter()
+            .fold(auth, |auth, (name, password)| auth.user(name, password));
+        Some(auth)
+    }
+
+    /// prefix 与请求路径一样经过 normalize_path 规范化后再比较
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        self.prefix = normalize_path(prefix).unwrap_or_else(|| prefix.to_string());
+        self
+    }
+
+    pub fn user(mut self, name: &str, password: &str) -> Self {
+        self.users.insert(name.to_string(), password.to_string());
+        self
+    }
+
+    // 返回认证通过的用户名
+    fn authenticate(&self, req: &Request) -> Option<String> {
+        let value = req.header("authorization")?;
+        let (scheme, credentials) = value.split_once(' ')?;
+        if !scheme.eq_ignore_ascii_case("basic") {
+            return None;
+        }
+        let decoded = BASE64.decode(credentials.trim()).ok()?;
+        let decoded = String::from_utf8(decoded).ok()?;
+        let (name, password) = decoded.split_once(':')?;
+        let expected = self.users.get(name)?;
+        constant_time_eq(expected.as_bytes(), password.as_bytes()).then(|| name.to_string())
+    }
+}
+
+// 比较耗时只与长度有关，不会因为第一个不同字节的位置泄露密码
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+impl Middleware for BasicAuth {
+    fn before(&self, req: &mut Request, ctx: &mut Context) -> Option<Response> {
+        if !req.path.starts_with(&self.prefix) {
+            return None;
+        }
+        match self.authenticate(req) {
+            Some(user) => {
+                ctx.user = Some(user);
+                None
+            }
+            None => {
+                let challenge = format!("Basic realm=\"{}\", charset=\"UTF-8\"", self.realm);
+                let resp = Response::text(StatusCode::Unauthorized, "unauthorized")
+                    .with_header("WWW-Authenticate", challenge);
+                Some(resp)
+            }
+        }
+    }
+}
+
+/// 处理跨域请求：拦截 preflight 请求，并在允许的 origin 的响应中添加 CORS headers
+/// refer: https://fetch.spec.whatwg.org/#http-cors-protocol
+pub struct Cors {
+    // 为空时允许所有 origin
+    origins: Vec<String>,
+    methods: Vec<Method>,
+    headers: Vec<String>,
+    max_age: Duration,
+}
+
+impl Default for Cors {
+    fn default() -> Self {
+        Cors {
+            origins: Vec::new(),
+            methods: vec![
+                Method::Get,
+                Method::Head,
+                Method::Post,
+                Method::Put,
+                Method::Delete,
+            ],
+            headers: vec![String::from("Content-Type"), String::from("Authorization")],
+            max_age: Duration::from_secs(600),
+        }
+    }
+}
+
+impl Cors {
+    pub fn new() -> Self {
+        Cors::default()
+    }
+
+    /// 没有配置 origin 时不处理跨域请求，* 表示允许所有 origin
+    pub fn from_config(config: &Config) -> Option<Self> {
+        if config.cors_origins.is_empty() {
+            return None;
+        }
+        let cors = Cors::new();
+        if config.cors_origins.iter().any(|origin| origin == "*") {
+            return Some(cors);
+        }
+        let cors = config
+            .cors_origins
+            .iter()
+            .fold(cors, |cors, origin| cors.allow_origin(origin));
+        Some(cors)
+    }
+
+    pub fn allow_origin(mut self, origin: &str) -> Self {
+        self.origins.push(origin.to_string());
+        self
+    }
+
+    pub fn allow_methods(mut self, methods: &[Method]) -> Self {
+        self.methods = methods.to_vec();
+        self
+    }
+
+    pub fn allow_headers(mut self, headers: &[&str]) -> Self {
+        self.headers = headers.iter().map(|h| h.to_string()).collect();
+        self
+    }
+
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = max_age;
+        self
+    }
+
+    fn allowed_origin(&self, headers: &Headers) -> Option<String> {
+        let origin = headers.get("origin")?;
+        if self.origins.is_empty() {
+            Some(String::from("*"))
+        } else {
+            self.origins
+                .iter()
+                .any(|o| o == origin)
+                .then(|| origin.to_string())
+        }
+    }
+}
+
+impl Middleware for Cors {
+    fn before(&self, req: &mut Request, _ctx: &mut Context) -> Option<Response> {
+        let request_method = req.header("access-control-request-method")?;
+        if req.method != Method::Options {
+            return None;
+        }
+        let Some(origin) = self.allowed_origin(&req.headers) else {
+            return Some(Response::text(StatusCode::Forbidden, "origin not allowed"));
+        };
+        let method_allowed = request_method
+            .parse::<Method>()
+            .is_ok_and(|m| self.methods.contains(&m));
+        if !method_allowed {
+            return Some(Response::text(StatusCode::Forbidden, "method not allowed"));
+        }
+
+        let methods: Vec<&str> = self.methods.iter().map(|m| m.as_str()).collect();
+        let resp = Response::new(StatusCode::NoContent)
+            .with_header("Access-Control-Allow-Origin", origin)
+            .with_header("Access-Control-Allow-Methods", methods.join(", "))
+            .with_header("Access-Control-Allow-Headers", self.headers.join(", "))
+            .with_header("Access-Control-Max-Age", self.max_age.as_secs().to_string())
+            .with_header("Vary", "Origin");
+        Some(resp)
+    }
+
+    fn after(&self, ctx: &Context, resp: &mut Response) {
+        // 是否返回 CORS 头取决于 Origin, 没有 Origin 或不允许的 origin 也需要 Vary,
+        // 否则缓存可能把不带 CORS 头的响应返回给允许的 origin
+        let varies = resp.headers.get_all("vary").any(|value| {
+            value
+                .split(',')
+                .any(|v| v.trim().eq_ignore_ascii_case("origin"))
+        });
+        if !varies {
+            resp.headers.append("Vary", "Origin");
+        }
+        if resp.headers.contains("access-control-allow-origin") {
+            return;
+        }
+        if let Some(origin) = self.allowed_origin(&ctx.headers) {
+            resp.headers.set("Access-Control-Allow-Origin", origin);
+        }
+    }
+}
+
+/// 小于该字节数的响应不压缩，压缩后节省的流量不足以抵消开销
+pub const COMPRESS_MIN_SIZE: u64 = 1024;
+/// 大于该字节数的响应不压缩，避免把整个文件读入内存
+pub const COMPRESS_MAX_SIZE: u64 = 8 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Gzip,
+    Deflate,
+}
+
+impl Encoding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+
+    /// 根据 Accept-Encoding 选择 q 值最大的编码，q 值相同时优先 gzip, 不接受压缩时返回 None
+    /// refer: https://www.rfc-editor.org/rfc/rfc9110#name-accept-encoding
+    pub fn negotiate(accept_encoding: &str) -> Option<Encoding> {
+        let (mut gzip, mut deflate, mut any) = (None, None, None);
+        for item in accept_encoding.split(',') {
+            let mut parts = item.split(';');
+            let coding = parts.next().unwrap_or_default().trim().to_ascii_lowercase();
+            // 没有 q 参数时 q = 1, 无效的 q 值按 0 处理
+            let q = parts
+                .find_map(|param| {
+                    let (name, value) = param.split_once('=')?;
+                    name.trim()
+                        .eq_ignore_ascii_case("q")
+                        .then(|| value.trim().parse::<f32>().unwrap_or(0.0))
+                })
+                .unwrap_or(1.0);
+            match coding.as_str() {
+                "gzip" | "x-gzip" => gzip = Some(q),
+                "deflate" => deflate = Some(q),
+                "*" => any = Some(q),
+                _ => {}
+            }
+        }
+
+        // 没有单独列出的编码使用 * 的 q 值
+        let gzip = gzip.or(any).unwrap_or(0.0);
+        let deflate = deflate.or(any).unwrap_or(0.0);
+        if gzip <= 0.0 && deflate <= 0.0 {
+            None
+        } else if gzip >= deflate {
+            Some(Encoding::Gzip)
+        } else {
+            Some(Encoding::Deflate)
+        }
+    }
+
+    pub fn encode(&self, data: &[u8], level: flate2::Compression) -> io::Result<Vec<u8>> {
+        match self {
+            Encoding::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), level);
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+            Encoding::Deflate => {
+                let mut encoder = DeflateEncoder::new(Vec::new(), level);
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+        }
+    }
+}
+
+/// 根据请求的 Accept-Encoding 使用 gzip 或 deflate 压缩响应
+/// 只压缩长度已知的 200 响应，跳过图片、视频、压缩包等已经压缩过的类型
+/// 压缩文件 body 时会阻塞地读取整个文件
+pub struct Compression {
+    min_size: u64,
+    max_size: u64,
+    level: flate2::Compression,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression {
+            min_size: COMPRESS_MIN_SIZE,
+            max_size: COMPRESS_MAX_SIZE,
+            level: flate2::Compression::default(),
+        }
+    }
+}
+
+impl Compression {
+    pub fn new() -> Self {
+        Compression::default()
+    }
+
+    pub fn min_size(mut self, min_size: u64) -> Self {
+        self.min_size = min_size;
+        self
+    }
+
+    pub fn max_size(mut self, max_size: u64) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// 压缩级别 0-9
+    pub fn level(mut self, level: u32) -> Self {
+        self.level = flate2::Compression::new(level.min(9));
+        self
+    }
+
+    // 响应的内容是否会随 Accept-Encoding 变化
+    fn applies_to(&self, resp: &Response) -> bool {
+        if resp.status != StatusCode::Ok || resp.headers.contains("content-encoding") {
+            return false;
+        }
+        let no_transform = resp
+            .headers
+            .get("cache-control")
+            .is_some_and(|v| v.to_ascii_lowercase().contains("no-transform"));
+        let compressible = resp.headers.get("content-type").is_some_and(compressible);
+        let size_ok = resp
+            .body
+            .len()
+            .is_some_and(|len| len >= self.min_size && len <= self.max_size);
+        !no_transform && compressible && size_ok
+    }
+}
+
+impl Middleware for Compression {
+    fn after(&self, ctx: &Context, resp: &mut Response) {
+        if !self.applies_to(resp) {
+            return;
+        }
+        // 无论这次是否压缩，缓存都需要按 Accept-Encoding 区分响应
+        resp.headers.append("Vary", "Accept-Encoding");
+        let Some(encoding) = ctx
+            .headers
+            .get("accept-encoding")
+            .and_then(Encoding::negotiate)
+        else {
+            return;
+        };
+
+        let data = match read_body(&mut resp.body) {
+            Ok(data) => data,
+            Err(err) => {
+                eprintln!("compression: read body error: {err}");
+                return;
+            }
+        };
+        let compressed = match encoding.encode(&data, self.level) {
+            // 压缩后没有变小时保持原样
+            Ok(compressed) if compressed.len() < data.len() => compressed,
+            Ok(_) => return,
+            Err(err) => {
+                eprintln!("compression: {err}");
+                return;
+            }
+        };
+        resp.body = Body::from(compressed);
+        resp.headers.set("Content-Encoding", encoding.as_str());
+        // Range 针对未压缩的内容，压缩后的响应不再支持
+        resp.headers.remove("accept-ranges");
+        // 压缩后的内容与原内容不同，strong ETag 改为 weak ETag
+        if let Some(etag) = resp.headers.get("etag").filter(|e| !e.starts_with("W/")) {
+            let etag = format!("W/{etag}");
+            resp.headers.set("ETag", etag);
+        }
+    }
+}
+
+// 读取长度已知的 body, 文件的读取位置由 offset 决定，不影响之后的写出
+// 读取文件是阻塞的，异步 server 需要在阻塞线程中调用 after
+fn read_body(body: &mut Body) -> io::Result<Vec<u8>> {
+    match body {
+        Body::Empty => Ok(Vec::new()),
+        Body::Bytes(bytes) => Ok(bytes.to_vec()),
+        Body::File { file, offset, len } => {
+            let mut data = vec![0u8; *len as usize];
+            file.seek(SeekFrom::Start(*offset))?;
+            file.read_exact(&mut data)?;
+            Ok(data)
+        }
+        Body::Stream(_) => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "stream body can not be compressed",
+        )),
+    }
+}
+
+// 图片、音视频、字体和压缩包本身已经压缩过，再压缩没有意义
+fn compressible(content_type: &str) -> bool {
+    let media_type = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    match media_type.split_once('/') {
+        Some(("image", subtype)) => subtype == "svg+xml" || subtype == "x-icon",
+        Some(("video" | "audio", _)) => false,
+        Some(("font", subtype)) => !matches!(subtype, "woff" | "woff2"),
+        Some(("application", subtype)) => !matches!(
+            subtype,
+            "zip"
+                | "gzip"
+                | "x-gzip"
+                | "x-bzip2"
+                | "x-xz"
+                | "zstd"
+                | "x-7z-compressed"
+                | "x-rar-compressed"
+                | "pdf"
+                | "octet-stream"
+        ),
+        Some(_) => true,
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apps::webserver::http::parse_request;
+    use std::sync::{Arc, Mutex};
+
+    fn request(method: &str, target: &str, headers: &[(&str, &str)]) -> Request {
+        let mut raw = format!("{method} {target} HTTP/1.1\r\nHost: localhost\r\n");
+        for (name, value) in headers {
+            raw.push_str(&format!("{name}: {value}\r\n"));
+        }
+        raw.push_str("\r\n");
+        let mut req = parse_request(raw.as_bytes()).unwrap().unwrap().0;
+        req.remote_addr = Some("127.0.0.1:50000".parse().unwrap());
+        req
+    }
+
+    // 模拟 server 的处理流程，handler 固定返回 "ok"
+    fn handle(middlewares: &Middlewares, mut req: Request) -> Response {
+        let (ctx, resp) = middlewares.before(&mut req);
+        let mut resp = resp.unwrap_or_else(|| Response::text(StatusCode::Ok, "ok"));
+        middlewares.after(&ctx, &mut resp);
+        resp
+    }
+
+    #[test]
+    fn test_access_log_and_timing() {
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let writer = Arc::clone(&lines);
+        let middlewares = Middlewares::new()
+            .with(AccessLog::with_writer(move |line| {
+                writer.lock().unwrap().push(line)
+            }))
+            .with(Timing);
+
+        let resp = handle(&middlewares, request("GET", "/a?b=1", &[]));
+        assert!(resp.headers.get("x-response-time").unwrap().ends_with("ms"));
+
+        let lines = lines.lock().unwrap();
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].starts_with("127.0.0.1 - - ["), "{}", lines[0]);
+        assert!(lines[0].ends_with("] \"GET /a?b=1 HTTP/1.1\" 200 2"));
+    }
+
+    #[test]
+    fn test_basic_auth() {
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let writer = Arc::clone(&lines);
+        let middlewares = Middlewares::new()
+            .with(AccessLog::with_writer(move |line| {
+                writer.lock().unwrap().push(line)
+            }))
+            .with(
+                BasicAuth::new("admin")
+                    .prefix("/admin")
+                    .user("alice", "secret"),
+            );
+
+        let resp = handle(&middlewares, request("GET", "/public", &[]));
+        assert_eq!(resp.status, StatusCode::Ok);
+
+        let resp = handle(&middlewares, request("GET", "/admin/stats", &[]));
+        assert_eq!(resp.status, StatusCode::Unauthorized);
+        assert_eq!(
+            resp.headers.get("www-authenticate"),
+            Some("Basic realm=\"admin\", charset=\"UTF-8\"")
+        );
+
+        for wrong in ["alice:wrong", "alice:secre", "alice:secrets", "bob:secret"] {
+            let wrong = format!("Basic {}", BASE64.encode(wrong));
+            let resp = handle(
+                &middlewares,
+                request("GET", "/admin", &[("Authorization", &wrong)]),
+            );
+            assert_eq!(resp.status, StatusCode::Unauthorized);
+        }
+
+        let auth = format!("Basic {}", BASE64.encode("alice:secret"));
+        let resp = handle(
+            &middlewares,
+            request("GET", "/admin", &[("Authorization", &auth)]),
+        );
+        assert_eq!(resp.status, StatusCode::Ok);
+
+        let lines = lines.lock().unwrap();
+        assert!(lines[1].contains(" - - [") && lines[1].contains("\" 401 "));
+        assert!(lines[6].starts_with("127.0.0.1 - alice ["));
+        drop(lines);
+
+        // 编码、重复的 / 和 . 不能绕过认证
+        for path in [
+            "/%61dmin/stats",
+            "//admin/stats",
+            "/./admin/stats",
+            "/admin/./stats",
+        ] {
+            let resp = handle(&middlewares, request("GET", path, &[]));
+            assert_eq!(resp.status, StatusCode::Unauthorized, "path: {path}");
+        }
+    }
+
+    #[test]
+    fn test_cors() {
+        let middlewares = Middlewares::new().with(
+            Cors::new()
+                .allow_origin("https://example.com")
+                .allow_methods(&[Method::Get, Method::Post]),
+        );
+
+        let preflight = |origin: &str, method: &str| {
+            request(
+                "OPTIONS",
+                "/api",
+                &[
+                    ("Origin", origin),
+                    ("Access-Control-Request-Method", method),
+                ],
+            )
+        };
+        let resp = handle(&middlewares, preflight("https://example.com", "POST"));
+        assert_eq!(resp.status, StatusCode::NoContent);
+        assert_eq!(
+            resp.headers.get("access-control-allow-origin"),
+            Some("https://example.com")
+        );
+        assert_eq!(
+            resp.headers.get("access-control-allow-methods"),
+            Some("GET, POST")
+        );
+        assert_eq!(resp.headers.get("access-control-max-age"), Some("600"));
+        assert_eq!(resp.headers.get_all("vary").collect::<Vec<_>>(), ["Origin"]);
+
+        let resp = handle(&middlewares, preflight("https://example.com", "DELETE"));
+        assert_eq!(resp.status, StatusCode::Forbidden);
+        assert_eq!(resp.headers.get("vary"), Some("Origin"));
+        let resp = handle(&middlewares, preflight("https://evil.com", "GET"));
+        assert_eq!(resp.status, StatusCode::Forbidden);
+
+        let req = request("GET", "/api", &[("Origin", "https://example.com")]);
+        let resp = handle(&middlewares, req);
+        assert_eq!(
+            resp.headers.get("access-control-allow-origin"),
+            Some("https://example.com")
+        );
+        assert_eq!(resp.headers.get("vary"), Some("Origin"));
+
+        let req = request("GET", "/api", &[("Origin", "https://evil.com")]);
+        let resp = handle(&middlewares, req);
+        assert!(!resp.headers.contains("access-control-allow-origin"));
+        assert_eq!(resp.headers.get("vary"), Some("Origin"));
+        let resp = handle(&middlewares, request("GET", "/api", &[]));
+        assert!(!resp.headers.contains("access-control-allow-origin"));
+        assert_eq!(resp.headers.get("vary"), Some("Origin"));
+    }
+
+    #[test]
+    fn test_negotiate_encoding() {
+        assert_eq!(
+            Encoding::negotiate("gzip, deflate, br"),
+            Some(Encoding::Gzip)
+        );
+        assert_eq!(Encoding::negotiate("deflate"), Some(Encoding::Deflate));
+        assert_eq!(
+            Encoding::negotiate("gzip;q=0.5, deflate;q=0.8"),
+            Some(Encoding::Deflate)
+        );
+        assert_eq!(
+            Encoding::negotiate("*;q=0.1, gzip;Q=0"),
+            Some(Encoding::Deflate)
+        );
+        assert_eq!(Encoding::negotiate("*"), Some(Encoding::Gzip));
+        assert_eq!(Encoding::negotiate("gzip;q=0, deflate;q=0"), None);
+        assert_eq!(Encoding::negotiate("identity, br"), None);
+        assert_eq!(Encoding::negotiate(""), None);
+    }
+
+    #[test]
+    fn test_compression() {
+        use flate2::read::{DeflateDecoder, GzDecoder};
+
+        let html = "<p>hello compression</p>\n".repeat(100);
+        let middlewares = Middlewares::new().with(Compression::new());
+        let handle = |accept: &str, resp: Response| {
+            let mut req = request("GET", "/", &[("Accept-Encoding", accept)]);
+            let (ctx, _) = middlewares.before(&mut req);
+            let mut resp = resp;
+            middlewares.after(&ctx, &mut resp);
+            resp
+        };
+        let body = |resp: &Response| match &resp.body {
+            Body::Bytes(bytes) => bytes.to_vec(),
+            body => panic!("unexpected body {body:?}"),
+        };
+
+        let page = || {
+            Response::html(StatusCode::Ok, html.clone())
+                .with_header("ETag", "\"abc\"")
+                .with_header("Accept-Ranges", "bytes")
+        };
+        let resp = handle("gzip, deflate", page());
+        assert_eq!(resp.headers.get("content-encoding"), Some("gzip"));
+        assert_eq!(resp.headers.get("vary"), Some("Accept-Encoding"));
+        assert_eq!(resp.headers.get("etag"), Some("W/\"abc\""));
+        assert!(!resp.headers.contains("accept-ranges"));
+        let mut decoded = String::new();
+        GzDecoder::new(&body(&resp)[..])
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, html);
+
+        let resp = handle("deflate", page());
+        assert_eq!(resp.headers.get("content-encoding"), Some("deflate"));
+        let mut decoded = String::new();
+        DeflateDecoder::new(&body(&resp)[..])
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, html);
+
+        // 不接受压缩时保持原样，但仍然需要 Vary
+        let resp = handle("identity", page());
+        assert!(!resp.headers.contains("content-encoding"));
+        assert_eq!(resp.headers.get("vary"), Some("Accept-Encoding"));
+        assert_eq!(body(&resp), html.as_bytes());
+
+        // 太小的响应、已经压缩过的类型和 206 响应不压缩
+        for resp in [
+            Response::text(StatusCode::Ok, "ok"),
+            Response::new(StatusCode::Ok)
+                .with_header("Content-Type", "image/png")
+                .with_body(html.clone()),
+            Response::html(StatusCode::PartialContent, html.clone()),
+            Response::html(StatusCode::Ok, html.clone())
+                .with_header("Cache-Control", "no-transform"),
+        ] {
+            let resp = handle("gzip", resp);
+            assert!(!resp.headers.contains("content-encoding"));
+            assert!(!resp.headers.contains("vary"));
+        }
+
+        let path = std::env::temp_dir().join(format!("compression_{}.txt", std::process::id()));
+        std::fs::write(&path, &html).unwrap();
+        let resp = handle("gzip", Response::file(StatusCode::Ok, &path).unwrap());
+        assert_eq!(resp.headers.get("content-encoding"), Some("gzip"));
+        assert!(resp.body.len().unwrap() < html.len() as u64);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -11,13 +11,14 @@ pub struct MockTcpStream {
 
 impl Read for MockTcpStream {
     fn poll_read(
-        self: Pin<&mut Self>,
+        mut self: Pin<&mut Self>,
         _: &mut Context<'_>,
         buf: &mut [u8],
     ) -> Poll<std::io::Result<usize>> {
-        // read: io -> buf
+        // read: io -> buf, 已读取的数据从 read_data 中移除，读完后返回 0 表示连接关闭
         let size = min(self.read_data.len(), buf.len());
         buf[..size].copy_from_slice(&self.read_data[..size]);
+        self.read_data.drain(..size);
         Poll::Ready(Ok(size))
     }
 }
@@ -28,8 +29,8 @@ impl Write for MockTcpStream {
         _: &mut Context<'_>,
         buf: &[u8],
     ) -> Poll<std::io::Result<usize>> {
-        // write: buf -> io
-        self.write_data = Vec::from(buf);
+        // write: buf -> io, 多次写入的数据依次追加
+        self.write_data.extend_from_slice(buf);
         Poll::Ready(Ok(buf.len()))
     }
 
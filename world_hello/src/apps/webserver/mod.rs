@@ -1,5 +1,18 @@
 pub mod appv1;
 pub mod appv2;
 pub mod appv3;
+pub mod config;
+pub mod conn_limit;
+pub mod http;
+pub mod job;
+pub mod middleware;
+#[cfg(test)]
 mod mock_stream;
-mod pool;
+pub mod pool;
+pub mod proxy;
+pub mod rate_limit;
+pub mod router;
+pub mod sse;
+pub mod static_files;
+pub mod tls;
+pub mod websocket;
@@ -1,38 +1,225 @@
+use crossbeam_deque::{Injector, Steal, Stealer, Worker as LocalQueue};
 use std::{
-    sync::{mpsc, Arc, Mutex},
+    cell::RefCell,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Condvar, Mutex, MutexGuard, RwLock,
+    },
     thread,
+    time::Duration,
 };
 
-// thread pool
+// work-stealing thread pool
+// 外部提交的任务进入全局队列 (injector), worker 中提交的任务进入该 worker 的本地队列
+// worker 依次从本地队列、全局队列和其他 worker 的本地队列中获取任务，避免所有 worker 争用同一把锁
+// 所有队列中的任务总数有上限，超过时按 QueuePolicy 处理新任务
+// worker 数量在 min 和 max 之间变化：任务积压时增加 worker, 空闲超时后减少到 min
 
-type Job = Box<dyn FnOnce() + Send + 'static>; // 类型为特征对象
+pub(super) type Job = Box<dyn FnOnce() + Send + 'static>; // 类型为特征对象
+
+/// 队列的默认容量
+pub const DEFAULT_QUEUE_CAPACITY: usize = 256;
+
+// worker 没有任务时，休眠前尝试获取任务的次数
+const SPIN_ROUNDS: usize = 16;
+
+/// 队列满时如何处理新提交的任务
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueuePolicy {
+    /// 阻塞提交者，直到队列有空位
+    /// worker 中提交的任务不阻塞，超出容量也放入该 worker 的本地队列，
+    /// 否则所有 worker 都在等待队列空位时，没有 worker 取走任务，线程池死锁
+    Block,
+    /// 拒绝新任务，返回 PoolError::Full
+    Reject,
+    /// 丢弃队列中最早的任务，再加入新任务
+    DropOldest,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum PoolError {
+    #[error("job queue is full")]
+    Full,
+    #[error("thread pool is closed")]
+    Closed,
+}
+
+#[derive(Debug, Clone)]
+pub struct PoolConfig {
+    pub min_workers: usize,
+    pub max_workers: usize,
+    /// 超过 min_workers 的 worker 空闲超过该时间后退出
+    pub idle_timeout: Duration,
+    pub queue_capacity: usize,
+    pub queue_policy: QueuePolicy,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            min_workers: 1,
+            max_workers: 8,
+            idle_timeout: Duration::from_secs(30),
+            queue_capacity: DEFAULT_QUEUE_CAPACITY,
+            queue_policy: QueuePolicy::Block,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    /// 当前的 worker 数量
+    pub workers: usize,
+    /// 正在执行任务的 worker 数量
+    pub active: usize,
+    /// 等待任务的 worker 数量
+    pub idle: usize,
+    pub queued: usize,
+    pub completed: u64,
+    pub panicked: u64,
+}
+
+struct State {
+    next_id: usize,
+    threads: Vec<thread::JoinHandle<()>>,
+}
+
+// 所有 worker 共享的队列和计数
+// 计数使用原子变量，只有 worker 休眠/唤醒和增减 worker 时才需要加锁
+struct Shared {
+    injector: Injector<Job>,
+    stealers: RwLock<Vec<(usize, Stealer<Job>)>>,
+    state: Mutex<State>,
+    // 有新任务或线程池关闭时通知休眠的 worker
+    not_empty: Condvar,
+    // 队列有空位或线程池关闭时通知阻塞的提交者
+    not_full: Condvar,
+    closed: AtomicBool,
+    // 已提交但还没有被 worker 取出的任务数，包括所有本地队列中的任务
+    queued: AtomicUsize,
+    workers: AtomicUsize,
+    active: AtomicUsize,
+    idle: AtomicUsize,
+    completed: AtomicU64,
+    panicked: AtomicU64,
+    config: PoolConfig,
+}
+
+impl Shared {
+    // job 在锁外执行并且 panic 会被捕获，这里的 PoisonError 可以忽略
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    // 用于判断当前线程是否为本线程池的 worker
+    fn id(&self) -> usize {
+        self as *const Shared as usize
+    }
+
+    // 当前线程是否为该线程池的 worker
+    fn in_worker(&self) -> bool {
+        LOCAL.with(|local| matches!(&*local.borrow(), Some((id, _)) if *id == self.id()))
+    }
+
+    // 取出一个任务，本地队列为空时从全局队列批量获取，再从其他 worker 窃取
+    fn find_job(&self, local: &LocalQueue<Job>) -> Option<Job> {
+        local.pop().or_else(|| loop {
+            let steal = self.injector.steal_batch_and_pop(local).or_else(|| {
+                let stealers = self.stealers.read().unwrap_or_else(|err| err.into_inner());
+                stealers.iter().map(|(_, s)| s.steal()).collect()
+            });
+            match steal {
+                Steal::Success(job) => break Some(job),
+                Steal::Empty => break None,
+                Steal::Retry => continue,
+            }
+        })
+    }
+
+    // 取出最早提交的任务，用于 QueuePolicy::DropOldest
+    fn steal_oldest(&self) -> Option<Job> {
+        loop {
+            let steal = self.injector.steal().or_else(|| {
+                let stealers = self.stealers.read().unwrap_or_else(|err| err.into_inner());
+                stealers.iter().map(|(_, s)| s.steal()).collect()
+            });
+            match steal {
+                Steal::Success(job) => return Some(job),
+                Steal::Empty => return None,
+                Steal::Retry => continue,
+            }
+        }
+    }
+
+    // 任务被取出后调用，队列从满变为不满时唤醒阻塞的提交者
+    fn job_taken(&self) {
+        if self.queued.fetch_sub(1, Ordering::SeqCst) == self.config.queue_capacity {
+            let _state = self.lock();
+            self.not_full.notify_all();
+        }
+    }
+}
+
+thread_local! {
+    // worker 线程的本地队列，以及所属线程池的 id
+    static LOCAL: RefCell<Option<(usize, LocalQueue<Job>)>> = const { RefCell::new(None) };
+}
 
 pub struct ThreadPool {
-    workers: Vec<Worker>,
-    sender: Option<mpsc::Sender<Job>>,
+    shared: Arc<Shared>,
 }
 
 impl ThreadPool {
+    /// 固定 size 个 worker 的线程池
     pub fn new(size: usize) -> Self {
-        assert!(size > 0);
+        ThreadPool::with_queue(size, DEFAULT_QUEUE_CAPACITY, QueuePolicy::Block)
+    }
+
+    pub fn with_queue(size: usize, capacity: usize, policy: QueuePolicy) -> Self {
+        ThreadPool::with_config(PoolConfig {
+            min_workers: size,
+            max_workers: size,
+            queue_capacity: capacity,
+            queue_policy: policy,
+            ..PoolConfig::default()
+        })
+    }
 
-        let (sender, receiver) = mpsc::channel();
-        // Arc 允许多个 Worker 同时持有 receiver（安全共享）
-        // 而 Mutex 可以确保一次只有一个 Worker 能从 receiver 接收消息（互斥使用）
-        let receiver = Arc::new(Mutex::new(receiver));
+    pub fn with_config(config: PoolConfig) -> Self {
+        assert!(config.max_workers > 0);
+        assert!(config.min_workers <= config.max_workers);
+        assert!(config.queue_capacity > 0);
 
-        let mut workers = Vec::with_capacity(size);
-        for id in 0..size {
-            workers.push(Worker::new(id, Arc::clone(&receiver)));
-        }
+        let shared = Arc::new(Shared {
+            injector: Injector::new(),
+            stealers: RwLock::new(Vec::with_capacity(config.max_workers)),
+            state: Mutex::new(State {
+                next_id: 0,
+                threads: Vec::with_capacity(config.max_workers),
+            }),
+            not_empty: Condvar::new(),
+            not_full: Condvar::new(),
+            closed: AtomicBool::new(false),
+            queued: AtomicUsize::new(0),
+            workers: AtomicUsize::new(0),
+            active: AtomicUsize::new(0),
+            idle: AtomicUsize::new(0),
+            completed: AtomicU64::new(0),
+            panicked: AtomicU64::new(0),
+            config,
+        });
 
-        ThreadPool {
-            workers,
-            sender: Some(sender),
+        let mut state = shared.lock();
+        for _ in 0..shared.config.min_workers {
+            Worker::spawn(&shared, &mut state);
         }
+        drop(state);
+
+        ThreadPool { shared }
     }
 
-    pub fn execute<F>(&self, f: F)
+    pub fn execute<F>(&self, f: F) -> Result<(), PoolError>
     where
         // 参考 thread::spawn 函数签名
         // FnOnce:  闭包作为任务只需被线程执行一次即可
@@ -40,21 +227,138 @@ impl ThreadPool {
         // 'static: 我们并不知道线程需要多久时间来执行该任务
         F: FnOnce() + Send + 'static,
     {
-        let job = Box::new(f);
-        self.sender.as_ref().unwrap().send(job).unwrap();
+        self.push(Box::new(f))
+    }
+
+    pub(super) fn push(&self, job: Job) -> Result<(), PoolError> {
+        let shared = &self.shared;
+        let config = &shared.config;
+        // 被丢弃的任务在最后 drop, 避免其中的资源（如连接）释放时阻塞其他线程
+        let mut dropped = Vec::new();
+
+        // 先占用队列中的一个位置，再放入任务
+        loop {
+            if shared.closed.load(Ordering::SeqCst) {
+                return Err(PoolError::Closed);
+            }
+            let queued = shared.queued.load(Ordering::SeqCst);
+            if queued < config.queue_capacity {
+                if shared
+                    .queued
+                    .compare_exchange(queued, queued + 1, Ordering::SeqCst, Ordering::SeqCst)
+                    .is_ok()
+                {
+                    break;
+                }
+                continue;
+            }
+            match config.queue_policy {
+                QueuePolicy::Block if shared.in_worker() => {
+                    shared.queued.fetch_add(1, Ordering::SeqCst);
+                    break;
+                }
+                QueuePolicy::Block => {
+                    let mut state = shared.lock();
+                    while shared.queued.load(Ordering::SeqCst) >= config.queue_capacity
+                        && !shared.closed.load(Ordering::SeqCst)
+                    {
+                        state = shared
+                            .not_full
+                            .wait(state)
+                            .unwrap_or_else(|err| err.into_inner());
+                    }
+                }
+                QueuePolicy::Reject => return Err(PoolError::Full),
+                QueuePolicy::DropOldest => match shared.steal_oldest() {
+                    Some(job) => {
+                        shared.queued.fetch_sub(1, Ordering::SeqCst);
+                        dropped.push(job);
+                    }
+                    // 任务已被 worker 取走，计数还没有更新
+                    None => thread::yield_now(),
+                },
+            }
+        }
+
+        // 在 worker 中提交的任务放入该 worker 的本地队列
+        let job = LOCAL.with(|local| match &*local.borrow() {
+            Some((id, local)) if *id == shared.id() => {
+                local.push(job);
+                None
+            }
+            _ => Some(job),
+        });
+        if let Some(job) = job {
+            shared.injector.push(job);
+        }
+
+        // 等待的任务比空闲的 worker 多时增加 worker
+        let idle = shared.idle.load(Ordering::SeqCst);
+        if shared.queued.load(Ordering::SeqCst) > idle
+            && shared.workers.load(Ordering::SeqCst) < config.max_workers
+        {
+            let mut state = shared.lock();
+            if shared.workers.load(Ordering::SeqCst) < config.max_workers {
+                Worker::spawn(shared, &mut state);
+            }
+        }
+        if idle > 0 {
+            // worker 在加锁后检查 queued 再休眠，这里加锁后通知不会丢失
+            let _state = shared.lock();
+            shared.not_empty.notify_one();
+        }
+        Ok(())
+    }
+
+    /// 队列中等待执行的任务数
+    pub fn queued(&self) -> usize {
+        self.shared.queued.load(Ordering::SeqCst)
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        let shared = &self.shared;
+        PoolStats {
+            workers: shared.workers.load(Ordering::SeqCst),
+            active: shared.active.load(Ordering::SeqCst),
+            idle: shared.idle.load(Ordering::SeqCst),
+            queued: shared.queued.load(Ordering::SeqCst),
+            completed: shared.completed.load(Ordering::SeqCst),
+            panicked: shared.panicked.load(Ordering::SeqCst),
+        }
+    }
+
+    /// 不再接收新任务，等待队列中的任务执行完后结束所有 worker
+    pub fn shutdown(&mut self) {
+        self.close();
+
+        // join 期间可能有替换 panic worker 的新线程加入，直到没有线程为止
+        loop {
+            let threads = std::mem::take(&mut self.shared.lock().threads);
+            if threads.is_empty() {
+                break;
+            }
+            for thread in threads {
+                let _ = thread.join();
+            }
+        }
+    }
+
+    // 不再接收新任务，唤醒等待的 worker 和提交者，worker 执行完队列中的任务后退出
+    fn close(&self) {
+        self.shared.closed.store(true, Ordering::SeqCst);
+        let _state = self.shared.lock();
+        self.shared.not_empty.notify_all();
+        self.shared.not_full.notify_all();
     }
 }
 
 impl Drop for ThreadPool {
     fn drop(&mut self) {
-        // 关闭 sender 后，将关闭对应的 channel, 意味着不会再有任何消息被发送。随后，所有的处于无限 loop 的接收端将收到一个错误
-        drop(self.sender.take());
-
-        for worker in &mut self.workers {
-            println!("Shutting down worker {}", worker.id);
-            if let Some(thread) = worker.thread.take() {
-                thread.join().unwrap();
-            }
+        // 最后一个引用在任务中释放时不能 join 自己，只关闭线程池，worker 线程自行退出
+        if self.shared.in_worker() {
+            self.close();
+        } else {
+            self.shutdown();
         }
     }
 }
@@ -63,34 +367,346 @@ impl Drop for ThreadPool {
 
 struct Worker {
     id: usize,
-    // 对于 Option 类型，可以使用 take 方法拿走内部值的所有权
-    // JoinHandle 的 T 是传入的闭包任务所返回的，我们的任务无需任何返回，因此 T 直接使用 () 即可
-    thread: Option<thread::JoinHandle<()>>,
+    shared: Arc<Shared>,
+    // 正在执行任务，用于线程意外退出时修正计数
+    active: bool,
 }
 
 impl Worker {
-    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Job>>>) -> Self {
-        let thread = thread::spawn(move || loop {
-            // Mutex 结构体没有提供显式的 unlock, 要依赖作用域结束后的 drop 来自动释放
-            // 由于这里使用了 let, 右边的任何临时变量会在 let 语句结束后立即被 drop, 因此锁会自动释放
-            // recv 的调用过程是阻塞的
-            let message = receiver.lock().unwrap().recv();
-            // 这里锁已经释放
-            match message {
-                Ok(job) => {
-                    println!("Worker {id} got a job; executing");
-                    job();
+    // 调用者需持有锁
+    fn spawn(shared: &Arc<Shared>, state: &mut State) {
+        let id = state.next_id;
+        state.next_id += 1;
+        shared.workers.fetch_add(1, Ordering::SeqCst);
+        // 清理已退出的 worker 线程
+        state.threads.retain(|t| !t.is_finished());
+
+        let worker = Worker {
+            id,
+            shared: Arc::clone(shared),
+            active: false,
+        };
+        let thread = thread::Builder::new()
+            .name(format!("pool-worker-{id}"))
+            .spawn(move || worker.run())
+            .expect("failed to spawn worker thread");
+        state.threads.push(thread);
+    }
+
+    fn run(mut self) {
+        let shared = Arc::clone(&self.shared);
+        let local = LocalQueue::new_fifo();
+        shared
+            .stealers
+            .write()
+            .unwrap_or_else(|err| err.into_inner())
+            .push((self.id, local.stealer()));
+        LOCAL.with(|cell| *cell.borrow_mut() = Some((shared.id(), local)));
+
+        loop {
+            // 休眠前先短暂自旋，任务密集时避免频繁休眠和唤醒
+            let job = (0..SPIN_ROUNDS).find_map(|round| {
+                if round > 0 {
+                    thread::yield_now();
                 }
-                Err(_) => {
-                    println!("Worker {id} disconnected; shutting down");
-                    break;
+                LOCAL.with(|cell| {
+                    let local = cell.borrow();
+                    shared.find_job(&local.as_ref().unwrap().1)
+                })
+            });
+            let Some(job) = job else {
+                if self.park() {
+                    continue;
                 }
+                println!("Worker {} disconnected; shutting down", self.id);
+                return;
+            };
+            shared.job_taken();
+
+            shared.active.fetch_add(1, Ordering::SeqCst);
+            self.active = true;
+            // 捕获 job 中的 panic, worker 线程可以继续执行后面的任务
+            let result = panic::catch_unwind(AssertUnwindSafe(job));
+            shared.active.fetch_sub(1, Ordering::SeqCst);
+            self.active = false;
+            match result {
+                Ok(()) => shared.completed.fetch_add(1, Ordering::SeqCst),
+                Err(_) => shared.panicked.fetch_add(1, Ordering::SeqCst),
+            };
+        }
+    }
+
+    // 没有任务时休眠，返回 false 表示 worker 应该退出
+    fn park(&self) -> bool {
+        let shared = &self.shared;
+        let config = &shared.config;
+        // wait 时会释放锁，被唤醒后重新获取锁
+        let mut state = shared.lock();
+        shared.idle.fetch_add(1, Ordering::SeqCst);
+        let keep = loop {
+            // 任务可能正在被放入队列，或在其他 worker 的本地队列中
+            if shared.queued.load(Ordering::SeqCst) > 0 {
+                break true;
+            }
+            // 线程池已关闭且所有任务都已取完
+            if shared.closed.load(Ordering::SeqCst) {
+                break false;
+            }
+            let (guard, timeout) = shared
+                .not_empty
+                .wait_timeout(state, config.idle_timeout)
+                .unwrap_or_else(|err| err.into_inner());
+            state = guard;
+            // 空闲超时，多于 min_workers 的 worker 退出
+            if timeout.timed_out()
+                && shared.queued.load(Ordering::SeqCst) == 0
+                && shared.workers.load(Ordering::SeqCst) > config.min_workers
+            {
+                break false;
+            }
+        };
+        shared.idle.fetch_sub(1, Ordering::SeqCst);
+        if keep {
+            drop(state);
+            thread::yield_now();
+        } else {
+            shared.workers.fetch_sub(1, Ordering::SeqCst);
+        }
+        keep
+    }
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        let shared = &self.shared;
+        shared
+            .stealers
+            .write()
+            .unwrap_or_else(|err| err.into_inner())
+            .retain(|(id, _)| *id != self.id);
+        // 本地队列中剩余的任务放回全局队列
+        if let Some((_, local)) = LOCAL.with(|cell| cell.borrow_mut().take()) {
+            while let Some(job) = local.pop() {
+                shared.injector.push(job);
             }
+        }
+
+        // worker 线程因为 job 之外的原因 panic 时，修正计数并启动一个新的 worker 代替它
+        if !thread::panicking() {
+            return;
+        }
+        let mut state = shared.lock();
+        shared.workers.fetch_sub(1, Ordering::SeqCst);
+        if self.active {
+            shared.active.fetch_sub(1, Ordering::SeqCst);
+            shared.panicked.fetch_add(1, Ordering::SeqCst);
+        }
+        if !shared.closed.load(Ordering::SeqCst) || shared.queued.load(Ordering::SeqCst) > 0 {
+            Worker::spawn(&self.shared, &mut state);
+        }
+        shared.not_empty.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    // 提交一个阻塞 worker 的任务，返回用于放行的 sender
+    fn block_worker(pool: &ThreadPool) -> mpsc::Sender<()> {
+        let (tx, rx) = mpsc::channel::<()>();
+        let (started_tx, started_rx) = mpsc::channel();
+        pool.execute(move || {
+            started_tx.send(()).unwrap();
+            let _ = rx.recv();
+        })
+        .unwrap();
+        started_rx.recv().unwrap();
+        tx
+    }
+
+    #[test]
+    fn test_pool_shutdown_drains_queue() {
+        let counter = Arc::new(AtomicUsize::new(0));
+        let mut pool = ThreadPool::new(2);
+        for _ in 0..20 {
+            let counter = Arc::clone(&counter);
+            pool.execute(move || {
+                thread::sleep(Duration::from_millis(1));
+                counter.fetch_add(1, Ordering::SeqCst);
+            })
+            .unwrap();
+        }
+        pool.shutdown();
+        assert_eq!(counter.load(Ordering::SeqCst), 20);
+        assert_eq!(pool.execute(|| {}), Err(PoolError::Closed));
+    }
+
+    #[test]
+    fn test_pool_reject() {
+        let pool = ThreadPool::with_queue(1, 2, QueuePolicy::Reject);
+        let release = block_worker(&pool);
+        pool.execute(|| {}).unwrap();
+        pool.execute(|| {}).unwrap();
+        assert_eq!(pool.execute(|| {}), Err(PoolError::Full));
+        assert_eq!(pool.queued(), 2);
+        release.send(()).unwrap();
+    }
+
+    #[test]
+    fn test_pool_drop_oldest() {
+        let done = Arc::new(Mutex::new(Vec::new()));
+        let mut pool = ThreadPool::with_queue(1, 2, QueuePolicy::DropOldest);
+        let release = block_worker(&pool);
+        for i in 0..4 {
+            let done = Arc::clone(&done);
+            pool.execute(move || done.lock().unwrap().push(i)).unwrap();
+        }
+        release.send(()).unwrap();
+        pool.shutdown();
+        assert_eq!(*done.lock().unwrap(), vec![2, 3]);
+    }
+
+    #[test]
+    fn test_pool_block() {
+        let pool = Arc::new(ThreadPool::with_queue(1, 1, QueuePolicy::Block));
+        let release = block_worker(&pool);
+        pool.execute(|| {}).unwrap();
+
+        // 队列已满，提交者阻塞直到 worker 取走任务
+        let (tx, rx) = mpsc::channel();
+        let submitter = {
+            let pool = Arc::clone(&pool);
+            thread::spawn(move || {
+                pool.execute(|| {}).unwrap();
+                tx.send(()).unwrap();
+            })
+        };
+        assert!(rx.recv_timeout(Duration::from_millis(50)).is_err());
+        release.send(()).unwrap();
+        rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        submitter.join().unwrap();
+    }
+
+    #[test]
+    fn test_pool_block_in_worker() {
+        let pool = Arc::new(ThreadPool::with_queue(1, 1, QueuePolicy::Block));
+        let (tx, rx) = mpsc::channel();
+        let inner = Arc::clone(&pool);
+        pool.execute(move || {
+            // 唯一的 worker 正在执行这个任务，队列满时不能等待其他 worker 取走任务
+            inner.execute(|| {}).unwrap();
+            inner.execute(move || tx.send(()).unwrap()).unwrap();
+            assert_eq!(inner.queued(), 2);
+        })
+        .unwrap();
+        rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    }
+
+    #[test]
+    fn test_pool_drop_in_worker() {
+        let pool = Arc::new(ThreadPool::new(1));
+        let (tx, rx) = mpsc::channel();
+        let (release, wait) = mpsc::channel::<()>();
+        let (inner, queued) = (Arc::clone(&pool), tx.clone());
+        pool.execute(move || {
+            wait.recv().unwrap();
+            // 线程池的最后一个引用在 worker 中释放
+            drop(inner);
+            tx.send("dropped").unwrap();
+        })
+        .unwrap();
+        pool.execute(move || queued.send("queued").unwrap())
+            .unwrap();
+        drop(pool);
+        release.send(()).unwrap();
+        // 关闭后队列中的任务仍然会执行
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)), Ok("dropped"));
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)), Ok("queued"));
+    }
+
+    #[test]
+    fn test_pool_panic_recovery() {
+        let counter = Arc::new(AtomicUsize::new(0));
+        let mut pool = ThreadPool::new(1);
+        pool.execute(|| panic!("job panic")).unwrap();
+        for _ in 0..3 {
+            let counter = Arc::clone(&counter);
+            pool.execute(move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+            })
+            .unwrap();
+        }
+        pool.shutdown();
+        assert_eq!(counter.load(Ordering::SeqCst), 3);
+        let stats = pool.stats();
+        assert_eq!(stats.panicked, 1);
+        assert_eq!(stats.completed, 3);
+        assert_eq!(stats.workers, 0);
+    }
+
+    #[test]
+    fn test_pool_grow_and_shrink() {
+        let pool = ThreadPool::with_config(PoolConfig {
+            min_workers: 1,
+            max_workers: 3,
+            idle_timeout: Duration::from_millis(50),
+            ..PoolConfig::default()
         });
+        assert_eq!(pool.stats().workers, 1);
+
+        let releases: Vec<_> = (0..3).map(|_| block_worker(&pool)).collect();
+        let stats = pool.stats();
+        assert_eq!(stats.workers, 3);
+        assert_eq!(stats.active, 3);
+
+        // 达到 max_workers 后新任务在队列中等待
+        pool.execute(|| {}).unwrap();
+        assert_eq!(pool.stats().workers, 3);
+        assert_eq!(pool.stats().queued, 1);
+
+        for release in releases {
+            release.send(()).unwrap();
+        }
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while pool.stats().workers > 1 && std::time::Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        let stats = pool.stats();
+        assert_eq!(stats.workers, 1);
+        assert_eq!(stats.completed, 4);
+        assert_eq!(stats.queued, 0);
+    }
 
-        Worker {
-            id: id,
-            thread: Some(thread),
+    #[test]
+    fn test_pool_work_stealing() {
+        let pool = Arc::new(ThreadPool::new(4));
+        let counter = Arc::new(AtomicUsize::new(0));
+        let (tx, rx) = mpsc::channel();
+        {
+            let pool = Arc::clone(&pool);
+            let counter = Arc::clone(&counter);
+            pool.clone()
+                .execute(move || {
+                    // 在 worker 中提交的任务进入本地队列，当前 worker 阻塞时由其他 worker 窃取执行
+                    for _ in 0..100 {
+                        let counter = Arc::clone(&counter);
+                        pool.execute(move || {
+                            counter.fetch_add(1, Ordering::SeqCst);
+                        })
+                        .unwrap();
+                    }
+                    while counter.load(Ordering::SeqCst) < 100 {
+                        thread::sleep(Duration::from_millis(1));
+                    }
+                    tx.send(()).unwrap();
+                })
+                .unwrap();
         }
+        rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(counter.load(Ordering::SeqCst), 100);
     }
 }
@@ -0,0 +1,969 @@
+use crate::apps::webserver::config::Config;
+use crate::apps::webserver::http::{
+    normalize_path, Body, BodyStream, Headers, Method, Request, Response, StatusCode,
+};
+use async_std::future;
+use async_std::net::TcpStream;
+use bytes::Bytes;
+use futures::stream::{self, StreamExt};
+use futures::{AsyncReadExt, AsyncWriteExt};
+use std::io;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// 反向代理：把请求转发给一组 upstream, 支持轮询和最少连接两种负载均衡方式
+// 请求体和响应体都边读边转发：server 只读取请求头，请求体通过 Request::body_stream 逐段交给代理
+// 转发的请求体大小同样受 max-body-size 限制
+// refer: https://www.rfc-editor.org/rfc/rfc9110#name-intermediaries
+
+/// 连接 upstream 的超时时间
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+/// 等待 upstream 响应头，以及读取响应体时每次读取的超时时间
+pub const UPSTREAM_TIMEOUT: Duration = Duration::from_secs(60);
+/// 连续失败该次数后，upstream 在 FAIL_TIMEOUT 内不再被选中
+pub const MAX_FAILS: u32 = 3;
+pub const FAIL_TIMEOUT: Duration = Duration::from_secs(10);
+
+// upstream 响应头的最大字节数
+const MAX_RESPONSE_HEAD_SIZE: usize = 64 * 1024;
+const MAX_CHUNK_LINE_SIZE: usize = 1024;
+const READ_CHUNK_SIZE: usize = 8 * 1024;
+
+// 只对单个连接有效的 header, 代理不能转发
+// content-length 是 end-to-end 的，只在代理重新设置 body 的编码时去掉
+const HOP_BY_HOP: [&str; 7] = [
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// 负载均衡方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Balance {
+    RoundRobin,
+    /// 选择当前转发中的请求最少的 upstream
+    LeastConnections,
+}
+
+impl FromStr for Balance {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "round-robin" => Ok(Balance::RoundRobin),
+            "least-conn" => Ok(Balance::LeastConnections),
+            _ => Err(format!(
+                "unknown balance [{s}], expect round-robin or least-conn"
+            )),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Upstream {
+    addr: String,
+    // 正在转发的请求数，包括还在写回的响应体
+    active: AtomicUsize,
+    // 连续失败次数，成功后清零
+    fails: AtomicU32,
+    down_until: Mutex<Option<Instant>>,
+}
+
+impl Upstream {
+    fn is_up(&self) -> bool {
+        match *self.down_until.lock().unwrap() {
+            Some(until) => Instant::now() >= until,
+            None => true,
+        }
+    }
+
+    // 被动健康检查：根据转发的结果判断 upstream 是否可用
+    fn failed(&self, max_fails: u32, fail_timeout: Duration) {
+        let fails = self.fails.fetch_add(1, Ordering::SeqCst) + 1;
+        if fails >= max_fails {
+            self.fails.store(0, Ordering::SeqCst);
+            *self.down_until.lock().unwrap() = Some(Instant::now() + fail_timeout);
+            eprintln!("proxy: upstream {} is down for {fail_timeout:?}", self.addr);
+        }
+    }
+
+    fn succeeded(&self) {
+        self.fails.store(0, Ordering::SeqCst);
+        *self.down_until.lock().unwrap() = None;
+    }
+}
+
+// 一次转发，期间计入 upstream 的 active, 响应体读完时记录成功，读取出错时记录失败
+// 客户端提前断开时直接 drop, 不影响 upstream 的健康状态
+struct ActiveGuard {
+    upstream: Arc<Upstream>,
+    max_fails: u32,
+    fail_timeout: Duration,
+}
+
+impl ActiveGuard {
+    fn new(upstream: &Arc<Upstream>, max_fails: u32, fail_timeout: Duration) -> Self {
+        upstream.active.fetch_add(1, Ordering::SeqCst);
+        ActiveGuard {
+            upstream: Arc::clone(upstream),
+            max_fails,
+            fail_timeout,
+        }
+    }
+
+    fn succeeded(self) {
+        self.upstream.succeeded();
+    }
+
+    fn failed(self, err: &io::Error) {
+        eprintln!("proxy: upstream {} error: {err}", self.upstream.addr);
+        self.upstream.failed(self.max_fails, self.fail_timeout);
+    }
+}
+
+impl Drop for ActiveGuard {
+    fn drop(&mut self) {
+        self.upstream.active.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// 转发失败的原因，读取客户端的请求体出错不计入 upstream 的失败
+enum ExchangeError {
+    Client(io::Error),
+    Upstream(io::Error),
+}
+
+impl From<io::Error> for ExchangeError {
+    fn from(err: io::Error) -> Self {
+        ExchangeError::Upstream(err)
+    }
+}
+
+pub struct Proxy {
+    upstreams: Vec<Arc<Upstream>>,
+    balance: Balance,
+    next: AtomicUsize,
+    connect_timeout: Duration,
+    timeout: Duration,
+    max_fails: u32,
+    fail_timeout: Duration,
+    retries: usize,
+    strip_prefix: Option<String>,
+}
+
+impl Proxy {
+    /// 默认在连接失败时依次尝试其他 upstream
+    pub fn new(addrs: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        let upstreams: Vec<_> = addrs
+            .into_iter()
+            .map(|addr| {
+                Arc::new(Upstream {
+                    addr: addr.into(),
+                    active: AtomicUsize::new(0),
+                    fails: AtomicU32::new(0),
+                    down_until: Mutex::new(None),
+                })
+            })
+            .collect();
+        assert!(
+            !upstreams.is_empty(),
+            "proxy requires at least one upstream"
+        );
+        Proxy {
+            retries: upstreams.len() - 1,
+            upstreams,
+            balance: Balance::RoundRobin,
+            next: AtomicUsize::new(0),
+            connect_timeout: CONNECT_TIMEOUT,
+            timeout: UPSTREAM_TIMEOUT,
+            max_fails: MAX_FAILS,
+            fail_timeout: FAIL_TIMEOUT,
+            strip_prefix: None,
+        }
+    }
+
+    /// 没有配置 upstream 时返回 None
+    pub fn from_config(config: &Config) -> Option<Proxy> {
+        if config.upstreams.is_empty() {
+            return None;
+        }
+        let proxy = Proxy::new(config.upstreams.iter().cloned())
+            .balance(config.balance)
+            .strip_prefix(&config.proxy_prefix);
+        Some(proxy)
+    }
+
+    pub fn balance(mut self, balance: Balance) -> Self {
+        self.balance = balance;
+        self
+    }
+
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn max_fails(mut self, max_fails: u32, fail_timeout: Duration) -> Self {
+        self.max_fails = max_fails.max(1);
+        self.fail_timeout = fail_timeout;
+        self
+    }
+
+    /// 转发时去掉路径的前缀，如 prefix 为 /api 时 /api/users 转发为 /users
+    pub fn strip_prefix(mut self, prefix: &str) -> Self {
+        let prefix = normalize_path(prefix).unwrap_or_else(|| prefix.to_string());
+        let prefix = prefix.trim_end_matches('/');
+        self.strip_prefix = (!prefix.is_empty()).then(|| prefix.to_string());
+        self
+    }
+
+    /// 连接失败时最多再尝试的 upstream 数
+    pub fn retries(mut self, retries: usize) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// 转发请求，所有 upstream 都无法连接时返回 502, 等待响应超时返回 504
+    pub async fn forward(&self, mut req: Request) -> Response {
+        // 请求体 stream 在连接 upstream 成功后才开始读取，连接失败时仍然可以重试
+        let stream = req.body_stream.take();
+        let head = request_head(&req, &self.upstream_path(&req.path), stream.is_some());
+        let mut tried = Vec::new();
+        while tried.len() <= self.retries {
+            let Some(i) = self.select(&tried) else {
+                break;
+            };
+            tried.push(i);
+            let upstream = &self.upstreams[i];
+
+            let connected =
+                future::timeout(self.connect_timeout, TcpStream::connect(&upstream.addr)).await;
+            let conn = match connected {
+                Ok(Ok(conn)) => conn,
+                // 请求还没有发送，可以安全地重试下一个 upstream
+                Ok(Err(err)) => {
+                    eprintln!("proxy: connect {} error: {err}", upstream.addr);
+                    upstream.failed(self.max_fails, self.fail_timeout);
+                    continue;
+                }
+                Err(_) => {
+                    eprintln!("proxy: connect {} timeout", upstream.addr);
+                    upstream.failed(self.max_fails, self.fail_timeout);
+                    continue;
+                }
+            };
+
+            let guard = ActiveGuard::new(upstream, self.max_fails, self.fail_timeout);
+            return match self.exchange(conn, &req, &head, stream, guard).await {
+                // 有响应体时，在响应体读完后才记录结果
+                Ok(resp) => resp,
+                // 请求体不完整，server 会关闭连接
+                Err(ExchangeError::Client(err)) => {
+                    eprintln!("proxy: read request body error: {err}");
+                    Response::text(StatusCode::BadRequest, "incomplete request body")
+                }
+                Err(ExchangeError::Upstream(err)) => {
+                    eprintln!("proxy: upstream {} error: {err}", upstream.addr);
+                    upstream.failed(self.max_fails, self.fail_timeout);
+                    if err.kind() == io::ErrorKind::TimedOut {
+                        Response::text(StatusCode::GatewayTimeout, "upstream timeout")
+                    } else {
+                        Response::text(StatusCode::BadGateway, "bad gateway")
+                    }
+                }
+            };
+        }
+        Response::text(StatusCode::BadGateway, "no upstream available")
+    }
+
+    fn upstream_path(&self, path: &str) -> String {
+        let Some(rest) = self
+            .strip_prefix
+            .as_deref()
+            .and_then(|p| path.strip_prefix(p))
+        else {
+            return path.to_string();
+        };
+        if rest.starts_with('/') {
+            rest.to_string()
+        } else {
+            format!("/{rest}")
+        }
+    }
+
+    // 从可用且没有尝试过的 upstream 中选择一个，都不可用时返回 None
+    fn select(&self, tried: &[usize]) -> Option<usize> {
+        // 在可用的 upstream 中轮询，不可用的 upstream 的请求平均分给其他 upstream
+        let candidates: Vec<usize> = (0..self.upstreams.len())
+            .filter(|i| !tried.contains(i) && self.upstreams[*i].is_up())
+            .collect();
+        if candidates.is_empty() {
+            return None;
+        }
+        let start = self.next.fetch_add(1, Ordering::Relaxed) % candidates.len();
+        let mut rotated = candidates[start..]
+            .iter()
+            .chain(&candidates[..start])
+            .copied();
+        match self.balance {
+            Balance::RoundRobin => rotated.next(),
+            // 连接数相同时按轮询的顺序选择
+            Balance::LeastConnections => {
+                rotated.min_by_key(|i| self.upstreams[*i].active.load(Ordering::SeqCst))
+            }
+        }
+    }
+
+    async fn exchange(
+        &self,
+        mut conn: TcpStream,
+        req: &Request,
+        head: &str,
+        stream: Option<BodyStream>,
+        guard: ActiveGuard,
+    ) -> Result<Response, ExchangeError> {
+        let timeout = self.timeout;
+        with_timeout(timeout, conn.write_all(head.as_bytes())).await?;
+        match stream {
+            // 超时只针对写入 upstream, 等待客户端的时间由 server 的 body timeout 限制
+            Some(mut stream) => {
+                let chunked = !req.headers.contains("content-length");
+                while let Some(chunk) = stream.next().await {
+                    let chunk = chunk.map_err(ExchangeError::Client)?;
+                    if chunk.is_empty() {
+                        continue;
+                    }
+                    if chunked {
+                        let size = format!("{:x}\r\n", chunk.len());
+                        with_timeout(timeout, conn.write_all(size.as_bytes())).await?;
+                    }
+                    with_timeout(timeout, conn.write_all(&chunk)).await?;
+                    if chunked {
+                        with_timeout(timeout, conn.write_all(b"\r\n")).await?;
+                    }
+                }
+                if chunked {
+                    with_timeout(timeout, conn.write_all(b"0\r\n\r\n")).await?;
+                }
+            }
+            None => with_timeout(timeout, conn.write_all(&req.body)).await?,
+        }
+        with_timeout(timeout, conn.flush()).await?;
+
+        let mut buf = Vec::new();
+        let (status, headers) = loop {
+            let (status, headers) = with_timeout(timeout, read_head(&mut conn, &mut buf)).await?;
+            // 跳过 100 Continue 等中间响应
+            if !(100..200).contains(&status.as_u16()) {
+                break (status, headers);
+            }
+        };
+
+        let framing = if req.method == Method::Head || !status.allows_body() {
+            Framing::Done
+        } else if headers
+            .get_all("transfer-encoding")
+            .any(|v| v.to_ascii_lowercase().contains("chunked"))
+        {
+            Framing::ChunkSize
+        } else if let Some(len) = headers.get("content-length") {
+            let len = len
+                .trim()
+                .parse()
+                .map_err(|_| invalid_data("invalid content-length"))?;
+            Framing::Length(len)
+        } else {
+            Framing::Eof
+        };
+
+        let mut resp = Response::new(status);
+        resp.headers = forward_headers(&headers);
+        // 没有 body 时保留 upstream 的 Content-Length, 如 HEAD 请求的响应
+        if framing == Framing::Done {
+            guard.succeeded();
+            return Ok(resp.without_body());
+        }
+        // 响应体以 chunked 编码或关闭连接的方式写回
+        resp.headers.remove("content-length");
+        let body = UpstreamBody {
+            conn,
+            buf,
+            framing,
+            timeout,
+            guard,
+        };
+        // 逐块写回客户端，出错时中断响应
+        let stream = stream::unfold(Some(body), |body| async move {
+            let mut body = body?;
+            match body.next_chunk().await {
+                Ok(Some(chunk)) => Some((Ok(chunk), Some(body))),
+                Ok(None) => {
+                    body.guard.succeeded();
+                    None
+                }
+                Err(err) => {
+                    body.guard.failed(&err);
+                    Some((Err(err), None))
+                }
+            }
+        });
+        resp.body = Body::Stream(stream.boxed());
+        Ok(resp)
+    }
+}
+
+// 转发给 upstream 的请求行和 headers, 每个请求使用新的连接
+// 边读边转发请求体时保留客户端的 Content-Length, 客户端使用 chunked 编码时重新以 chunked 编码发送
+fn request_head(req: &Request, path: &str, streamed: bool) -> String {
+    let target = match &req.query {
+        Some(query) => format!("{path}?{query}"),
+        None => path.to_string(),
+    };
+    let mut head = format!("{} {} HTTP/1.1\r\n", req.method.as_str(), target);
+    let mut headers = forward_headers(&req.headers);
+    // 不等待 upstream 的 100 Continue, 直接发送请求体
+    headers.remove("expect");
+    headers.remove("content-length");
+    if let Some(addr) = req.remote_addr {
+        let forwarded = match req.header("x-forwarded-for") {
+            Some(prev) => format!("{prev}, {}", addr.ip()),
+            None => addr.ip().to_string(),
+        };
+        headers.set("X-Forwarded-For", forwarded);
+    }
+    if let Some(host) = req.header("host") {
+        if !headers.contains("x-forwarded-host") {
+            headers.set("X-Forwarded-Host", host);
+        }
+    }
+    for (name, value) in headers.iter() {
+        head.push_str(&format!("{name}: {value}\r\n"));
+    }
+    if streamed {
+        match req.header("content-length") {
+            Some(len) => head.push_str(&format!("Content-Length: {}\r\n", len.trim())),
+            None => head.push_str("Transfer-Encoding: chunked\r\n"),
+        }
+    } else if !req.body.is_empty()
+        || matches!(req.method, Method::Post | Method::Put | Method::Patch)
+    {
+        head.push_str(&format!("Content-Length: {}\r\n", req.body.len()));
+    }
+    head.push_str("Connection: close\r\n\r\n");
+    head
+}
+
+// 去掉 hop-by-hop header, 以及 Connection 中列出的 header
+fn forward_headers(headers: &Headers) -> Headers {
+    let listed: Vec<String> = headers
+        .get_all("connection")
+        .flat_map(|v| v.split(','))
+        .map(|v| v.trim().to_ascii_lowercase())
+        .collect();
+    let mut forwarded = Headers::new();
+    for (name, value) in headers.iter() {
+        let lower = name.to_ascii_lowercase();
+        if !HOP_BY_HOP.contains(&lower.as_str()) && !listed.contains(&lower) {
+            forwarded.append(name, value);
+        }
+    }
+    forwarded
+}
+
+// 读取并解析响应头，buf 中保留响应头之后已读取的数据
+async fn read_head(conn: &mut TcpStream, buf: &mut Vec<u8>) -> io::Result<(StatusCode, Headers)> {
+    let mut chunk = [0u8; READ_CHUNK_SIZE];
+    let head_end = loop {
+        if let Some(pos) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            break pos;
+        }
+        if buf.len() > MAX_RESPONSE_HEAD_SIZE {
+            return Err(invalid_data("response head too large"));
+        }
+        let n = conn.read(&mut chunk).await?;
+        if n == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    };
+    let head = String::from_utf8(buf.drain(..head_end + 4).collect())
+        .map_err(|_| invalid_data("invalid utf-8 in response head"))?;
+
+    let mut lines = head.trim_end().split("\r\n");
+    let status_line = lines.next().unwrap_or_default();
+    let code = match status_line.split(' ').collect::<Vec<_>>()[..] {
+        [version, code, ..] if version.starts_with("HTTP/1.") => code,
+        _ => return Err(invalid_data("invalid status line")),
+    };
+    let code = code
+        .parse::<u16>()
+        .ok()
+        .filter(|code| (100..600).contains(code))
+        .ok_or_else(|| invalid_data("invalid status code"))?;
+    let mut headers = Headers::new();
+    for line in lines {
+        let (name, value) = line
+            .split_once(':')
+            .ok_or_else(|| invalid_data("invalid header line"))?;
+        headers.append(name.trim(), value.trim());
+    }
+    Ok((StatusCode::from_u16(code), headers))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Framing {
+    /// 剩余的字节数
+    Length(u64),
+    ChunkSize,
+    /// 当前 chunk 剩余的字节数
+    ChunkData(u64),
+    // chunk data 之后的 CRLF
+    ChunkEnd,
+    Trailers,
+    /// 读到连接关闭为止
+    Eof,
+    Done,
+}
+
+// upstream 的响应体，按 framing 解码后逐块返回
+struct UpstreamBody {
+    conn: TcpStream,
+    buf: Vec<u8>,
+    framing: Framing,
+    timeout: Duration,
+    guard: ActiveGuard,
+}
+
+impl UpstreamBody {
+    async fn next_chunk(&mut self) -> io::Result<Option<Bytes>> {
+        loop {
+            match self.framing {
+                Framing::Done | Framing::Length(0) => {
+                    self.framing = Framing::Done;
+                    return Ok(None);
+                }
+                Framing::Length(n) => {
+                    let chunk = self.take(n).await?;
+                    self.framing = Framing::Length(n - chunk.len() as u64);
+                    return Ok(Some(chunk));
+                }
+                Framing::ChunkData(n) => {
+                    let chunk = self.take(n).await?;
+                    let remaining = n - chunk.len() as u64;
+                    self.framing = if remaining == 0 {
+                        Framing::ChunkEnd
+                    } else {
+                        Framing::ChunkData(remaining)
+                    };
+                    return Ok(Some(chunk));
+                }
+                Framing::Eof => {
+                    if self.buf.is_empty() && self.read().await? == 0 {
+                        self.framing = Framing::Done;
+                        return Ok(None);
+                    }
+                    return Ok(Some(Bytes::from(std::mem::take(&mut self.buf))));
+                }
+                Framing::ChunkSize => {
+                    let line = self.line().await?;
+                    let size = line.split(';').next().unwrap_or_default().trim();
+                    let size = u64::from_str_radix(size, 16)
+                        .map_err(|_| invalid_data("invalid chunk size"))?;
+                    self.framing = if size == 0 {
+                        Framing::Trailers
+                    } else {
+                        Framing::ChunkData(size)
+                    };
+                }
+                Framing::ChunkEnd => {
+                    if !self.line().await?.is_empty() {
+                        return Err(invalid_data("invalid chunk end"));
+                    }
+                    self.framing = Framing::ChunkSize;
+                }
+                // trailer 不转发
+                Framing::Trailers => {
+                    if self.line().await?.is_empty() {
+                        self.framing = Framing::Done;
+                    }
+                }
+            }
+        }
+    }
+
+    // 返回 buf 中最多 n 个字节，buf 为空时先读取
+    async fn take(&mut self, n: u64) -> io::Result<Bytes> {
+        if self.buf.is_empty() && self.read().await? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let len = self.buf.len().min(n.try_into().unwrap_or(usize::MAX));
+        Ok(Bytes::from(self.buf.drain(..len).collect::<Vec<_>>()))
+    }
+
+    // 读取一行，不包括 CRLF
+    async fn line(&mut self) -> io::Result<String> {
+        loop {
+            if let Some(pos) = self.buf.windows(2).position(|w| w == b"\r\n") {
+                let line: Vec<u8> = self.buf.drain(..pos + 2).take(pos).collect();
+                return String::from_utf8(line).map_err(|_| invalid_data("invalid chunk line"));
+            }
+            if self.buf.len() > MAX_CHUNK_LINE_SIZE {
+                return Err(invalid_data("chunk line too long"));
+            }
+            if self.read().await? == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+        }
+    }
+
+    async fn read(&mut self) -> io::Result<usize> {
+        let mut chunk = [0u8; READ_CHUNK_SIZE];
+        let n = with_timeout(self.timeout, self.conn.read(&mut chunk)).await?;
+        self.buf.extend_from_slice(&chunk[..n]);
+        Ok(n)
+    }
+}
+
+async fn with_timeout<T>(
+    timeout: Duration,
+    f: impl std::future::Future<Output = io::Result<T>>,
+) -> io::Result<T> {
+    future::timeout(timeout, f)
+        .await
+        .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))?
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apps::webserver::http::parse_request;
+    use async_std::net::TcpListener;
+    use async_std::task;
+    use futures::SinkExt;
+    use std::net::SocketAddr;
+
+    // 启动一个 upstream, 对每个连接读取请求后返回 respond 生成的响应并关闭连接
+    async fn upstream(respond: fn(Request) -> Vec<u8>) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        task::spawn(async move {
+            loop {
+                let Ok((mut conn, _)) = listener.accept().await else {
+                    break;
+                };
+                task::spawn(async move {
+                    let mut buf = Vec::new();
+                    let mut chunk = [0u8; 1024];
+                    let req = loop {
+                        if let Ok(Some((req, _))) = parse_request(&buf) {
+                            break req;
+                        }
+                        match conn.read(&mut chunk).await {
+                            Ok(n) if n > 0 => buf.extend_from_slice(&chunk[..n]),
+                            _ => return,
+                        }
+                    };
+                    let _ = conn.write_all(&respond(req)).await;
+                });
+            }
+        });
+        addr
+    }
+
+    // 绑定后立即关闭，连接这个地址会失败
+    async fn closed_addr() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        listener.local_addr().unwrap()
+    }
+
+    fn request(raw: &str) -> Request {
+        let mut req = parse_request(raw.as_bytes()).unwrap().unwrap().0;
+        req.remote_addr = Some("127.0.0.1:50000".parse().unwrap());
+        req
+    }
+
+    async fn body(resp: Response) -> String {
+        let data = match resp.body {
+            Body::Empty => Vec::new(),
+            Body::Stream(stream) => stream
+                .map(Result::unwrap)
+                .collect::<Vec<_>>()
+                .await
+                .concat(),
+            body => panic!("unexpected body {body:?}"),
+        };
+        String::from_utf8(data).unwrap()
+    }
+
+    // 把收到的请求原样作为响应体返回
+    fn echo(req: Request) -> Vec<u8> {
+        let mut head = format!("{} {}", req.method, req.path);
+        if let Some(query) = &req.query {
+            head.push_str(&format!("?{query}"));
+        }
+        let mut names: Vec<_> = req
+            .headers
+            .iter()
+            .map(|(n, v)| format!("{n}: {v}"))
+            .collect();
+        names.sort();
+        let body = format!(
+            "{head}\n{}\n\n{}",
+            names.join("\n"),
+            String::from_utf8_lossy(&req.body)
+        );
+        format!(
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\nX-Upstream: echo\r\n\r\n{body}",
+            body.len()
+        )
+        .into_bytes()
+    }
+
+    #[async_std::test]
+    async fn test_proxy_forward() {
+        let proxy = Proxy::new([upstream(echo).await.to_string()]);
+        let req = request(
+            "POST /api/items?page=2 HTTP/1.1\r\nHost: example.com\r\n\
+            Connection: keep-alive, X-Hop\r\nX-Hop: 1\r\nX-Forwarded-For: 10.0.0.1\r\n\
+            Transfer-Encoding: chunked\r\n\r\n4\r\nping\r\n0\r\n\r\n",
+        );
+        let resp = proxy.forward(req).await;
+        assert_eq!(resp.status, StatusCode::Ok);
+        assert_eq!(resp.headers.get("x-upstream"), Some("echo"));
+        assert!(!resp.headers.contains("connection"));
+        assert!(!resp.headers.contains("content-length"));
+        assert_eq!(
+            body(resp).await,
+            "POST /api/items?page=2\n\
+            Connection: close\n\
+            Content-Length: 4\n\
+            Host: example.com\n\
+            X-Forwarded-For: 10.0.0.1, 127.0.0.1\n\
+            X-Forwarded-Host: example.com\n\nping"
+        );
+
+        let proxy = proxy.strip_prefix("/api/");
+        let resp = proxy
+            .forward(request("GET /api HTTP/1.1\r\nHost: a\r\n\r\n"))
+            .await;
+        assert!(body(resp).await.starts_with("GET /\n"));
+        let resp = proxy
+            .forward(request("GET /api/a/b/?x=1 HTTP/1.1\r\nHost: a\r\n\r\n"))
+            .await;
+        assert!(body(resp).await.starts_with("GET /a/b/?x=1\n"));
+
+        // HEAD 请求的响应没有 body, 保留 upstream 的 Content-Length
+        let addr = upstream(|_| b"HTTP/1.1 200 OK\r\nContent-Length: 42\r\n\r\n".to_vec()).await;
+        let resp = Proxy::new([addr.to_string()])
+            .forward(request("HEAD / HTTP/1.1\r\nHost: a\r\n\r\n"))
+            .await;
+        assert_eq!(resp.status, StatusCode::Ok);
+        assert!(matches!(resp.body, Body::Empty));
+        let mut out = Vec::new();
+        resp.write_to(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("\r\nContent-Length: 42\r\n"), "resp: {out}");
+        assert!(out.ends_with("\r\n\r\n"));
+    }
+
+    #[async_std::test]
+    async fn test_proxy_stream_request_body() {
+        // upstream 收到第一段请求体后才发送第二段，整体读取请求体时会一直等待
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (received_tx, received_rx) = futures::channel::oneshot::channel();
+        task::spawn(async move {
+            let (mut conn, _) = listener.accept().await.unwrap();
+            let mut buf = Vec::new();
+            let mut chunk = [0u8; 1024];
+            let mut received_tx = Some(received_tx);
+            while !buf.ends_with(b"0\r\n\r\n") {
+                let n = conn.read(&mut chunk).await.unwrap();
+                assert!(n > 0, "incomplete request: {buf:?}");
+                buf.extend_from_slice(&chunk[..n]);
+                if buf.ends_with(b"4\r\nping\r\n") {
+                    received_tx.take().unwrap().send(()).unwrap();
+                }
+            }
+            let head_end = buf.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+            let head = String::from_utf8_lossy(&buf[..head_end]).to_string();
+            assert!(
+                head.contains("\r\nTransfer-Encoding: chunked"),
+                "head: {head}"
+            );
+            let body = &buf[head_end + 4..];
+            let mut resp =
+                format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n", body.len()).into_bytes();
+            resp.extend_from_slice(body);
+            conn.write_all(&resp).await.unwrap();
+        });
+
+        let proxy = Proxy::new([addr.to_string()]);
+        let mut req =
+            request("POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n");
+        let (mut tx, rx) = futures::channel::mpsc::channel(1);
+        req.body_stream = Some(rx);
+        let forwarded = task::spawn(async move { body(proxy.forward(req).await).await });
+        tx.send(Ok(Bytes::from("ping"))).await.unwrap();
+        future::timeout(Duration::from_secs(1), received_rx)
+            .await
+            .expect("request body is not streamed")
+            .unwrap();
+        tx.send(Ok(Bytes::from("pong"))).await.unwrap();
+        drop(tx);
+        assert_eq!(forwarded.await, "4\r\nping\r\n4\r\npong\r\n0\r\n\r\n");
+    }
+
+    #[async_std::test]
+    async fn test_proxy_stream_request_body_length() {
+        // 客户端发送了 Content-Length 时原样转发
+        let proxy = Proxy::new([upstream(echo).await.to_string()]);
+        let mut req = request("PUT / HTTP/1.1\r\nHost: a\r\nContent-Length: 8\r\n\r\npingpong");
+        let (mut tx, rx) = futures::channel::mpsc::channel(2);
+        tx.send(Ok(Bytes::from("ping"))).await.unwrap();
+        tx.send(Ok(Bytes::from("pong"))).await.unwrap();
+        drop(tx);
+        req.body = Vec::new();
+        req.body_stream = Some(rx);
+        let echoed = body(proxy.forward(req).await).await;
+        assert!(echoed.contains("\nContent-Length: 8\n"), "echoed: {echoed}");
+        assert!(echoed.ends_with("\n\npingpong"), "echoed: {echoed}");
+
+        // 客户端的请求体不完整时不计入 upstream 的失败
+        let mut req = request("PUT / HTTP/1.1\r\nHost: a\r\nContent-Length: 8\r\n\r\npingpong");
+        let (mut tx, rx) = futures::channel::mpsc::channel(2);
+        tx.send(Ok(Bytes::from("ping"))).await.unwrap();
+        tx.send(Err(io::ErrorKind::UnexpectedEof.into()))
+            .await
+            .unwrap();
+        req.body_stream = Some(rx);
+        let resp = proxy.forward(req).await;
+        assert_eq!(resp.status, StatusCode::BadRequest);
+        assert_eq!(proxy.upstreams[0].fails.load(Ordering::SeqCst), 0);
+        assert_eq!(proxy.upstreams[0].active.load(Ordering::SeqCst), 0);
+    }
+
+    #[async_std::test]
+    async fn test_proxy_chunked_response() {
+        let addr = upstream(|_| {
+            b"HTTP/1.1 100 Continue\r\n\r\n\
+            HTTP/1.1 418 I'm a teapot\r\nTransfer-Encoding: chunked\r\nTrailer: X-Sum\r\n\r\n\
+            3\r\nabc\r\n2;ext=1\r\nde\r\n0\r\nX-Sum: 5\r\n\r\n"
+                .to_vec()
+        })
+        .await;
+        let proxy = Proxy::new([addr.to_string()]);
+        let resp = proxy
+            .forward(request("GET / HTTP/1.1\r\nHost: a\r\n\r\n"))
+            .await;
+        assert_eq!(resp.status, StatusCode::Other(418));
+        assert!(!resp.headers.contains("transfer-encoding"));
+        assert!(!resp.headers.contains("trailer"));
+        assert_eq!(body(resp).await, "abcde");
+
+        // 响应体读完后才记录成功
+        proxy.upstreams[0].fails.store(1, Ordering::SeqCst);
+        let resp = proxy
+            .forward(request("GET / HTTP/1.1\r\nHost: a\r\n\r\n"))
+            .await;
+        assert_eq!(proxy.upstreams[0].active.load(Ordering::SeqCst), 1);
+        assert_eq!(proxy.upstreams[0].fails.load(Ordering::SeqCst), 1);
+        assert_eq!(body(resp).await, "abcde");
+        assert_eq!(proxy.upstreams[0].active.load(Ordering::SeqCst), 0);
+        assert_eq!(proxy.upstreams[0].fails.load(Ordering::SeqCst), 0);
+
+        // 响应不完整时中断响应体，并记录失败
+        let addr = upstream(|_| b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nabc".to_vec()).await;
+        let proxy = Proxy::new([addr.to_string()]);
+        let resp = proxy
+            .forward(request("GET / HTTP/1.1\r\nHost: a\r\n\r\n"))
+            .await;
+        assert_eq!(proxy.upstreams[0].fails.load(Ordering::SeqCst), 0);
+        let Body::Stream(stream) = resp.body else {
+            panic!("expect stream body");
+        };
+        let chunks: Vec<_> = stream.collect().await;
+        assert_eq!(chunks.len(), 2);
+        assert!(chunks[1].is_err());
+        assert_eq!(proxy.upstreams[0].fails.load(Ordering::SeqCst), 1);
+        assert_eq!(proxy.upstreams[0].active.load(Ordering::SeqCst), 0);
+    }
+
+    #[async_std::test]
+    async fn test_proxy_balance_and_health() {
+        fn named(name: &'static str) -> Vec<u8> {
+            format!("HTTP/1.1 200 OK\r\nContent-Length: 1\r\n\r\n{name}").into_bytes()
+        }
+        let a = upstream(|_| named("a")).await;
+        let b = upstream(|_| named("b")).await;
+        let dead = closed_addr().await;
+        let proxy = Proxy::new([dead, a, b].map(|addr| addr.to_string()))
+            .max_fails(2, Duration::from_secs(60));
+
+        // 连接失败时重试其他 upstream, 连续失败后不再选择
+        let mut names = Vec::new();
+        for _ in 0..6 {
+            let resp = proxy
+                .forward(request("GET / HTTP/1.1\r\nHost: a\r\n\r\n"))
+                .await;
+            assert_eq!(resp.status, StatusCode::Ok);
+            names.push(body(resp).await);
+        }
+        assert!(names.contains(&String::from("a")) && names.contains(&String::from("b")));
+        assert!(!proxy.upstreams[0].is_up());
+        assert!(proxy.upstreams[1].is_up() && proxy.upstreams[2].is_up());
+
+        // 轮询时依次选择可用的 upstream
+        let picks: Vec<_> = (0..4).map(|_| proxy.select(&[]).unwrap()).collect();
+        assert!(picks.windows(2).all(|w| w[0] != w[1]), "picks: {picks:?}");
+        assert!(!picks.contains(&0));
+
+        let proxy = proxy.balance(Balance::LeastConnections);
+        proxy.upstreams[0].succeeded();
+        proxy.upstreams[0].active.store(2, Ordering::SeqCst);
+        proxy.upstreams[2].active.store(1, Ordering::SeqCst);
+        assert_eq!(proxy.select(&[]), Some(1));
+        assert_eq!(proxy.select(&[1]), Some(2));
+        assert_eq!(proxy.select(&[0, 1, 2]), None);
+
+        // 所有 upstream 都无法连接
+        let proxy = Proxy::new([closed_addr().await.to_string()]);
+        let resp = proxy
+            .forward(request("GET / HTTP/1.1\r\nHost: a\r\n\r\n"))
+            .await;
+        assert_eq!(resp.status, StatusCode::BadGateway);
+    }
+
+    #[async_std::test]
+    async fn test_proxy_timeout() {
+        // 接受连接但不返回响应
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        task::spawn(async move {
+            let mut conns = Vec::new();
+            while let Ok((conn, _)) = listener.accept().await {
+                conns.push(conn);
+            }
+        });
+        let proxy = Proxy::new([addr.to_string()]).timeout(Duration::from_millis(50));
+        let resp = proxy
+            .forward(request("GET / HTTP/1.1\r\nHost: a\r\n\r\n"))
+            .await;
+        assert_eq!(resp.status, StatusCode::GatewayTimeout);
+    }
+}
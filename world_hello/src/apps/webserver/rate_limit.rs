@@ -0,0 +1,281 @@
+use crate::apps::webserver::config::Config;
+use crate::apps::webserver::http::{Request, Response, StatusCode};
+use crate::apps::webserver::middleware::{Context, Middleware};
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, OnceLock, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// 按客户端限制请求速率: 每个客户端一个令牌桶，以 rate 的速度补充令牌，最多积攒 burst 个
+// 每个请求消耗一个令牌，没有令牌时返回 429, Retry-After 为等到下一个令牌的秒数
+// refer: https://en.wikipedia.org/wiki/Token_bucket
+
+/// 后台清理空闲令牌桶的间隔
+pub const CLEANUP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// 区分客户端的方式
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RateKey {
+    /// 客户端 ip
+    Ip,
+    /// 请求头的值，如 X-Api-Key, 没有该请求头时按 ip 限制
+    Header(String),
+}
+
+impl FromStr for RateKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "ip" => Ok(RateKey::Ip),
+            Some(("header", name)) if !name.trim().is_empty() => {
+                Ok(RateKey::Header(name.trim().to_ascii_lowercase()))
+            }
+            _ => Err(format!(
+                "unknown rate limit key [{s}], expect ip or header:<name>"
+            )),
+        }
+    }
+}
+
+impl RateKey {
+    fn key(&self, req: &Request) -> Option<String> {
+        let ip = || req.remote_addr.map(|addr| addr.ip().to_string());
+        match self {
+            RateKey::Ip => ip(),
+            RateKey::Header(name) => req.header(name).map(String::from).or_else(ip),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct TokenBucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    // 补充从上次更新到 now 的令牌
+    fn refill(&mut self, rate: f64, burst: f64, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(burst);
+        self.updated = now;
+    }
+
+    /// 取出一个令牌，没有令牌时返回需要等待的时间
+    fn take(&mut self, rate: f64, burst: f64, now: Instant) -> Result<(), Duration> {
+        self.refill(rate, burst, now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / rate))
+        }
+    }
+}
+
+type Buckets = Mutex<HashMap<String, TokenBucket>>;
+
+/// 令牌桶限流 middleware, 不用于 single 模式
+pub struct RateLimit {
+    rate: f64,
+    burst: f64,
+    key: RateKey,
+    cleanup_interval: Duration,
+    buckets: Arc<Buckets>,
+    // 第一次处理请求时启动清理线程
+    cleaner: OnceLock<()>,
+}
+
+impl RateLimit {
+    /// 每秒补充 rate 个令牌，默认 burst 为 rate 向上取整
+    pub fn new(rate: f64) -> Self {
+        assert!(rate > 0.0, "rate must be positive");
+        RateLimit {
+            rate,
+            burst: rate.ceil(),
+            key: RateKey::Ip,
+            cleanup_interval: CLEANUP_INTERVAL,
+            buckets: Arc::new(Mutex::new(HashMap::new())),
+            cleaner: OnceLock::new(),
+        }
+    }
+
+    pub fn from_config(config: &Config) -> Option<Self> {
+        let rate = config.rate_limit?;
+        let mut limit = RateLimit::new(rate).key(config.rate_limit_key.clone());
+        if let Some(burst) = config.rate_burst {
+            limit = limit.burst(burst);
+        }
+        Some(limit)
+    }
+
+    /// 最多可以连续发送的请求数
+    pub fn burst(mut self, burst: usize) -> Self {
+        self.burst = burst.max(1) as f64;
+        self
+    }
+
+    pub fn key(mut self, key: RateKey) -> Self {
+        self.key = key;
+        self
+    }
+
+    pub fn cleanup_interval(mut self, interval: Duration) -> Self {
+        self.cleanup_interval = interval;
+        self
+    }
+
+    /// 当前保存的令牌桶数量
+    pub fn len(&self) -> usize {
+        self.buckets.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn check(&self, key: &str, now: Instant) -> Result<(), Duration> {
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets.entry(key.to_string()).or_insert(TokenBucket {
+            tokens: self.burst,
+            updated: now,
+        });
+        bucket.take(self.rate, self.burst, now)
+    }
+
+    // 清理线程只持有 Weak, RateLimit drop 之后线程在下一次醒来时退出
+    fn start_cleaner(&self) {
+        let buckets = Arc::downgrade(&self.buckets);
+        let (rate, burst, interval) = (self.rate, self.burst, self.cleanup_interval);
+        let spawned = thread::Builder::new()
+            .name(String::from("rate-limit-cleanup"))
+            .spawn(move || clean_periodically(buckets, rate, burst, interval));
+        if let Err(err) = spawned {
+            eprintln!("rate limit: spawn cleanup thread error: {err}");
+        }
+    }
+}
+
+fn clean_periodically(buckets: Weak<Buckets>, rate: f64, burst: f64, interval: Duration) {
+    loop {
+        thread::sleep(interval);
+        let Some(buckets) = buckets.upgrade() else {
+            return;
+        };
+        cleanup(&buckets, rate, burst, Instant::now());
+    }
+}
+
+// 移除已经补满的令牌桶，补满的桶与新建的桶没有区别
+fn cleanup(buckets: &Buckets, rate: f64, burst: f64, now: Instant) {
+    let mut buckets = buckets.lock().unwrap_or_else(|err| err.into_inner());
+    buckets.retain(|_, bucket| {
+        let mut bucket = *bucket;
+        bucket.refill(rate, burst, now);
+        bucket.tokens < burst
+    });
+}
+
+impl Middleware for RateLimit {
+    fn before(&self, req: &mut Request, _ctx: &mut Context) -> Option<Response> {
+        self.cleaner.get_or_init(|| self.start_cleaner());
+        // 无法区分客户端时不限制
+        let key = self.key.key(req)?;
+        let wait = self.check(&key, Instant::now()).err()?;
+        // Retry-After 以秒为单位，向上取整
+        let retry_after = wait.as_secs_f64().ceil().max(1.0) as u64;
+        let resp = Response::text(StatusCode::TooManyRequests, "too many requests")
+            .with_header("Retry-After", retry_after.to_string());
+        Some(resp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apps::webserver::http::parse_request;
+
+    fn request(raw: &str, ip: &str) -> Request {
+        let mut req = parse_request(raw.as_bytes()).unwrap().unwrap().0;
+        req.remote_addr = Some(format!("{ip}:4000").parse().unwrap());
+        req
+    }
+
+    #[test]
+    fn test_token_bucket() {
+        let limit = RateLimit::new(2.0).burst(3);
+        let now = Instant::now();
+        for _ in 0..3 {
+            assert!(limit.check("a", now).is_ok());
+        }
+        assert_eq!(limit.check("a", now), Err(Duration::from_millis(500)));
+        // 其他客户端不受影响
+        assert!(limit.check("b", now).is_ok());
+
+        // 每 500ms 补充一个令牌
+        let later = now + Duration::from_millis(600);
+        assert!(limit.check("a", later).is_ok());
+        assert_eq!(limit.check("a", later), Err(Duration::from_millis(400)));
+
+        // 补满的令牌桶被清理
+        assert_eq!(limit.len(), 2);
+        cleanup(&limit.buckets, 2.0, 3.0, now + Duration::from_millis(1000));
+        assert_eq!(limit.len(), 1);
+        cleanup(&limit.buckets, 2.0, 3.0, now + Duration::from_secs(2));
+        assert!(limit.is_empty());
+    }
+
+    #[test]
+    fn test_rate_limit_middleware() {
+        let limit = RateLimit::new(0.5).burst(2);
+        let raw = "GET / HTTP/1.1\r\nHost: a\r\n\r\n";
+        let mut req = request(raw, "10.0.0.1");
+        let mut ctx = Context::new(&req);
+        assert!(limit.before(&mut req, &mut ctx).is_none());
+        assert!(limit.before(&mut req, &mut ctx).is_none());
+        let resp = limit.before(&mut req, &mut ctx).unwrap();
+        assert_eq!(resp.status, StatusCode::TooManyRequests);
+        assert_eq!(resp.headers.get("retry-after"), Some("2"));
+        assert!(limit
+            .before(&mut request(raw, "10.0.0.2"), &mut ctx)
+            .is_none());
+
+        // 按请求头限制，同一个 ip 的不同 key 分别计数
+        let limit = RateLimit::new(1.0).key("header:X-Api-Key".parse().unwrap());
+        let raw_a = "GET / HTTP/1.1\r\nHost: a\r\nX-Api-Key: a\r\n\r\n";
+        let raw_b = "GET / HTTP/1.1\r\nHost: a\r\nX-Api-Key: b\r\n\r\n";
+        assert!(limit
+            .before(&mut request(raw_a, "10.0.0.1"), &mut ctx)
+            .is_none());
+        assert!(limit
+            .before(&mut request(raw_a, "10.0.0.2"), &mut ctx)
+            .is_some());
+        assert!(limit
+            .before(&mut request(raw_b, "10.0.0.1"), &mut ctx)
+            .is_none());
+        // 没有请求头时按 ip 限制
+        assert!(limit
+            .before(&mut request(raw, "10.0.0.1"), &mut ctx)
+            .is_none());
+        assert!(limit
+            .before(&mut request(raw, "10.0.0.1"), &mut ctx)
+            .is_some());
+
+        assert!("header:".parse::<RateKey>().is_err());
+        assert!("cookie".parse::<RateKey>().is_err());
+    }
+
+    #[test]
+    fn test_cleanup_thread() {
+        let limit = RateLimit::new(100.0).cleanup_interval(Duration::from_millis(20));
+        let raw = "GET / HTTP/1.1\r\nHost: a\r\n\r\n";
+        let mut req = request(raw, "10.0.0.1");
+        let mut ctx = Context::new(&req);
+        assert!(limit.before(&mut req, &mut ctx).is_none());
+        assert_eq!(limit.len(), 1);
+        thread::sleep(Duration::from_millis(100));
+        assert!(limit.is_empty());
+    }
+}
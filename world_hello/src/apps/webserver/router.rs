@@ -0,0 +1,237 @@
+use crate::apps::webserver::http::{percent_decode, Method, Request, Response, StatusCode};
+use std::collections::HashMap;
+
+// 按 method + path 模式注册 handler 的路由表
+// 模式中 `:name` 匹配一段路径，`*name` 匹配剩余的所有路径（只能放在最后）
+// handler 的类型由 server 决定，同步和异步的 server 可以共用
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Static(String),
+    Param(String),
+    Wildcard(String),
+}
+
+struct Route<H> {
+    method: Method,
+    segments: Vec<Segment>,
+    handler: H,
+}
+
+pub struct Router<H> {
+    routes: Vec<Route<H>>,
+    // 没有匹配的路由时使用，未设置时返回默认的 404 响应
+    fallback: Option<H>,
+}
+
+impl<H> Default for Router<H> {
+    fn default() -> Self {
+        Router {
+            routes: Vec::new(),
+            fallback: None,
+        }
+    }
+}
+
+impl<H> Router<H> {
+    pub fn new() -> Self {
+        Router::default()
+    }
+
+    pub fn route(mut self, method: Method, pattern: &str, handler: H) -> Self {
+        let segments = parse_pattern(pattern);
+        if let Some(pos) = segments
+            .iter()
+            .position(|s| matches!(s, Segment::Wildcard(_)))
+        {
+            assert!(
+                pos == segments.len() - 1,
+                "wildcard must be the last segment: {pattern}"
+            );
+        }
+        self.routes.push(Route {
+            method,
+            segments,
+            handler,
+        });
+        self
+    }
+
+    pub fn get(self, pattern: &str, handler: H) -> Self {
+        self.route(Method::Get, pattern, handler)
+    }
+
+    pub fn post(self, pattern: &str, handler: H) -> Self {
+        self.route(Method::Post, pattern, handler)
+    }
+
+    pub fn put(self, pattern: &str, handler: H) -> Self {
+        self.route(Method::Put, pattern, handler)
+    }
+
+    pub fn delete(self, pattern: &str, handler: H) -> Self {
+        self.route(Method::Delete, pattern, handler)
+    }
+
+    pub fn fallback(mut self, handler: H) -> Self {
+        self.fallback = Some(handler);
+        self
+    }
+
+    /// 查找请求对应的 handler, 并把路径参数写入 req.params
+    /// 路径不存在时返回 404, 路径存在但 method 不匹配时返回带 Allow header 的 405
+    pub fn resolve(&self, req: &mut Request) -> Result<&H, Response> {
+        let path: Vec<&str> = split_path(&req.path).collect();
+        let mut allowed = Vec::new();
+        for route in &self.routes {
+            let Some(params) = match_segments(&route.segments, &path) else {
+                continue;
+            };
+            // HEAD 请求可以使用 GET 的 handler
+            if route.method == req.method
+                || (req.method == Method::Head && route.method == Method::Get)
+            {
+                req.params = params;
+                return Ok(&route.handler);
+            }
+            if !allowed.contains(&route.method) {
+                allowed.push(route.method);
+            }
+        }
+
+        if !allowed.is_empty() {
+            if allowed.contains(&Method::Get) && !allowed.contains(&Method::Head) {
+                allowed.push(Method::Head);
+            }
+            let allow = allowed
+                .iter()
+                .map(|m| m.as_str())
+                .collect::<Vec<_>>()
+                .join(", ");
+            return Err(Response::new(StatusCode::MethodNotAllowed).with_header("Allow", allow));
+        }
+        match &self.fallback {
+            Some(handler) => Ok(handler),
+            None => Err(Response::text(StatusCode::NotFound, "not found")),
+        }
+    }
+}
+
+fn split_path(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|s| !s.is_empty())
+}
+
+fn parse_pattern(pattern: &str) -> Vec<Segment> {
+    split_path(pattern)
+        .map(|s| {
+            if let Some(name) = s.strip_prefix(':') {
+                Segment::Param(name.to_string())
+            } else if let Some(name) = s.strip_prefix('*') {
+                Segment::Wildcard(name.to_string())
+            } else {
+                Segment::Static(s.to_string())
+            }
+        })
+        .collect()
+}
+
+fn match_segments(segments: &[Segment], path: &[&str]) -> Option<HashMap<String, String>> {
+    let mut params = HashMap::new();
+    for (i, segment) in segments.iter().enumerate() {
+        match segment {
+            Segment::Static(s) => {
+                if path.get(i).map(|p| percent_decode(p)) != Some(s.clone()) {
+                    return None;
+                }
+            }
+            Segment::Param(name) => {
+                params.insert(name.clone(), percent_decode(path.get(i)?));
+            }
+            Segment::Wildcard(name) => {
+                // 剩余路径不做 decode, 由 handler 自己处理（如静态文件需要检查 `..`）
+                params.insert(name.clone(), path[i.min(path.len())..].join("/"));
+                return Some(params);
+            }
+        }
+    }
+    (segments.len() == path.len()).then_some(params)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apps::webserver::http::parse_request;
+
+    fn request(method: &str, target: &str) -> Request {
+        let raw = format!("{method} {target} HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        parse_request(raw.as_bytes()).unwrap().unwrap().0
+    }
+
+    fn router() -> Router<&'static str> {
+        Router::new()
+            .get("/", "index")
+            .get("/users/:id", "get_user")
+            .delete("/users/:id", "delete_user")
+            .post("/users", "create_user")
+            .get("/static/*path", "static")
+    }
+
+    #[test]
+    fn test_router_resolve() {
+        let router = router();
+
+        let mut req = request("GET", "/");
+        assert_eq!(router.resolve(&mut req).unwrap(), &"index");
+
+        let mut req = request("GET", "/users/a%20b?verbose=1");
+        assert_eq!(router.resolve(&mut req).unwrap(), &"get_user");
+        assert_eq!(req.param("id"), Some("a b"));
+        assert_eq!(req.query_param("verbose").as_deref(), Some("1"));
+
+        let mut req = request("DELETE", "/users/42/");
+        assert_eq!(router.resolve(&mut req).unwrap(), &"delete_user");
+        assert_eq!(req.param("id"), Some("42"));
+
+        let mut req = request("HEAD", "/static/css/site.css");
+        assert_eq!(router.resolve(&mut req).unwrap(), &"static");
+        assert_eq!(req.param("path"), Some("css/site.css"));
+
+        let mut req = request("GET", "/static");
+        assert_eq!(router.resolve(&mut req).unwrap(), &"static");
+        assert_eq!(req.param("path"), Some(""));
+    }
+
+    #[test]
+    fn test_router_not_found_and_method_not_allowed() {
+        let router = router();
+
+        let mut req = request("GET", "/users/1/posts");
+        let resp = router.resolve(&mut req).unwrap_err();
+        assert_eq!(resp.status, StatusCode::NotFound);
+
+        let mut req = request("PUT", "/users/1");
+        let resp = router.resolve(&mut req).unwrap_err();
+        assert_eq!(resp.status, StatusCode::MethodNotAllowed);
+        assert_eq!(resp.headers.get("allow"), Some("GET, DELETE, HEAD"));
+
+        let router = router.fallback("fallback");
+        let mut req = request("GET", "/missing");
+        assert_eq!(router.resolve(&mut req).unwrap(), &"fallback");
+    }
+
+    #[test]
+    fn test_query_params() {
+        let req = request("GET", "/search?q=rust+web&tag=a%26b&tag=c&empty");
+        assert_eq!(
+            req.query_params(),
+            vec![
+                ("q".to_string(), "rust web".to_string()),
+                ("tag".to_string(), "a&b".to_string()),
+                ("tag".to_string(), "c".to_string()),
+                ("empty".to_string(), "".to_string()),
+            ]
+        );
+        assert_eq!(req.query_param("tag").as_deref(), Some("a&b"));
+        assert_eq!(percent_decode("%e4%b8%ad%2"), "中%2");
+    }
+}
@@ -0,0 +1,175 @@
+use crate::apps::webserver::http::{Body, Request, Response, StatusCode};
+use async_std::future;
+use bytes::Bytes;
+use futures::stream::{self, BoxStream, Stream, StreamExt};
+use std::io;
+use std::time::Duration;
+
+// server-sent events: 保持连接，按 text/event-stream 格式逐个写出事件
+// refer: https://html.spec.whatwg.org/multipage/server-sent-events.html
+
+/// 没有事件时发送注释的间隔，避免代理或客户端因连接空闲而断开
+pub const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Event {
+    id: Option<String>,
+    event: Option<String>,
+    data: String,
+    retry: Option<Duration>,
+}
+
+impl Event {
+    /// data 中的换行会拆分为多个 data 行，客户端收到后会还原
+    pub fn data(data: impl Into<String>) -> Self {
+        Event {
+            data: data.into(),
+            ..Event::default()
+        }
+    }
+
+    /// 事件类型，客户端通过 addEventListener(event, ...) 接收，默认为 message
+    pub fn event(mut self, event: impl Into<String>) -> Self {
+        self.event = Some(event.into());
+        self
+    }
+
+    /// 客户端重连时通过 Last-Event-ID 发送最后收到的 id
+    pub fn id(mut self, id: impl Into<String>) -> Self {
+        self.id = Some(id.into());
+        self
+    }
+
+    /// 客户端断开后重连前等待的时间
+    pub fn retry(mut self, retry: Duration) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+
+    pub fn to_bytes(&self) -> Bytes {
+        let mut out = String::new();
+        // event 和 id 中不能包含换行，id 中也不能包含 NUL
+        if let Some(event) = &self.event {
+            out.push_str(&format!("event: {}\n", single_line(event)));
+        }
+        if let Some(id) = &self.id {
+            out.push_str(&format!("id: {}\n", single_line(id).replace('\0', "")));
+        }
+        if let Some(retry) = self.retry {
+            out.push_str(&format!("retry: {}\n", retry.as_millis()));
+        }
+        for line in self.data.split('\n') {
+            out.push_str(&format!("data: {}\n", line.trim_end_matches('\r')));
+        }
+        out.push('\n');
+        Bytes::from(out)
+    }
+}
+
+fn single_line(s: &str) -> String {
+    s.replace(['\r', '\n'], "")
+}
+
+/// 客户端重连时发送的最后一个事件 id
+pub fn last_event_id(req: &Request) -> Option<&str> {
+    req.header("last-event-id").map(str::trim)
+}
+
+/// text/event-stream 响应，events 结束时关闭响应
+pub struct Sse {
+    events: BoxStream<'static, Event>,
+    keep_alive: Option<Duration>,
+}
+
+impl Sse {
+    pub fn new(events: impl Stream<Item = Event> + Send + 'static) -> Self {
+        Sse {
+            events: events.boxed(),
+            keep_alive: Some(KEEP_ALIVE_INTERVAL),
+        }
+    }
+
+    /// 设置发送 keep-alive 注释的间隔，None 时不发送
+    pub fn keep_alive(mut self, interval: Option<Duration>) -> Self {
+        self.keep_alive = interval;
+        self
+    }
+
+    pub fn into_response(self) -> Response {
+        let keep_alive = self.keep_alive;
+        let body = stream::unfold(self.events, move |mut events| async move {
+            let next = match keep_alive {
+                // 超时只会丢弃 next 的 future, 不会丢失事件
+                Some(interval) => match future::timeout(interval, events.next()).await {
+                    Ok(event) => event.map(|e| e.to_bytes()),
+                    Err(_) => Some(Bytes::from_static(b": keep-alive\n\n")),
+                },
+                None => events.next().await.map(|e| e.to_bytes()),
+            };
+            next.map(|chunk| (Ok::<_, io::Error>(chunk), events))
+        });
+        Response::new(StatusCode::Ok)
+            .with_header("Content-Type", "text/event-stream")
+            .with_header("Cache-Control", "no-cache")
+            .with_body(Body::Stream(body.boxed()))
+    }
+}
+
+impl From<Sse> for Response {
+    fn from(sse: Sse) -> Self {
+        sse.into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apps::webserver::http::parse_request;
+
+    async fn body(resp: Response) -> String {
+        let Body::Stream(stream) = resp.body else {
+            panic!("expect stream body");
+        };
+        let chunks: Vec<_> = stream.map(Result::unwrap).collect().await;
+        String::from_utf8(chunks.concat()).unwrap()
+    }
+
+    #[test]
+    fn test_event_format() {
+        assert_eq!(Event::data("hello").to_bytes(), "data: hello\n\n");
+        let event = Event::data("line 1\r\nline 2\n")
+            .event("up\ndate")
+            .id("7")
+            .retry(Duration::from_secs(3));
+        assert_eq!(
+            event.to_bytes(),
+            "event: update\nid: 7\nretry: 3000\ndata: line 1\ndata: line 2\ndata: \n\n"
+        );
+
+        let raw = b"GET /events HTTP/1.1\r\nHost: a\r\nLast-Event-ID: 42\r\n\r\n";
+        let req = parse_request(raw).unwrap().unwrap().0;
+        assert_eq!(last_event_id(&req), Some("42"));
+    }
+
+    #[async_std::test]
+    async fn test_sse_response() {
+        let events = stream::iter([Event::data("a").id("1"), Event::data("b").id("2")]);
+        let resp = Sse::new(events).into_response();
+        assert_eq!(resp.headers.get("content-type"), Some("text/event-stream"));
+        assert_eq!(resp.headers.get("cache-control"), Some("no-cache"));
+        assert_eq!(body(resp).await, "id: 1\ndata: a\n\nid: 2\ndata: b\n\n");
+
+        // 两个事件之间的空闲时间超过间隔时发送注释
+        let events = stream::iter(["a", "b"]).then(|data| async move {
+            async_std::task::sleep(Duration::from_millis(100)).await;
+            Event::data(data)
+        });
+        let resp = Sse::new(events)
+            .keep_alive(Some(Duration::from_millis(70)))
+            .into_response();
+        assert_eq!(
+            body(resp).await,
+            ": keep-alive\n\ndata: a\n\n: keep-alive\n\ndata: b\n\n"
+        );
+    }
+}
@@ -0,0 +1,339 @@
+use crate::apps::webserver::http::{
+    content_type, format_http_date, parse_http_date, percent_decode, Body, Request, Response,
+    StatusCode,
+};
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// 静态文件服务
+// 支持目录下的 index.html, 条件请求 (ETag / Last-Modified -> 304) 和单个 Range 请求
+
+pub struct StaticFiles {
+    root: PathBuf,
+    index: String,
+}
+
+impl StaticFiles {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        StaticFiles {
+            root: root.into(),
+            index: String::from("index.html"),
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// 返回 root 下 path 对应的文件，path 为 url 中未 decode 的相对路径
+    pub fn serve(&self, req: &Request, path: &str) -> Response {
+        match self.resolve(path) {
+            Ok(file_path) => self.serve_file(req, &file_path),
+            Err(resp) => resp,
+        }
+    }
+
+    /// 使用 root 下的 404.html 作为 404 页面，文件不存在时返回纯文本
+    pub fn not_found(&self, req: &Request) -> Response {
+        let mut resp = self.serve(req, "404.html");
+        if resp.status == StatusCode::Ok {
+            resp.status = StatusCode::NotFound;
+            resp
+        } else {
+            Response::text(StatusCode::NotFound, "not found")
+        }
+    }
+
+    // 把 url 路径映射为 root 下的文件路径，不允许访问 root 之外的文件
+    fn resolve(&self, path: &str) -> Result<PathBuf, Response> {
+        let decoded = percent_decode(path);
+        let mut file_path = self.root.clone();
+        for part in decoded.split('/') {
+            match part {
+                "" | "." => {}
+                ".." => return Err(forbidden()),
+                part if part.contains(['\\', '\0']) => return Err(forbidden()),
+                part => file_path.push(part),
+            }
+        }
+
+        // 解析符号链接后再检查一次，防止通过链接访问 root 之外的文件
+        let root = self.root.canonicalize().map_err(io_error)?;
+        let mut file_path = file_path.canonicalize().map_err(io_error)?;
+        if !file_path.starts_with(&root) {
+            return Err(forbidden());
+        }
+        if file_path.is_dir() {
+            file_path.push(&self.index);
+        }
+        Ok(file_path)
+    }
+
+    fn serve_file(&self, req: &Request, path: &Path) -> Response {
+        let (file, metadata) = match File::open(path).and_then(|f| {
+            let metadata = f.metadata()?;
+            Ok((f, metadata))
+        }) {
+            Ok(res) => res,
+            Err(err) => return io_error(err),
+        };
+        if !metadata.is_file() {
+            return Response::text(StatusCode::NotFound, "not found");
+        }
+
+        let len = metadata.len();
+        // http-date 只精确到秒
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| UNIX_EPOCH + Duration::from_secs(d.as_secs()));
+        let etag = format!(
+            "\"{:x}-{:x}\"",
+            len,
+            modified
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_secs())
+                .unwrap_or_default()
+        );
+
+        let mut resp = Response::new(StatusCode::Ok)
+            .with_header("ETag", etag.as_str())
+            .with_header("Accept-Ranges", "bytes");
+        if let Some(modified) = modified {
+            resp = resp.with_header("Last-Modified", format_http_date(modified));
+        }
+        if not_modified(req, &etag, modified) {
+            resp.status = StatusCode::NotModified;
+            return resp;
+        }
+
+        resp = resp.with_header("Content-Type", content_type(path));
+        let range = match req.header("range") {
+            Some(range) if if_range_matches(req, &etag, modified) => parse_range(range, len),
+            _ => None,
+        };
+        match range {
+            None => resp.with_body(Body::File {
+                file,
+                offset: 0,
+                len,
+            }),
+            Some(Ok((start, end))) => {
+                resp.status = StatusCode::PartialContent;
+                resp.with_header("Content-Range", format!("bytes {start}-{end}/{len}"))
+                    .with_body(Body::File {
+                        file,
+                        offset: start,
+                        len: end - start + 1,
+                    })
+            }
+            Some(Err(())) => Response::new(StatusCode::RangeNotSatisfiable)
+                .with_header("Content-Range", format!("bytes */{len}")),
+        }
+    }
+}
+
+fn forbidden() -> Response {
+    Response::text(StatusCode::Forbidden, "forbidden")
+}
+
+fn io_error(err: io::Error) -> Response {
+    match err.kind() {
+        io::ErrorKind::NotFound => Response::text(StatusCode::NotFound, "not found"),
+        io::ErrorKind::PermissionDenied => forbidden(),
+        _ => Response::text(StatusCode::InternalServerError, err.to_string()),
+    }
+}
+
+// If-None-Match 优先于 If-Modified-Since
+fn not_modified(req: &Request, etag: &str, modified: Option<SystemTime>) -> bool {
+    if let Some(value) = req.header("if-none-match") {
+        return value
+            .split(',')
+            .map(|tag| tag.trim().trim_start_matches("W/"))
+            .any(|tag| tag == etag || tag == "*");
+    }
+    match (
+        req.header("if-modified-since").and_then(parse_http_date),
+        modified,
+    ) {
+        (Some(since), Some(modified)) => modified <= since,
+        _ => false,
+    }
+}
+
+// If-Range 与当前文件不一致时忽略 Range, 返回完整内容
+fn if_range_matches(req: &Request, etag: &str, modified: Option<SystemTime>) -> bool {
+    match req.header("if-range") {
+        None => true,
+        Some(value) if value.starts_with('"') => value == etag,
+        Some(value) => parse_http_date(value).is_some() && parse_http_date(value) == modified,
+    }
+}
+
+/// 解析 `bytes=start-end` 格式的 Range, 返回闭区间 [start, end]
+/// 格式不支持（如多个 range）时返回 None, 按普通请求处理；超出文件范围时返回 Err
+fn parse_range(value: &str, len: u64) -> Option<Result<(u64, u64), ()>> {
+    let spec = value.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+    let (start, end) = spec.trim().split_once('-')?;
+    if start.is_empty() {
+        // bytes=-n 表示最后 n 个字节
+        let suffix: u64 = end.parse().ok()?;
+        if suffix == 0 || len == 0 {
+            return Some(Err(()));
+        }
+        return Some(Ok((len.saturating_sub(suffix), len - 1)));
+    }
+
+    let start: u64 = start.parse().ok()?;
+    let end: Option<u64> = if end.is_empty() {
+        None
+    } else {
+        Some(end.parse().ok()?)
+    };
+    if end.is_some_and(|end| end < start) {
+        return None;
+    }
+    if start >= len {
+        return Some(Err(()));
+    }
+    let end = end.map_or(len - 1, |end| end.min(len - 1));
+    Some(Ok((start, end)))
+}
+
+/// 创建用于测试的目录，返回目录路径
+#[cfg(test)]
+pub(crate) fn test_root(name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let root = std::env::temp_dir().join(format!("webserver_{}_{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    for (path, contents) in files {
+        let path = root.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    }
+    root
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apps::webserver::http::parse_request;
+    use std::fs;
+
+    fn request(target: &str, headers: &[(&str, &str)]) -> Request {
+        let mut raw = format!("GET {target} HTTP/1.1\r\nHost: localhost\r\n");
+        for (name, value) in headers {
+            raw.push_str(&format!("{name}: {value}\r\n"));
+        }
+        raw.push_str("\r\n");
+        parse_request(raw.as_bytes()).unwrap().unwrap().0
+    }
+
+    fn body(resp: Response) -> String {
+        let mut out = Vec::new();
+        resp.write_to(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        out.split_once("\r\n\r\n").unwrap().1.to_string()
+    }
+
+    #[test]
+    fn test_static_files_serve() {
+        let root = test_root(
+            "static_serve",
+            &[
+                ("index.html", "<h1>index</h1>"),
+                ("css/site.css", "body {}"),
+                ("a b.txt", "space"),
+            ],
+        );
+        let files = StaticFiles::new(&root);
+
+        let resp = files.serve(&request("/", &[]), "/");
+        assert_eq!(resp.status, StatusCode::Ok);
+        assert_eq!(
+            resp.headers.get("content-type"),
+            Some("text/html; charset=utf-8")
+        );
+        assert_eq!(body(resp), "<h1>index</h1>");
+
+        let resp = files.serve(&request("/", &[]), "css/site.css");
+        assert_eq!(
+            resp.headers.get("content-type"),
+            Some("text/css; charset=utf-8")
+        );
+        assert!(resp.headers.contains("etag"));
+        assert!(resp.headers.contains("last-modified"));
+
+        let resp = files.serve(&request("/", &[]), "a%20b.txt");
+        assert_eq!(body(resp), "space");
+
+        let resp = files.serve(&request("/", &[]), "missing.html");
+        assert_eq!(resp.status, StatusCode::NotFound);
+        let resp = files.not_found(&request("/", &[]));
+        assert_eq!(resp.status, StatusCode::NotFound);
+
+        for path in ["../etc/passwd", "css/../../x", "%2e%2e/x", "a\\b"] {
+            let resp = files.serve(&request("/", &[]), path);
+            assert_eq!(resp.status, StatusCode::Forbidden, "path: {path}");
+        }
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_static_files_conditional() {
+        let root = test_root("static_conditional", &[("a.txt", "hello")]);
+        let files = StaticFiles::new(&root);
+
+        let resp = files.serve(&request("/", &[]), "a.txt");
+        let etag = resp.headers.get("etag").unwrap().to_string();
+        let last_modified = resp.headers.get("last-modified").unwrap().to_string();
+
+        let resp = files.serve(&request("/", &[("If-None-Match", &etag)]), "a.txt");
+        assert_eq!(resp.status, StatusCode::NotModified);
+        assert_eq!(body(resp), "");
+
+        let req = request("/", &[("If-Modified-Since", &last_modified)]);
+        let resp = files.serve(&req, "a.txt");
+        assert_eq!(resp.status, StatusCode::NotModified);
+
+        let resp = files.serve(&request("/", &[("If-None-Match", "\"x\"")]), "a.txt");
+        assert_eq!(resp.status, StatusCode::Ok);
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_static_files_range() {
+        let root = test_root("static_range", &[("a.txt", "0123456789")]);
+        let files = StaticFiles::new(&root);
+
+        let resp = files.serve(&request("/", &[("Range", "bytes=2-4")]), "a.txt");
+        assert_eq!(resp.status, StatusCode::PartialContent);
+        assert_eq!(resp.headers.get("content-range"), Some("bytes 2-4/10"));
+        assert_eq!(body(resp), "234");
+
+        let resp = files.serve(&request("/", &[("Range", "bytes=-3")]), "a.txt");
+        assert_eq!(body(resp), "789");
+
+        let resp = files.serve(&request("/", &[("Range", "bytes=20-")]), "a.txt");
+        assert_eq!(resp.status, StatusCode::RangeNotSatisfiable);
+        assert_eq!(resp.headers.get("content-range"), Some("bytes */10"));
+
+        let req = request("/", &[("Range", "bytes=2-4"), ("If-Range", "\"old\"")]);
+        let resp = files.serve(&req, "a.txt");
+        assert_eq!(resp.status, StatusCode::Ok);
+        assert_eq!(body(resp), "0123456789");
+
+        assert_eq!(parse_range("bytes=5-", 10), Some(Ok((5, 9))));
+        assert_eq!(parse_range("bytes=5-100", 10), Some(Ok((5, 9))));
+        assert_eq!(parse_range("bytes=0-1,3-4", 10), None);
+        assert_eq!(parse_range("bytes=4-2", 10), None);
+        assert_eq!(parse_range("items=0-1", 10), None);
+        fs::remove_dir_all(root).unwrap();
+    }
+}
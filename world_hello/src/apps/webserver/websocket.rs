@@ -0,0 +1,656 @@
+use crate::apps::webserver::http::{Method, Request, Response, StatusCode, Version};
+use async_std::io::{Read, Write};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use futures::future::BoxFuture;
+use futures::io::{AsyncReadExt, AsyncWriteExt, ReadHalf, WriteHalf};
+use futures::lock::Mutex;
+use futures::stream::{self, Stream};
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+// websocket: 握手、帧的解析与掩码、ping/pong、关闭握手和分片消息
+// refer: https://www.rfc-editor.org/rfc/rfc6455
+
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+// 控制帧的 payload 不能超过 125 字节
+const MAX_CONTROL_PAYLOAD: usize = 125;
+const READ_CHUNK_SIZE: usize = 4096;
+
+/// 关闭码
+pub const CLOSE_NORMAL: u16 = 1000;
+pub const CLOSE_PROTOCOL_ERROR: u16 = 1002;
+pub const CLOSE_INVALID_DATA: u16 = 1007;
+pub const CLOSE_TOO_BIG: u16 = 1009;
+
+/// websocket 连接使用的底层流，tcp 或 tls
+pub trait Conn: Read + Write + Unpin + Send {}
+
+impl<T: Read + Write + Unpin + Send> Conn for T {}
+
+/// websocket 的 handler, 'a 为连接的生命周期
+pub type SocketHandler =
+    Box<dyn for<'a> Fn(Request, WebSocket<'a>) -> BoxFuture<'a, ()> + Send + Sync>;
+
+/// 把闭包转换为 SocketHandler, 帮助编译器推断闭包参数的生命周期
+pub fn handler<F>(f: F) -> SocketHandler
+where
+    F: for<'a> Fn(Request, WebSocket<'a>) -> BoxFuture<'a, ()> + Send + Sync + 'static,
+{
+    Box::new(f)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    /// 收到的 ping 已经自动回复 pong
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    /// 关闭码和原因，对方没有提供关闭码时为 None
+    Close(Option<(u16, String)>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Opcode {
+    Continuation,
+    Text,
+    Binary,
+    Close,
+    Ping,
+    Pong,
+}
+
+impl Opcode {
+    fn from_u8(b: u8) -> Option<Opcode> {
+        match b {
+            0x0 => Some(Opcode::Continuation),
+            0x1 => Some(Opcode::Text),
+            0x2 => Some(Opcode::Binary),
+            0x8 => Some(Opcode::Close),
+            0x9 => Some(Opcode::Ping),
+            0xA => Some(Opcode::Pong),
+            _ => None,
+        }
+    }
+
+    fn as_u8(&self) -> u8 {
+        match self {
+            Opcode::Continuation => 0x0,
+            Opcode::Text => 0x1,
+            Opcode::Binary => 0x2,
+            Opcode::Close => 0x8,
+            Opcode::Ping => 0x9,
+            Opcode::Pong => 0xA,
+        }
+    }
+
+    fn is_control(&self) -> bool {
+        matches!(self, Opcode::Close | Opcode::Ping | Opcode::Pong)
+    }
+}
+
+#[derive(Debug)]
+struct Frame {
+    fin: bool,
+    opcode: Opcode,
+    payload: Vec<u8>,
+}
+
+/// 请求是否要求升级为 websocket
+pub fn is_upgrade(req: &Request) -> bool {
+    req.header("upgrade")
+        .is_some_and(|v| v.trim().eq_ignore_ascii_case("websocket"))
+}
+
+/// 根据 Sec-WebSocket-Key 计算 Sec-WebSocket-Accept
+pub fn accept_key(key: &str) -> String {
+    let mut sha1 = sha1_smol::Sha1::new();
+    sha1.update(key.as_bytes());
+    sha1.update(ACCEPT_GUID.as_bytes());
+    BASE64.encode(sha1.digest().bytes())
+}
+
+/// 检查握手请求，成功时返回 101 响应，失败时返回 400 或 426 响应
+pub fn handshake(req: &Request) -> Result<Response, Response> {
+    let bad_request = |msg: &str| Response::text(StatusCode::BadRequest, msg.to_string());
+    if req.method != Method::Get || req.version != Version::Http11 {
+        return Err(bad_request("websocket requires GET over HTTP/1.1"));
+    }
+    let connection_upgrade = req
+        .headers
+        .get_all("connection")
+        .flat_map(|v| v.split(','))
+        .any(|v| v.trim().eq_ignore_ascii_case("upgrade"));
+    if !is_upgrade(req) || !connection_upgrade {
+        return Err(bad_request("missing websocket upgrade headers"));
+    }
+    if req.header("sec-websocket-version") != Some("13") {
+        return Err(
+            Response::text(StatusCode::UpgradeRequired, "unsupported websocket version")
+                .with_header("Sec-WebSocket-Version", "13"),
+        );
+    }
+    // key 是 16 个随机字节的 base64 编码
+    let key = req
+        .header("sec-websocket-key")
+        .map(str::trim)
+        .filter(|key| BASE64.decode(key).is_ok_and(|k| k.len() == 16))
+        .ok_or_else(|| bad_request("invalid Sec-WebSocket-Key"))?;
+
+    Ok(Response::new(StatusCode::SwitchingProtocols)
+        .with_header("Upgrade", "websocket")
+        .with_header("Connection", "Upgrade")
+        .with_header("Sec-WebSocket-Accept", accept_key(key)))
+}
+
+/// 已完成握手的 websocket 连接
+/// recv 依次返回收到的消息，sender 可以复制后与 recv 同时使用
+pub struct WebSocket<'a> {
+    reader: ReadHalf<Box<dyn Conn + 'a>>,
+    // 已读取但还未解析的数据
+    buf: Vec<u8>,
+    sender: Sender<'a>,
+    // 正在接收的分片消息，控制帧可以插在分片之间
+    fragments: Option<(Opcode, Vec<u8>)>,
+    max_message_size: usize,
+    // 收到 close 帧后不再读取
+    finished: bool,
+}
+
+impl<'a> WebSocket<'a> {
+    /// buf 为读取握手请求时多读的数据，max_message_size 限制合并分片后的消息大小
+    pub fn new(conn: impl Conn + 'a, buf: Vec<u8>, max_message_size: usize) -> Self {
+        let conn: Box<dyn Conn + 'a> = Box::new(conn);
+        let (reader, writer) = conn.split();
+        WebSocket {
+            reader,
+            buf,
+            sender: Sender {
+                writer: Arc::new(Mutex::new(writer)),
+                closed: Arc::new(AtomicBool::new(false)),
+            },
+            fragments: None,
+            max_message_size,
+            finished: false,
+        }
+    }
+
+    pub fn sender(&self) -> Sender<'a> {
+        self.sender.clone()
+    }
+
+    pub async fn send(&self, msg: Message) -> io::Result<()> {
+        self.sender.send(msg).await
+    }
+
+    pub async fn close(&self, code: u16, reason: &str) -> io::Result<()> {
+        self.sender.close(code, reason).await
+    }
+
+    /// 返回下一个消息，连接关闭后返回 None
+    /// 收到 close 帧时回复 close 并返回 Message::Close, 协议错误时发送对应的关闭码并返回错误
+    pub async fn recv(&mut self) -> Option<io::Result<Message>> {
+        if self.finished {
+            return None;
+        }
+        match self.read_message().await {
+            Ok(Some(msg)) => {
+                if matches!(msg, Message::Close(_)) {
+                    self.finished = true;
+                }
+                Some(Ok(msg))
+            }
+            Ok(None) => {
+                self.finished = true;
+                None
+            }
+            Err((code, err)) => {
+                self.finished = true;
+                if let Some(code) = code {
+                    let _ = self.sender.close(code, &err.to_string()).await;
+                }
+                Some(Err(err))
+            }
+        }
+    }
+
+    /// 转换为消息的 Stream, 需要发送消息时先通过 sender 获取 Sender
+    pub fn into_stream(self) -> impl Stream<Item = io::Result<Message>> + 'a {
+        stream::unfold(self, |mut ws| async move {
+            let msg = ws.recv().await?;
+            Some((msg, ws))
+        })
+    }
+
+    async fn read_message(&mut self) -> Result<Option<Message>, Error> {
+        loop {
+            // 在分片消息中间断开也视为连接关闭
+            let Some(frame) = self.read_frame().await? else {
+                return Ok(None);
+            };
+
+            match frame.opcode {
+                Opcode::Ping => {
+                    self.sender
+                        .send_frame(Opcode::Pong, &frame.payload)
+                        .await
+                        .map_err(|err| (None, err))?;
+                    return Ok(Some(Message::Ping(frame.payload)));
+                }
+                Opcode::Pong => return Ok(Some(Message::Pong(frame.payload))),
+                Opcode::Close => return self.on_close(frame.payload).await.map(Some),
+                Opcode::Text | Opcode::Binary if self.fragments.is_some() => {
+                    return Err(protocol_error("expect continuation frame"));
+                }
+                Opcode::Text | Opcode::Binary => {
+                    self.fragments = Some((frame.opcode, frame.payload));
+                }
+                Opcode::Continuation => match &mut self.fragments {
+                    Some((_, data)) => data.extend_from_slice(&frame.payload),
+                    None => return Err(protocol_error("unexpected continuation frame")),
+                },
+            }
+
+            let size = self.fragments.as_ref().map_or(0, |(_, data)| data.len());
+            if size > self.max_message_size {
+                return Err(close_error(CLOSE_TOO_BIG, "message too big"));
+            }
+            if frame.fin {
+                let Some((opcode, data)) = self.fragments.take() else {
+                    unreachable!("data frame always sets fragments");
+                };
+                return match opcode {
+                    Opcode::Text => String::from_utf8(data)
+                        .map(Message::Text)
+                        .map_err(|_| close_error(CLOSE_INVALID_DATA, "invalid utf-8 text")),
+                    _ => Ok(Message::Binary(data)),
+                }
+                .map(Some);
+            }
+        }
+    }
+
+    // 对方发起关闭时回复相同的关闭码，我方发起时这是对方的回复
+    async fn on_close(&mut self, payload: Vec<u8>) -> Result<Message, Error> {
+        let close = match payload.len() {
+            0 => None,
+            1 => return Err(protocol_error("invalid close payload")),
+            _ => {
+                let code = u16::from_be_bytes([payload[0], payload[1]]);
+                let reason = String::from_utf8(payload[2..].to_vec())
+                    .map_err(|_| protocol_error("invalid close reason"))?;
+                Some((code, reason))
+            }
+        };
+        let code = close.as_ref().map_or(CLOSE_NORMAL, |(code, _)| *code);
+        // 对方已经关闭时不需要回复，close 会忽略
+        let _ = self.sender.close(code, "").await;
+        Ok(Message::Close(close))
+    }
+
+    /// 读取一个帧，连接在帧的开头关闭时返回 None
+    async fn read_frame(&mut self) -> Result<Option<Frame>, Error> {
+        if !self.fill(2).await? {
+            return Ok(None);
+        }
+        let (b0, b1) = (self.buf[0], self.buf[1]);
+        let fin = b0 & 0x80 != 0;
+        // 没有协商扩展，rsv 必须为 0
+        if b0 & 0x70 != 0 {
+            return Err(protocol_error("reserved bits set"));
+        }
+        let opcode = Opcode::from_u8(b0 & 0x0F).ok_or_else(|| protocol_error("unknown opcode"))?;
+        // 客户端发送的帧必须有掩码
+        if b1 & 0x80 == 0 {
+            return Err(protocol_error("client frame not masked"));
+        }
+
+        let (len, mut pos) = match b1 & 0x7F {
+            126 => {
+                self.fill_exact(4).await?;
+                (u16::from_be_bytes([self.buf[2], self.buf[3]]) as u64, 4)
+            }
+            127 => {
+                self.fill_exact(10).await?;
+                let mut bytes = [0u8; 8];
+                bytes.copy_from_slice(&self.buf[2..10]);
+                (u64::from_be_bytes(bytes), 10)
+            }
+            len => (len as u64, 2),
+        };
+        if opcode.is_control() && (!fin || len > MAX_CONTROL_PAYLOAD as u64) {
+            return Err(protocol_error("invalid control frame"));
+        }
+        // 在读取 payload 之前检查长度，避免分配过大的内存
+        if len > self.max_message_size as u64 {
+            return Err(close_error(CLOSE_TOO_BIG, "frame too big"));
+        }
+
+        let len = len as usize;
+        self.fill_exact(pos + 4 + len).await?;
+        let mut mask = [0u8; 4];
+        mask.copy_from_slice(&self.buf[pos..pos + 4]);
+        pos += 4;
+        let mut payload: Vec<u8> = self.buf.drain(..pos + len).skip(pos).collect();
+        for (i, b) in payload.iter_mut().enumerate() {
+            *b ^= mask[i % 4];
+        }
+        Ok(Some(Frame {
+            fin,
+            opcode,
+            payload,
+        }))
+    }
+
+    // 读取直到 buf 中至少有 n 个字节，在读到任何数据之前连接关闭时返回 false
+    async fn fill(&mut self, n: usize) -> Result<bool, Error> {
+        let mut chunk = [0u8; READ_CHUNK_SIZE];
+        while self.buf.len() < n {
+            let read = self
+                .reader
+                .read(&mut chunk)
+                .await
+                .map_err(|err| (None, err))?;
+            if read == 0 {
+                if self.buf.is_empty() {
+                    return Ok(false);
+                }
+                return Err((None, io::ErrorKind::UnexpectedEof.into()));
+            }
+            self.buf.extend_from_slice(&chunk[..read]);
+        }
+        Ok(true)
+    }
+
+    async fn fill_exact(&mut self, n: usize) -> Result<(), Error> {
+        if self.fill(n).await? {
+            Ok(())
+        } else {
+            Err((None, io::ErrorKind::UnexpectedEof.into()))
+        }
+    }
+}
+
+/// 发送消息，可以复制到其他任务中使用
+pub struct Sender<'a> {
+    writer: Arc<Mutex<WriteHalf<Box<dyn Conn + 'a>>>>,
+    // 已发送 close 帧，之后不能再发送消息
+    closed: Arc<AtomicBool>,
+}
+
+impl Clone for Sender<'_> {
+    fn clone(&self) -> Self {
+        Sender {
+            writer: Arc::clone(&self.writer),
+            closed: Arc::clone(&self.closed),
+        }
+    }
+}
+
+impl Sender<'_> {
+    /// 发送 Message::Close 等同于调用 close
+    pub async fn send(&self, msg: Message) -> io::Result<()> {
+        match msg {
+            Message::Text(text) => self.send_frame(Opcode::Text, text.as_bytes()).await,
+            Message::Binary(data) => self.send_frame(Opcode::Binary, &data).await,
+            Message::Ping(data) => self.send_frame(Opcode::Ping, &data).await,
+            Message::Pong(data) => self.send_frame(Opcode::Pong, &data).await,
+            Message::Close(close) => match close {
+                Some((code, reason)) => self.close(code, &reason).await,
+                None => self.close(CLOSE_NORMAL, "").await,
+            },
+        }
+    }
+
+    /// 发送 close 帧，之后对方回复的 close 帧由 recv 返回，重复调用时忽略
+    pub async fn close(&self, code: u16, reason: &str) -> io::Result<()> {
+        let mut payload = code.to_be_bytes().to_vec();
+        // reason 与关闭码一起不能超过控制帧的长度限制
+        let mut end = reason.len().min(MAX_CONTROL_PAYLOAD - 2);
+        while !reason.is_char_boundary(end) {
+            end -= 1;
+        }
+        payload.extend_from_slice(&reason.as_bytes()[..end]);
+
+        let mut writer = self.writer.lock().await;
+        if self.closed.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+        write_frame(&mut *writer, Opcode::Close, &payload).await
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    async fn send_frame(&self, opcode: Opcode, payload: &[u8]) -> io::Result<()> {
+        if opcode.is_control() && payload.len() > MAX_CONTROL_PAYLOAD {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "control frame payload too long",
+            ));
+        }
+        let mut writer = self.writer.lock().await;
+        if self.is_closed() {
+            return Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "websocket closed",
+            ));
+        }
+        write_frame(&mut *writer, opcode, payload).await
+    }
+}
+
+// 服务端发送的帧不使用掩码，也不分片
+async fn write_frame(
+    w: &mut (impl Write + Unpin),
+    opcode: Opcode,
+    payload: &[u8],
+) -> io::Result<()> {
+    let mut head = vec![0x80 | opcode.as_u8()];
+    match payload.len() {
+        len if len < 126 => head.push(len as u8),
+        len if len <= u16::MAX as usize => {
+            head.push(126);
+            head.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            head.push(127);
+            head.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    w.write_all(&head).await?;
+    w.write_all(payload).await?;
+    w.flush().await
+}
+
+// 读取消息的错误，协议错误时同时返回应发送给对方的关闭码，io 错误时为 None
+type Error = (Option<u16>, io::Error);
+
+fn close_error(code: u16, msg: &str) -> Error {
+    (
+        Some(code),
+        io::Error::new(io::ErrorKind::InvalidData, msg.to_string()),
+    )
+}
+
+fn protocol_error(msg: &str) -> Error {
+    close_error(CLOSE_PROTOCOL_ERROR, msg)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::apps::webserver::http::parse_request;
+    use crate::apps::webserver::mock_stream::MockTcpStream;
+    use futures::StreamExt;
+
+    /// 生成客户端发送的带掩码的帧
+    pub(crate) fn client_frame(fin: bool, opcode: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [0x37, 0xfa, 0x21, 0x3d];
+        let mut frame = vec![if fin { 0x80 } else { 0 } | opcode];
+        match payload.len() {
+            len if len < 126 => frame.push(0x80 | len as u8),
+            len => {
+                frame.push(0x80 | 126);
+                frame.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+        }
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        frame
+    }
+
+    /// 解析服务端发送的不带掩码的帧，返回 (opcode, payload)
+    pub(crate) fn server_frames(mut data: &[u8]) -> Vec<(u8, Vec<u8>)> {
+        let mut frames = Vec::new();
+        while data.len() >= 2 {
+            let (len, start) = match data[1] {
+                126 => (u16::from_be_bytes([data[2], data[3]]) as usize, 4),
+                len => (len as usize, 2),
+            };
+            frames.push((data[0] & 0x0F, data[start..start + len].to_vec()));
+            data = &data[start + len..];
+        }
+        frames
+    }
+
+    #[test]
+    fn test_handshake() {
+        // rfc6455 中的例子
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+
+        let request = |headers: &str| {
+            let raw = format!("GET /ws HTTP/1.1\r\nHost: a\r\n{headers}\r\n");
+            parse_request(raw.as_bytes()).unwrap().unwrap().0
+        };
+        let req = request(
+            "Upgrade: websocket\r\nConnection: keep-alive, Upgrade\r\n\
+            Sec-WebSocket-Version: 13\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n",
+        );
+        assert!(is_upgrade(&req));
+        let resp = handshake(&req).unwrap();
+        assert_eq!(resp.status, StatusCode::SwitchingProtocols);
+        assert_eq!(
+            resp.headers.get("sec-websocket-accept"),
+            Some("s3pPLMBiTxaQ9kYGzzhZRbK+xOo=")
+        );
+
+        let resp = handshake(&request(
+            "Upgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Version: 8\r\n\
+            Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n",
+        ))
+        .unwrap_err();
+        assert_eq!(resp.status, StatusCode::UpgradeRequired);
+        assert_eq!(resp.headers.get("sec-websocket-version"), Some("13"));
+
+        for headers in [
+            "Upgrade: websocket\r\nSec-WebSocket-Version: 13\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n",
+            "Upgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Version: 13\r\nSec-WebSocket-Key: abc\r\n",
+        ] {
+            let resp = handshake(&request(headers)).unwrap_err();
+            assert_eq!(resp.status, StatusCode::BadRequest);
+        }
+    }
+
+    #[async_std::test]
+    async fn test_websocket_messages() {
+        let mut input = Vec::new();
+        input.extend(client_frame(true, 0x1, b"hello"));
+        // 分片消息中间插入 ping
+        input.extend(client_frame(false, 0x2, &[1, 2]));
+        input.extend(client_frame(true, 0x9, b"p"));
+        input.extend(client_frame(false, 0x0, &[3]));
+        input.extend(client_frame(true, 0x0, &[4]));
+        input.extend(client_frame(true, 0x1, "x".repeat(300).as_bytes()));
+        input.extend(client_frame(true, 0x8, &[0x03, 0xe8, b'b', b'y', b'e']));
+        let mut stream = MockTcpStream {
+            read_data: input,
+            write_data: Vec::new(),
+        };
+
+        let ws = WebSocket::new(&mut stream, Vec::new(), 1024);
+        let sender = ws.sender();
+        let messages: Vec<_> = ws.into_stream().map(Result::unwrap).collect().await;
+        assert_eq!(
+            messages,
+            vec![
+                Message::Text(String::from("hello")),
+                Message::Ping(b"p".to_vec()),
+                Message::Binary(vec![1, 2, 3, 4]),
+                Message::Text("x".repeat(300)),
+                Message::Close(Some((1000, String::from("bye")))),
+            ]
+        );
+        assert!(sender.is_closed());
+        assert!(sender
+            .send(Message::Text(String::from("late")))
+            .await
+            .is_err());
+        drop(sender);
+
+        // 自动回复 pong 和 close
+        assert_eq!(
+            server_frames(&stream.write_data),
+            vec![(0xA, b"p".to_vec()), (0x8, vec![0x03, 0xe8])]
+        );
+    }
+
+    #[async_std::test]
+    async fn test_websocket_errors() {
+        let cases: Vec<(Vec<u8>, u16)> = vec![
+            // 没有掩码
+            (vec![0x81, 0x01, b'a'], CLOSE_PROTOCOL_ERROR),
+            (client_frame(true, 0x0, b"a"), CLOSE_PROTOCOL_ERROR),
+            (client_frame(false, 0x9, b"a"), CLOSE_PROTOCOL_ERROR),
+            (client_frame(true, 0x1, &[0xff, 0xfe]), CLOSE_INVALID_DATA),
+            (
+                [
+                    client_frame(false, 0x2, &[0; 10]),
+                    client_frame(true, 0x0, &[0; 10]),
+                ]
+                .concat(),
+                CLOSE_TOO_BIG,
+            ),
+        ];
+        for (input, code) in cases {
+            let mut stream = MockTcpStream {
+                read_data: input,
+                write_data: Vec::new(),
+            };
+            let mut ws = WebSocket::new(&mut stream, Vec::new(), 16);
+            assert!(ws.recv().await.unwrap().is_err());
+            assert!(ws.recv().await.is_none());
+            drop(ws);
+            let frames = server_frames(&stream.write_data);
+            assert_eq!(frames[0].0, 0x8);
+            assert_eq!(frames[0].1[..2], code.to_be_bytes());
+        }
+
+        // 服务端发起关闭
+        let mut stream = MockTcpStream {
+            read_data: client_frame(true, 0x8, &[0x03, 0xe8]),
+            write_data: Vec::new(),
+        };
+        let mut ws = WebSocket::new(&mut stream, Vec::new(), 16);
+        ws.send(Message::Text(String::from("hi"))).await.unwrap();
+        ws.close(CLOSE_NORMAL, "done").await.unwrap();
+        assert_eq!(
+            ws.recv().await.unwrap().unwrap(),
+            Message::Close(Some((1000, String::new())))
+        );
+        drop(ws);
+        assert_eq!(
+            server_frames(&stream.write_data),
+            vec![
+                (0x1, b"hi".to_vec()),
+                (0x8, [&[0x03, 0xe8][..], b"done"].concat())
+            ]
+        );
+    }
+}
@@ -1,4 +1,5 @@
 use world_hello::webserver::appv3 as webapp;
+use world_hello::webserver::config::Config;
 
 #[async_std::main]
 async fn main() {
@@ -12,6 +13,6 @@ async fn main() {
 
 async fn run_app_async_websrv(is_run: bool) {
     if is_run {
-        webapp::tcp_srv().await;
+        webapp::tcp_srv(&Config::default()).await;
     }
 }
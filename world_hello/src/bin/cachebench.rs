@@ -0,0 +1,304 @@
+use bytes::Bytes;
+use mini_redis::{Connection, Frame};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::env;
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+
+/*
+cache server benchmark, like redis-benchmark
+
+start server:
+$ cargo run --bin cacheserver
+run bench:
+$ cargo run --release --bin cachebench -- -c 50 -n 100000 --set-ratio 0.2
+$ cargo run --release --bin cachebench -- -c 10 -d 10 -P 16
+*/
+
+const USAGE: &str = "\
+usage: cachebench [options]
+  -a, --addr <host:port>    target server address (default 127.0.0.1:6379)
+  -c, --clients <n>         number of concurrent connections (default 50)
+  -n, --requests <n>        total number of requests (default 100000)
+  -d, --duration <secs>     run for the given seconds instead of a request count
+  -P, --pipeline <n>        number of requests sent per batch (default 1)
+  -r, --set-ratio <0..1>    ratio of SET in the GET/SET mix (default 0.5)
+  -k, --keyspace <n>        number of distinct keys (default 10000)
+  -s, --value-size <bytes>  size of SET values (default 64)";
+
+#[tokio::main]
+async fn main() {
+    let opts = Options::build(env::args()).unwrap_or_else(|err| {
+        eprintln!("problem parsing arguments: {err}\n{USAGE}");
+        process::exit(1);
+    });
+
+    if let Err(err) = run_bench(opts).await {
+        eprintln!("bench error: {err}");
+        process::exit(1);
+    }
+}
+
+#[derive(Debug)]
+struct Options {
+    addr: String,
+    clients: usize,
+    requests: Option<u64>,
+    duration: Option<Duration>,
+    pipeline: usize,
+    set_ratio: f64,
+    keyspace: u64,
+    value_size: usize,
+}
+
+impl Options {
+    fn build(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+        args.next();
+
+        let mut opts = Options {
+            addr: String::from("127.0.0.1:6379"),
+            clients: 50,
+            requests: None,
+            duration: None,
+            pipeline: 1,
+            set_ratio: 0.5,
+            keyspace: 10_000,
+            value_size: 64,
+        };
+
+        while let Some(flag) = args.next() {
+            let value = args
+                .next()
+                .ok_or_else(|| format!("missing value for {flag}"))?;
+            match &flag[..] {
+                "-a" | "--addr" => opts.addr = value,
+                "-c" | "--clients" => opts.clients = parse_arg(&flag, &value)?,
+                "-n" | "--requests" => opts.requests = Some(parse_arg(&flag, &value)?),
+                "-d" | "--duration" => opts.duration = Some(parse_duration(&flag, &value)?),
+                "-P" | "--pipeline" => opts.pipeline = parse_arg(&flag, &value)?,
+                "-r" | "--set-ratio" => opts.set_ratio = parse_arg(&flag, &value)?,
+                "-k" | "--keyspace" => opts.keyspace = parse_arg(&flag, &value)?,
+                "-s" | "--value-size" => opts.value_size = parse_arg(&flag, &value)?,
+                _ => return Err(format!("unknown option {flag}")),
+            }
+        }
+
+        if opts.clients == 0 || opts.pipeline == 0 || opts.keyspace == 0 {
+            return Err("clients, pipeline and keyspace must be greater than 0".to_string());
+        }
+        if !(0.0..=1.0).contains(&opts.set_ratio) {
+            return Err("set ratio must be between 0 and 1".to_string());
+        }
+        // 未指定运行时长时，按请求数结束
+        if opts.requests.is_none() && opts.duration.is_none() {
+            opts.requests = Some(100_000);
+        }
+        Ok(opts)
+    }
+}
+
+fn parse_arg<T: std::str::FromStr>(flag: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value [{value}] for {flag}"))
+}
+
+// 运行时长为正的秒数，可以是小数
+fn parse_duration(flag: &str, value: &str) -> Result<Duration, String> {
+    let secs: f64 = parse_arg(flag, value)?;
+    Duration::try_from_secs_f64(secs)
+        .ok()
+        .filter(|d| !d.is_zero())
+        .ok_or_else(|| format!("invalid value [{value}] for {flag}"))
+}
+
+#[derive(Debug, Default)]
+struct Report {
+    errors: u64,
+    // 每个请求的往返耗时（us），pipeline 模式下同一批请求记录相同的耗时
+    latencies: Vec<u64>,
+}
+
+async fn run_bench(opts: Options) -> mini_redis::Result<()> {
+    println!(
+        "bench {}: clients={} pipeline={} set_ratio={} keyspace={} value_size={}",
+        opts.addr, opts.clients, opts.pipeline, opts.set_ratio, opts.keyspace, opts.value_size
+    );
+
+    let opts = Arc::new(opts);
+    let issued = Arc::new(AtomicU64::new(0));
+    let start = Instant::now();
+    let deadline = opts.duration.map(|d| start + d);
+
+    let mut handles = Vec::with_capacity(opts.clients);
+    for _ in 0..opts.clients {
+        let opts = opts.clone();
+        let issued = issued.clone();
+        handles.push(tokio::spawn(async move {
+            run_client(&opts, &issued, deadline).await
+        }));
+    }
+
+    let mut report = Report::default();
+    for handle in handles {
+        let client_report = handle.await??;
+        report.errors += client_report.errors;
+        report.latencies.extend(client_report.latencies);
+    }
+    let elapsed = start.elapsed();
+
+    print_report(&mut report, elapsed);
+    Ok(())
+}
+
+async fn run_client(
+    opts: &Options,
+    issued: &AtomicU64,
+    deadline: Option<Instant>,
+) -> mini_redis::Result<Report> {
+    let socket = TcpStream::connect(&opts.addr).await?;
+    // 关闭 Nagle 算法，避免 pipeline 的小包被延迟发送
+    socket.set_nodelay(true)?;
+    let mut connection = Connection::new(socket);
+    let mut rng = StdRng::from_entropy();
+    let value = Bytes::from(vec![b'x'; opts.value_size]);
+    let mut report = Report::default();
+
+    loop {
+        if deadline.is_some_and(|d| Instant::now() >= d) {
+            break;
+        }
+        let batch = reserve(issued, opts.requests, opts.pipeline as u64);
+        if batch == 0 {
+            break;
+        }
+
+        // 先连续写出一批请求，再依次读取响应
+        let start = Instant::now();
+        for _ in 0..batch {
+            let key = format!("key:{}", rng.gen_range(0..opts.keyspace));
+            let frame = if rng.gen_bool(opts.set_ratio) {
+                command(&[Bytes::from("SET"), Bytes::from(key), value.clone()])
+            } else {
+                command(&[Bytes::from("GET"), Bytes::from(key)])
+            };
+            connection.write_frame(&frame).await?;
+        }
+        for _ in 0..batch {
+            match connection.read_frame().await? {
+                Some(Frame::Error(_)) => report.errors += 1,
+                Some(_) => {}
+                None => return Err("connection closed by server".into()),
+            }
+        }
+        let elapsed = start.elapsed().as_micros() as u64;
+        report
+            .latencies
+            .extend(std::iter::repeat_n(elapsed, batch as usize));
+    }
+
+    Ok(report)
+}
+
+fn command(args: &[Bytes]) -> Frame {
+    Frame::Array(args.iter().cloned().map(Frame::Bulk).collect())
+}
+
+/// 从全局计数中申请最多 batch 个请求，返回实际可发送的请求数，为 0 表示已达到请求总数
+fn reserve(issued: &AtomicU64, limit: Option<u64>, batch: u64) -> u64 {
+    let Some(limit) = limit else {
+        issued.fetch_add(batch, Ordering::Relaxed);
+        return batch;
+    };
+
+    match issued.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+        (n < limit).then(|| n + batch.min(limit - n))
+    }) {
+        Ok(prev) => batch.min(limit - prev),
+        Err(_) => 0,
+    }
+}
+
+/// 返回已排序样本的百分位 p (0-100)
+fn percentile(sorted: &[u64], p: f64) -> u64 {
+    if sorted.is_empty() {
+        return 0;
+    }
+    let rank = ((p / 100.0) * sorted.len() as f64).ceil().max(1.0) as usize;
+    sorted[rank.min(sorted.len()) - 1]
+}
+
+fn print_report(report: &mut Report, elapsed: Duration) {
+    report.latencies.sort_unstable();
+    let latencies = &report.latencies;
+    let total = latencies.len();
+    let secs = elapsed.as_secs_f64();
+
+    println!(
+        "requests: {} (errors: {}) in {:.2}s",
+        total, report.errors, secs
+    );
+    println!("throughput: {:.2} req/s", total as f64 / secs);
+    println!(
+        "latency (us): min={} p50={} p90={} p99={} p99.9={} max={}",
+        latencies.first().copied().unwrap_or_default(),
+        percentile(latencies, 50.0),
+        percentile(latencies, 90.0),
+        percentile(latencies, 99.0),
+        percentile(latencies, 99.9),
+        latencies.last().copied().unwrap_or_default(),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(s: &str) -> impl Iterator<Item = String> + '_ {
+        std::iter::once("cachebench")
+            .chain(s.split_whitespace())
+            .map(String::from)
+    }
+
+    #[test]
+    fn test_options_build() {
+        let opts = Options::build(args("")).unwrap();
+        assert_eq!(opts.requests, Some(100_000));
+        assert_eq!(opts.duration, None);
+
+        let opts = Options::build(args("-c 4 -d 1.5 -P 16 --set-ratio 0.1")).unwrap();
+        assert_eq!(opts.clients, 4);
+        assert_eq!(opts.requests, None);
+        assert_eq!(opts.duration, Some(Duration::from_millis(1500)));
+        assert_eq!(opts.pipeline, 16);
+
+        assert!(Options::build(args("-c")).is_err());
+        assert!(Options::build(args("-r 2")).is_err());
+        assert!(Options::build(args("--unknown 1")).is_err());
+        for bad in ["-1", "0", "NaN", "inf", "1e30"] {
+            assert!(Options::build(args(&format!("-d {bad}"))).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn test_reserve_requests() {
+        let issued = AtomicU64::new(0);
+        assert_eq!(reserve(&issued, Some(10), 4), 4);
+        assert_eq!(reserve(&issued, Some(10), 4), 4);
+        assert_eq!(reserve(&issued, Some(10), 4), 2);
+        assert_eq!(reserve(&issued, Some(10), 4), 0);
+        assert_eq!(reserve(&issued, None, 4), 4);
+    }
+
+    #[test]
+    fn test_percentile() {
+        let sorted: Vec<u64> = (1..=100).collect();
+        assert_eq!(percentile(&sorted, 50.0), 50);
+        assert_eq!(percentile(&sorted, 99.9), 100);
+        assert_eq!(percentile(&[], 99.0), 0);
+    }
+}
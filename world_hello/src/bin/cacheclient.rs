@@ -1,15 +1,14 @@
-use bytes::Bytes;
 use mini_redis::{client, Result};
-use tokio::sync::mpsc;
-use tokio::sync::oneshot;
 use tokio::time;
 use tokio_stream::StreamExt;
+use world_hello::apps::cache::client::{ClusterClient, NearCacheClient};
 
 // mini redis client
 
 #[tokio::main]
 async fn main() {
     run_client_with_queue(false).await;
+    run_cluster_client(false).await.unwrap();
     run_subscribe_client(false).await.unwrap();
 
     println!("mini-redis client done")
@@ -75,80 +74,49 @@ async fn subscribe() -> mini_redis::Result<()> {
 }
 
 // Client by Queue
-
-type Responder<T> = oneshot::Sender<mini_redis::Result<T>>;
-
-#[derive(Debug)]
-enum Command {
-    Get {
-        key: String,
-        resp: Responder<Option<Bytes>>,
-    },
-    Set {
-        key: String,
-        val: Bytes,
-        resp: Responder<()>,
-    },
-}
+// 命令通过 channel 交给 NearCacheClient 内部的 manager 任务执行，读过的 key 缓存在本地直到被 server 通知失效
 
 async fn run_client_with_queue(is_run: bool) {
     if !is_run {
         return;
     }
 
-    let (tx, mut rx) = mpsc::channel(32);
-    let tx2 = tx.clone();
-
-    // 从 channel 获取 cmd 执行
-    let manager = tokio::spawn(async move {
-        let mut client = client::connect("127.0.0.1:6379").await.unwrap();
-        while let Some(cmd) = rx.recv().await {
-            match cmd {
-                Command::Get { key, resp } => {
-                    let res = client.get(&key).await;
-                    // 往 oneshot 中发送消息时，并没有使用 .await, 原因是该发送操作要么直接成功、要么失败，并不需要等待
-                    // 忽略错误
-                    let _ = resp.send(res);
-                }
-                Command::Set { key, val, resp } => {
-                    let res = client.set(&key, val).await;
-                    // 忽略错误
-                    let _ = resp.send(res);
-                }
-            }
-        }
-    });
+    let client = NearCacheClient::connect("127.0.0.1:6379").await.unwrap();
+    let client2 = client.clone();
 
     let t1 = tokio::spawn(async move {
-        let (resp_tx, resp_rx) = oneshot::channel();
-        let cmd = Command::Get {
-            key: "hello".to_string(),
-            resp: resp_tx,
-        };
-        // 发送 GET 请求
-        tx.send(cmd).await.unwrap();
-        // 等待回复
-        let res = resp_rx.await;
-        println!("GOT = {:?}", res);
+        // 第 2 次 GET 直接从本地缓存返回
+        for _ in 0..2 {
+            let res = client.get("hello").await;
+            println!("GOT = {:?}", res);
+        }
     });
 
     let t2 = tokio::spawn(async move {
-        let (resp_tx, resp_rx) = oneshot::channel();
-        let cmd = Command::Set {
-            key: "foo".to_string(),
-            val: "bar".into(),
-            resp: resp_tx,
-        };
-        // 发送 SET 请求
-        tx2.send(cmd).await.unwrap();
-        // 等待回复
-        let res = resp_rx.await;
+        let res = client2.set("foo", "bar".into()).await;
         println!("GOT = {:?}", res);
     });
 
     t1.await.unwrap();
     t2.await.unwrap();
-    manager.await.unwrap();
+}
+
+// Cluster Client
+
+async fn run_cluster_client(is_run: bool) -> mini_redis::Result<()> {
+    // pre cond: start cache server nodes in cluster mode, refer to bin/cacheserver.rs
+    if !is_run {
+        return Ok(());
+    }
+
+    // 只需要连接一个节点，其它节点通过 MOVED 重定向得到
+    let mut client = ClusterClient::connect("127.0.0.1:7001").await?;
+    for key in ["foo", "bar", "{user1000}.following", "{user1000}.followers"] {
+        client.set(key, "1".into()).await?;
+        let res = client.get(key).await?;
+        println!("GOT {} = {:?}", key, res);
+    }
+    Ok(())
 }
 
 // Client helloworld
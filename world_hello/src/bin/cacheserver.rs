@@ -1,4 +1,5 @@
-use world_hello::apps::cache::app;
+use std::{env, process};
+use world_hello::apps::cache::{app, config::Config};
 
 /*
 mini redis server
@@ -11,12 +12,20 @@ $ mini-redis-server
 check:
 $ mini-redis-cli set foo 1
 $ mini-redis-cli get foo
+
+cluster mode, each node owns the slots assigned to its addr in the topology file:
+$ cargo run --bin cacheserver -- --addr 127.0.0.1:7001 --cluster /tmp/test/topology.conf
+$ cargo run --bin cacheserver -- --addr 127.0.0.1:7002 --cluster /tmp/test/topology.conf
 */
 
 // #[tokio::main] 宏在将 async fn main 隐式的转换为 fn main 的同时还对整个异步运行时进行了初始化
 #[tokio::main]
 async fn main() {
-    app::srv().await;
+    let config = Config::build(env::args()).unwrap_or_else(|err| {
+        eprintln!("problem parsing arguments: {err}");
+        process::exit(1);
+    });
+    app::srv(config).await;
 }
 
 #[cfg(test)]
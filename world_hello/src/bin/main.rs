@@ -79,15 +79,15 @@ fn run_app_minigrep(is_run: bool) {
 
 fn run_app_websrv(is_run: bool) {
     if is_run {
-        use world_hello::webserver::appv1 as app;
-        app::tcp_srv();
+        use world_hello::webserver::{appv1 as app, config::Config};
+        app::tcp_srv(&Config::default());
     }
 }
 
 fn run_app_parallel_websrv(is_run: bool) {
     if is_run {
-        use world_hello::webserver::appv2 as app;
-        app::tcp_srv();
+        use world_hello::webserver::{appv2 as app, config::Config};
+        app::tcp_srv(&Config::default());
     }
 }
 
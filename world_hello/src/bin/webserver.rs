@@ -0,0 +1,40 @@
+use std::{env, process};
+use world_hello::webserver::config::{Config, Mode};
+use world_hello::webserver::{appv1, appv2, appv3};
+
+/*
+web server, mode: single (appv1), pool (appv2), async (appv3)
+
+$ cargo run --bin webserver -- --mode pool --addr 127.0.0.1:7878 --doc-root /tmp/test --workers 4
+$ cargo run --bin webserver -- --config /tmp/test/webserver.conf --mode async
+
+https (pool and async mode), see apps/webserver/tls.rs for generating a self-signed cert:
+$ cargo run --bin webserver -- --tls-cert /tmp/test/server.crt --tls-key /tmp/test/server.key
+$ curl --cacert /tmp/test/server.crt https://localhost:7878/
+
+webserver.conf:
+addr = 0.0.0.0:7878
+mode = pool
+workers = 4
+max-workers = 16
+queue-capacity = 128
+doc-root = /tmp/test
+keep-alive-timeout = 10
+
+test:
+$ curl http://127.0.0.1:7878/
+$ curl http://127.0.0.1:7878/hello/rust
+*/
+
+fn main() {
+    let config = Config::build(env::args()).unwrap_or_else(|err| {
+        eprintln!("problem parsing arguments: {err}");
+        process::exit(1);
+    });
+
+    match config.mode {
+        Mode::Single => appv1::tcp_srv(&config),
+        Mode::Pool => appv2::tcp_srv(&config),
+        Mode::Async => async_std::task::block_on(appv3::tcp_srv(&config)),
+    }
+}
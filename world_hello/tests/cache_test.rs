@@ -0,0 +1,215 @@
+//
+// Cache Server
+// 每个测试启动独立的 server, 绑定临时端口，可以并行执行
+//
+
+use bytes::Bytes;
+use mini_redis::{client, Connection, Frame};
+use std::time::Duration;
+use tokio::net::TcpStream;
+use world_hello::apps::cache::app::{CacheServer, ServerHandle};
+use world_hello::apps::cache::client::{ClusterClient, NearCacheClient};
+use world_hello::apps::cache::cluster::{self, Topology};
+use world_hello::apps::cache::config::Config;
+
+async fn start_server(config: Config) -> ServerHandle {
+    let config = Config {
+        addr: String::from("127.0.0.1:0"),
+        ..config
+    };
+    CacheServer::start(config).await.unwrap()
+}
+
+async fn send_command(conn: &mut Connection, args: &[&str]) -> Frame {
+    let frame = Frame::Array(
+        args.iter()
+            .map(|arg| Frame::Bulk(Bytes::from(arg.to_string())))
+            .collect(),
+    );
+    conn.write_frame(&frame).await.unwrap();
+    conn.read_frame().await.unwrap().unwrap()
+}
+
+#[tokio::test]
+async fn it_cache_servers_in_parallel() {
+    let srv1 = start_server(Config::default()).await;
+    let srv2 = start_server(Config::default()).await;
+    assert_ne!(srv1.addr(), srv2.addr());
+
+    let mut client1 = client::connect(srv1.addr()).await.unwrap();
+    let mut client2 = client::connect(srv2.addr()).await.unwrap();
+    client1.set("hello", "world".into()).await.unwrap();
+
+    let got = client1.get("hello").await.unwrap();
+    assert_eq!(got, Some(Bytes::from("world")));
+    let got = client2.get("hello").await.unwrap();
+    assert_eq!(got, None);
+}
+
+#[tokio::test]
+async fn it_cache_server_stop_on_drop() {
+    let srv = start_server(Config::default()).await;
+    let addr = srv.addr();
+    let mut client = client::connect(addr).await.unwrap();
+    client.set("hello", "world".into()).await.unwrap();
+
+    srv.shutdown().await;
+    assert!(client.get("hello").await.is_err());
+    assert!(TcpStream::connect(addr).await.is_err());
+
+    let srv = start_server(Config::default()).await;
+    let addr = srv.addr();
+    drop(srv);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(TcpStream::connect(addr).await.is_err());
+}
+
+#[tokio::test]
+async fn it_cache_server_slowlog_and_info() {
+    let srv = start_server(Config {
+        slowlog_slower_than: Duration::ZERO,
+        slowlog_max_len: 2,
+        ..Config::default()
+    })
+    .await;
+    let socket = TcpStream::connect(srv.addr()).await.unwrap();
+    let mut conn = Connection::new(socket);
+
+    send_command(&mut conn, &["SET", "foo", "bar"]).await;
+    send_command(&mut conn, &["GET", "foo"]).await;
+    send_command(&mut conn, &["GET", "foo"]).await;
+
+    match send_command(&mut conn, &["SLOWLOG", "LEN"]).await {
+        Frame::Integer(n) => assert_eq!(n, 2),
+        frame => panic!("unexpected frame: {:?}", frame),
+    }
+    match send_command(&mut conn, &["SLOWLOG", "GET", "1"]).await {
+        Frame::Array(entries) => {
+            assert_eq!(entries.len(), 1);
+            let entry = match &entries[0] {
+                Frame::Bulk(b) => String::from_utf8_lossy(b).into_owned(),
+                frame => panic!("unexpected frame: {:?}", frame),
+            };
+            assert!(entry.contains("args=SLOWLOG LEN"), "entry: {entry}");
+        }
+        frame => panic!("unexpected frame: {:?}", frame),
+    }
+
+    match send_command(&mut conn, &["INFO", "commandstats"]).await {
+        Frame::Bulk(b) => {
+            let info = String::from_utf8_lossy(&b);
+            assert!(info.contains("cmdstat_get:calls=2,"), "info: {info}");
+            assert!(info.contains("cmdstat_set:calls=1,"), "info: {info}");
+        }
+        frame => panic!("unexpected frame: {:?}", frame),
+    }
+
+    match send_command(&mut conn, &["SLOWLOG", "RESET"]).await {
+        Frame::Simple(s) => assert_eq!(s, "OK"),
+        frame => panic!("unexpected frame: {:?}", frame),
+    }
+}
+
+#[tokio::test]
+async fn it_cache_client_tracking() {
+    let srv = start_server(Config::default()).await;
+    let near = NearCacheClient::connect(srv.addr()).await.unwrap();
+    let mut other = client::connect(srv.addr()).await.unwrap();
+    other.set("hello", "world".into()).await.unwrap();
+
+    // 第 2 次 GET 命中本地缓存，不会发送到 server
+    for _ in 0..2 {
+        let got = near.get("hello").await.unwrap();
+        assert_eq!(got, Some(Bytes::from("world")));
+    }
+    let socket = TcpStream::connect(srv.addr()).await.unwrap();
+    let mut conn = Connection::new(socket);
+    match send_command(&mut conn, &["INFO"]).await {
+        Frame::Bulk(b) => {
+            let info = String::from_utf8_lossy(&b);
+            assert!(info.contains("cmdstat_get:calls=1,"), "info: {info}");
+        }
+        frame => panic!("unexpected frame: {:?}", frame),
+    }
+
+    // 其它连接修改 key 后，本地缓存被通知失效
+    other.set("hello", "rust".into()).await.unwrap();
+    let mut got = None;
+    for _ in 0..50 {
+        got = near.get("hello").await.unwrap();
+        if got == Some(Bytes::from("rust")) {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(got, Some(Bytes::from("rust")));
+
+    match send_command(&mut conn, &["CLIENT", "TRACKING", "MAYBE"]).await {
+        Frame::Error(_) => {}
+        frame => panic!("unexpected frame: {:?}", frame),
+    }
+}
+
+#[tokio::test]
+async fn it_cache_cluster_moved() {
+    // 集群节点需要事先知道彼此的地址，先绑定临时端口拿到可用的地址
+    let mut addrs = Vec::new();
+    for _ in 0..2 {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        addrs.push(listener.local_addr().unwrap().to_string());
+    }
+    let topology =
+        Topology::parse(&format!("{} 0-8191\n{} 8192-16383", addrs[0], addrs[1])).unwrap();
+
+    // 绑定通配地址时需要指定节点在拓扑中的地址
+    let port = addrs[1].rsplit(':').next().unwrap();
+    let wildcard = Config {
+        addr: format!("0.0.0.0:{port}"),
+        cluster: Some(topology.clone()),
+        ..Config::default()
+    };
+    let err = CacheServer::start(wildcard.clone()).await.err().unwrap();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+
+    let nodes = [
+        Config {
+            addr: addrs[0].clone(),
+            cluster: Some(topology.clone()),
+            ..Config::default()
+        },
+        Config {
+            cluster_announce: Some(addrs[1].clone()),
+            ..wildcard
+        },
+    ];
+    let mut handles = Vec::new();
+    for config in nodes {
+        handles.push(CacheServer::start(config).await.unwrap());
+    }
+
+    // "foo" 在 slot 12182, 由第 2 个节点负责
+    let socket = TcpStream::connect(&addrs[0]).await.unwrap();
+    let mut conn = Connection::new(socket);
+    match send_command(&mut conn, &["GET", "foo"]).await {
+        Frame::Error(msg) => assert_eq!(msg, format!("MOVED 12182 {}", addrs[1])),
+        frame => panic!("unexpected frame: {:?}", frame),
+    }
+    match send_command(&mut conn, &["CLUSTER", "KEYSLOT", "{foo}.bar"]).await {
+        Frame::Integer(slot) => assert_eq!(slot, 12182),
+        frame => panic!("unexpected frame: {:?}", frame),
+    }
+
+    let mut client = ClusterClient::connect(&addrs[0]).await.unwrap();
+    for key in ["foo", "bar", "hello", "{foo}.bar"] {
+        client.set(key, Bytes::from(key.to_string())).await.unwrap();
+    }
+    for key in ["foo", "bar", "hello", "{foo}.bar"] {
+        let got = client.get(key).await.unwrap();
+        assert_eq!(got, Some(Bytes::from(key.to_string())));
+
+        // key 只保存在负责其 slot 的节点上
+        let owner = if cluster::key_slot(key) < 8192 { 0 } else { 1 };
+        let mut direct = client::connect(&addrs[owner]).await.unwrap();
+        assert_eq!(direct.get(key).await.unwrap(), got);
+    }
+}
@@ -0,0 +1,478 @@
+//
+// Web Server
+// 每个测试启动独立的 server, 绑定临时端口，可以并行执行
+//
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+use world_hello::webserver::appv2::{Server, ShutdownHandle};
+use world_hello::webserver::appv3;
+use world_hello::webserver::config::{Config, Mode};
+use world_hello::webserver::pool::{QueuePolicy, ThreadPool};
+
+fn doc_root(name: &str) -> PathBuf {
+    let root = std::env::temp_dir().join(format!("webserver_it_{}_{}", name, std::process::id()));
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(root.join("hello.html"), "<h1>Hello!</h1>").unwrap();
+    root
+}
+
+fn test_config(name: &str) -> Config {
+    Config {
+        addr: String::from("127.0.0.1:0"),
+        doc_root: doc_root(name),
+        ..Config::default()
+    }
+}
+
+fn start_server(
+    config: &Config,
+    pool: ThreadPool,
+) -> (SocketAddr, ShutdownHandle, thread::JoinHandle<()>) {
+    let server = Server::bind(config, pool).unwrap();
+    let addr = server.local_addr().unwrap();
+    let handle = server.shutdown_handle();
+    let join = thread::spawn(move || server.run());
+    (addr, handle, join)
+}
+
+// 发送请求并读取响应，直到连接被 server 关闭
+fn get(addr: SocketAddr, path: &str) -> String {
+    let req = format!("GET {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+    send(TcpStream::connect(addr).unwrap(), req.as_bytes())
+}
+
+fn send(mut stream: TcpStream, data: &[u8]) -> String {
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    stream.write_all(data).unwrap();
+    let mut resp = String::new();
+    stream.read_to_string(&mut resp).unwrap();
+    resp
+}
+
+#[test]
+fn it_webserver_graceful_shutdown() {
+    let (addr, handle, join) = start_server(&test_config("shutdown"), ThreadPool::new(2));
+
+    let resp = get(addr, "/hello/rust");
+    assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "resp: {resp}");
+    assert!(resp.ends_with("\r\n\r\nHello, rust!"));
+    let resp = get(addr, "/");
+    assert!(resp.ends_with("\r\n\r\n<h1>Hello!</h1>"));
+
+    // 处理中的请求在 shutdown 之后仍然会完成
+    let slow = thread::spawn(move || get(addr, "/sleep"));
+    thread::sleep(Duration::from_millis(200));
+    handle.shutdown();
+    join.join().unwrap();
+    let resp = slow.join().unwrap();
+    assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "resp: {resp}");
+    assert!(resp.contains("\r\nConnection: close\r\n"));
+
+    assert!(TcpStream::connect(addr).is_err());
+}
+
+#[test]
+fn it_webserver_reject_when_queue_full() {
+    let pool = ThreadPool::with_queue(1, 1, QueuePolicy::Reject);
+    let (addr, handle, join) = start_server(&test_config("reject"), pool);
+
+    // 第一个连接占用唯一的 worker, 第二个连接在队列中等待
+    let busy = TcpStream::connect(addr).unwrap();
+    thread::sleep(Duration::from_millis(100));
+    let queued = TcpStream::connect(addr).unwrap();
+    thread::sleep(Duration::from_millis(100));
+
+    let resp = get(addr, "/");
+    assert!(
+        resp.starts_with("HTTP/1.1 503 Service Unavailable\r\n"),
+        "resp: {resp}"
+    );
+    assert!(resp.contains("\r\nRetry-After: 1\r\n"));
+
+    // 被拒绝的客户端缓慢地发送数据，不影响 accept 其他连接
+    let mut slow = TcpStream::connect(addr).unwrap();
+    let trickle = thread::spawn(move || {
+        for _ in 0..50 {
+            if slow.write_all(b"x").is_err() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(30));
+        }
+    });
+    thread::sleep(Duration::from_millis(100));
+    let start = std::time::Instant::now();
+    let resp = get(addr, "/");
+    assert!(
+        resp.starts_with("HTTP/1.1 503 Service Unavailable\r\n"),
+        "resp: {resp}"
+    );
+    assert!(start.elapsed() < Duration::from_millis(500));
+    trickle.join().unwrap();
+
+    drop(busy);
+    drop(queued);
+    handle.shutdown();
+    join.join().unwrap();
+}
+
+#[test]
+fn it_webserver_request_timeout() {
+    let config = Config {
+        header_timeout: Duration::from_millis(200),
+        ..test_config("timeout")
+    };
+    let (addr, handle, join) = start_server(&config, ThreadPool::new(1));
+
+    // 请求头发送得很慢 (slowloris), 超时后返回 408 并释放 worker
+    let start = std::time::Instant::now();
+    let resp = send(TcpStream::connect(addr).unwrap(), b"GET / HTTP/1.1\r\nHost");
+    assert!(
+        resp.starts_with("HTTP/1.1 408 Request Timeout\r\n"),
+        "resp: {resp}"
+    );
+    assert!(start.elapsed() < Duration::from_secs(2));
+    let resp = get(addr, "/hello/rust");
+    assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "resp: {resp}");
+
+    handle.shutdown();
+    join.join().unwrap();
+}
+
+#[test]
+fn it_webserver_conns_per_ip() {
+    let config = Config {
+        max_conns_per_ip: 1,
+        ..test_config("conns_per_ip")
+    };
+    let (addr, handle, join) = start_server(&config, ThreadPool::new(2));
+
+    let first = TcpStream::connect(addr).unwrap();
+    thread::sleep(Duration::from_millis(100));
+    let resp = get(addr, "/");
+    assert!(
+        resp.starts_with("HTTP/1.1 429 Too Many Requests\r\n"),
+        "resp: {resp}"
+    );
+
+    // 被拒绝的客户端缓慢地发送数据，不影响 accept 其他连接
+    let mut slow = TcpStream::connect(addr).unwrap();
+    let trickle = thread::spawn(move || {
+        for _ in 0..50 {
+            if slow.write_all(b"x").is_err() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(30));
+        }
+    });
+    thread::sleep(Duration::from_millis(100));
+    let start = std::time::Instant::now();
+    let resp = get(addr, "/");
+    assert!(
+        resp.starts_with("HTTP/1.1 429 Too Many Requests\r\n"),
+        "resp: {resp}"
+    );
+    assert!(start.elapsed() < Duration::from_millis(500));
+    trickle.join().unwrap();
+
+    // 连接关闭后释放
+    drop(first);
+    thread::sleep(Duration::from_millis(100));
+    let resp = get(addr, "/");
+    assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "resp: {resp}");
+
+    handle.shutdown();
+    join.join().unwrap();
+}
+
+#[test]
+fn it_webserver_rate_limit() {
+    let config = Config {
+        rate_limit: Some(1.0),
+        rate_burst: Some(2),
+        ..test_config("rate_limit")
+    };
+    let (addr, handle, join) = start_server(&config, ThreadPool::new(2));
+
+    for _ in 0..2 {
+        let resp = get(addr, "/");
+        assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "resp: {resp}");
+    }
+    let resp = get(addr, "/");
+    assert!(
+        resp.starts_with("HTTP/1.1 429 Too Many Requests\r\n"),
+        "resp: {resp}"
+    );
+    assert!(resp.contains("\r\nRetry-After: 1\r\n"), "resp: {resp}");
+
+    // 每秒补充一个令牌
+    thread::sleep(Duration::from_millis(1100));
+    let resp = get(addr, "/");
+    assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "resp: {resp}");
+
+    handle.shutdown();
+    join.join().unwrap();
+}
+
+#[test]
+fn it_webserver_auth_and_cors() {
+    let config = Config {
+        auth_users: vec![(String::from("admin"), String::from("secret"))],
+        auth_prefix: String::from("/hello"),
+        cors_origins: vec![String::from("https://a.example")],
+        ..test_config("auth_and_cors")
+    };
+    let (addr, handle, join) = start_server(&config, ThreadPool::new(2));
+    let async_addr = start_async_server(Config {
+        mode: Mode::Async,
+        ..config
+    });
+
+    for addr in [addr, async_addr] {
+        let resp = get(addr, "/hello/auth");
+        assert!(
+            resp.starts_with("HTTP/1.1 401 Unauthorized\r\n"),
+            "resp: {resp}"
+        );
+        assert!(resp.contains("\r\nWWW-Authenticate: Basic realm=\"webserver\""));
+        // 规范化之后与路由看到的路径相同，不能绕过认证
+        for path in ["/%68ello/auth", "//hello/auth", "/./hello/auth"] {
+            let resp = get(addr, path);
+            assert!(
+                resp.starts_with("HTTP/1.1 401 Unauthorized\r\n"),
+                "path: {path}, resp: {resp}"
+            );
+        }
+
+        // admin:secret
+        let req = b"GET /hello/auth HTTP/1.1\r\nHost: localhost\r\n\
+            Authorization: Basic YWRtaW46c2VjcmV0\r\nOrigin: https://a.example\r\n\
+            Connection: close\r\n\r\n";
+        let resp = send(TcpStream::connect(addr).unwrap(), req);
+        assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "resp: {resp}");
+        assert!(resp.contains("\r\nAccess-Control-Allow-Origin: https://a.example\r\n"));
+        assert!(resp.ends_with("Hello, auth!"));
+
+        // preflight 请求不需要认证
+        let req = b"OPTIONS /hello/auth HTTP/1.1\r\nHost: localhost\r\n\
+            Origin: https://a.example\r\nAccess-Control-Request-Method: POST\r\n\
+            Connection: close\r\n\r\n";
+        let resp = send(TcpStream::connect(addr).unwrap(), req);
+        assert!(
+            resp.starts_with("HTTP/1.1 204 No Content\r\n"),
+            "resp: {resp}"
+        );
+        assert!(resp.contains("\r\nAccess-Control-Allow-Methods: GET, HEAD, POST, PUT, DELETE\r\n"));
+
+        // prefix 之外的请求不需要认证
+        let resp = get(addr, "/");
+        assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "resp: {resp}");
+    }
+
+    handle.shutdown();
+    join.join().unwrap();
+}
+
+// 生成 localhost 的自签名证书，返回证书和私钥文件的路径
+fn self_signed_cert(name: &str) -> (PathBuf, PathBuf) {
+    use openssl::asn1::Asn1Time;
+    use openssl::bn::BigNum;
+    use openssl::hash::MessageDigest;
+    use openssl::pkey::PKey;
+    use openssl::rsa::Rsa;
+    use openssl::x509::extension::{BasicConstraints, SubjectAlternativeName};
+    use openssl::x509::{X509Builder, X509NameBuilder};
+
+    let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+    let mut subject = X509NameBuilder::new().unwrap();
+    subject.append_entry_by_text("CN", "localhost").unwrap();
+    let subject = subject.build();
+
+    let mut builder = X509Builder::new().unwrap();
+    builder.set_version(2).unwrap();
+    let serial = BigNum::from_u32(1).unwrap().to_asn1_integer().unwrap();
+    builder.set_serial_number(&serial).unwrap();
+    builder.set_subject_name(&subject).unwrap();
+    builder.set_issuer_name(&subject).unwrap();
+    builder.set_pubkey(&key).unwrap();
+    builder
+        .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+        .unwrap();
+    builder
+        .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+        .unwrap();
+    let san = SubjectAlternativeName::new()
+        .dns("localhost")
+        .ip("127.0.0.1")
+        .build(&builder.x509v3_context(None, None))
+        .unwrap();
+    builder.append_extension(san).unwrap();
+    builder
+        .append_extension(BasicConstraints::new().critical().ca().build().unwrap())
+        .unwrap();
+    builder.sign(&key, MessageDigest::sha256()).unwrap();
+    let cert = builder.build();
+
+    let dir = doc_root(name);
+    let (cert_path, key_path) = (dir.join("server.crt"), dir.join("server.key"));
+    std::fs::write(&cert_path, cert.to_pem().unwrap()).unwrap();
+    std::fs::write(&key_path, key.private_key_to_pem_pkcs8().unwrap()).unwrap();
+    (cert_path, key_path)
+}
+
+fn tls_config(name: &str) -> Config {
+    let (cert, key) = self_signed_cert(name);
+    Config {
+        tls_cert: Some(cert),
+        tls_key: Some(key),
+        ..test_config(name)
+    }
+}
+
+// 只信任测试生成的证书，通过 https 发送请求
+fn https_get(addr: SocketAddr, cert: &std::path::Path, path: &str) -> String {
+    let cert = std::fs::read(cert).unwrap();
+    let connector = native_tls::TlsConnector::builder()
+        .add_root_certificate(native_tls::Certificate::from_pem(&cert).unwrap())
+        .build()
+        .unwrap();
+    let stream = TcpStream::connect(addr).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let mut stream = connector.connect("localhost", stream).unwrap();
+    let req = format!("GET {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+    stream.write_all(req.as_bytes()).unwrap();
+    let mut resp = Vec::new();
+    // 服务端关闭连接时可能没有发送 close_notify, 读到的数据已经完整
+    let _ = stream.read_to_end(&mut resp);
+    String::from_utf8(resp).unwrap()
+}
+
+#[test]
+fn it_webserver_https() {
+    let config = tls_config("https");
+    let (addr, handle, join) = start_server(&config, ThreadPool::new(2));
+    let cert = config.tls_cert.as_ref().unwrap();
+
+    let resp = https_get(addr, cert, "/hello/tls");
+    assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "resp: {resp}");
+    assert!(resp.ends_with("\r\n\r\nHello, tls!"));
+
+    // 明文请求无法完成握手，连接被关闭
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream
+        .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    let mut resp = Vec::new();
+    let _ = stream.read_to_end(&mut resp);
+    assert!(!resp.starts_with(b"HTTP/1.1"));
+
+    handle.shutdown();
+    join.join().unwrap();
+}
+
+#[test]
+fn it_webserver_https_async() {
+    let config = tls_config("https_async");
+    let cert = config.tls_cert.clone().unwrap();
+    let addr = start_async_server(config);
+
+    let resp = https_get(addr, &cert, "/hello/async");
+    assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "resp: {resp}");
+    assert!(resp.ends_with("\r\n\r\nHello, async!"));
+}
+
+// 在后台任务中运行 async server
+fn start_async_server(config: Config) -> SocketAddr {
+    let listener =
+        async_std::task::block_on(async_std::net::TcpListener::bind("127.0.0.1:0")).unwrap();
+    let addr = listener.local_addr().unwrap();
+    async_std::task::spawn(async move { appv3::serve(listener, &config).await });
+    addr
+}
+
+#[test]
+fn it_webserver_proxy() {
+    // 两个 backend 返回不同的首页，另一个 upstream 无法连接
+    let backends: Vec<String> = ["proxy_a", "proxy_b"]
+        .iter()
+        .map(|name| {
+            let config = test_config(name);
+            std::fs::write(config.doc_root.join("hello.html"), *name).unwrap();
+            start_async_server(config).to_string()
+        })
+        .collect();
+    let dead = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let addr = start_async_server(Config {
+        mode: Mode::Async,
+        upstreams: vec![dead.to_string(), backends[0].clone(), backends[1].clone()],
+        proxy_prefix: String::from("/app"),
+        ..test_config("proxy")
+    });
+
+    let mut names = Vec::new();
+    for _ in 0..4 {
+        let resp = get(addr, "/app/");
+        assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "resp: {resp}");
+        // 响应体以 chunked 编码写回: 7\r\nproxy_a\r\n0\r\n\r\n
+        let body = resp.split_once("\r\n\r\n").unwrap().1;
+        names.push(body.split("\r\n").nth(1).unwrap().to_string());
+    }
+    assert!(names.contains(&String::from("proxy_a")), "names: {names:?}");
+    assert!(names.contains(&String::from("proxy_b")), "names: {names:?}");
+
+    // HEAD 请求的响应保留 backend 的 Content-Length
+    let req = b"HEAD /app/ HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+    let resp = send(TcpStream::connect(addr).unwrap(), req);
+    assert!(resp.contains("\r\nContent-Length: 7\r\n"), "resp: {resp}");
+    assert!(resp.ends_with("\r\n\r\n"));
+
+    // 请求体转发给 backend, 响应体以 chunked 编码写回
+    let req = b"POST /app/echo HTTP/1.1\r\nHost: localhost\r\nContent-Length: 4\r\n\
+        Connection: close\r\n\r\nping";
+    let resp = send(TcpStream::connect(addr).unwrap(), req);
+    assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "resp: {resp}");
+    assert!(resp.contains("\r\nTransfer-Encoding: chunked\r\n"));
+    assert!(resp.ends_with("\r\n\r\n4\r\nping\r\n0\r\n\r\n"));
+
+    // chunked 编码的请求体分多次发送，边读边转发给 backend
+    let mut stream = TcpStream::connect(addr).unwrap();
+    let head = b"POST /app/echo HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\
+        Connection: close\r\n\r\n4\r\nping\r\n";
+    stream.write_all(head).unwrap();
+    thread::sleep(Duration::from_millis(50));
+    let resp = send(stream, b"4\r\npong\r\n0\r\n\r\n");
+    assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "resp: {resp}");
+    assert!(
+        resp.ends_with("\r\n\r\n8\r\npingpong\r\n0\r\n\r\n"),
+        "resp: {resp}"
+    );
+
+    // prefix 之外的请求由 server 自己处理
+    let resp = get(addr, "/hello/proxy");
+    assert!(resp.ends_with("Hello, proxy!"));
+
+    // upstream 不可用时不等待剩余的请求体，返回 502 后关闭连接
+    let addr = start_async_server(Config {
+        mode: Mode::Async,
+        upstreams: vec![dead.to_string()],
+        proxy_prefix: String::from("/app"),
+        ..test_config("proxy_down")
+    });
+    let req = b"POST /app/echo HTTP/1.1\r\nHost: localhost\r\nContent-Length: 100\r\n\r\nping";
+    let resp = send(TcpStream::connect(addr).unwrap(), req);
+    assert!(
+        resp.starts_with("HTTP/1.1 502 Bad Gateway\r\n"),
+        "resp: {resp}"
+    );
+    assert!(resp.contains("\r\nConnection: close\r\n"), "resp: {resp}");
+}